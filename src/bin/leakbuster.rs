@@ -1,4 +1,7 @@
-use leakbuster::cmd::{delay, eval, run};
+use chrono::NaiveDate;
+use leakbuster::clock::SystemClock;
+use leakbuster::cmd::{check, delay, eval, export, import, migrate, report, run, stats, watch};
+use leakbuster::cmd::stats::{Period, Range};
 use structopt::StructOpt;
 use std::path::PathBuf;
 
@@ -26,11 +29,29 @@ enum Leakbuster {
         /// configuration.
         args: Vec<String>,
     },
+    /// Keep running in the background, and track the apps in config that
+    /// were started without `leakbuster run`. Their usage is recorded, and
+    /// their time and shutdown hooks are triggered. Startup hooks don't
+    /// apply, since the apps are already running when they are found.
+    Watch {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the configuration file
+        config: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the usage db
+        db: Option<PathBuf>
+    },
     /// Evaluate a condition on the usage of a given app.
     /// Exit 0: if the condition is true.
     /// Exit 1: if the condition is false.
     /// Other exit codes indicate that an error occured.
     Eval {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the configuration file. Used to look up groups, and to
+        /// check that the apps referenced in the condition exist.
+        config: Option<PathBuf>,
+
         #[structopt(long, parse(from_os_str))]
         /// Path to the usage db
         db: Option<PathBuf>,
@@ -41,6 +62,117 @@ enum Leakbuster {
         /// Condition to be evaluated.
         condition: String
     },
+    /// Show how long apps and groups were used: in total, in how many
+    /// sessions, the longest session, and the average use per day. Shows
+    /// today's usage, unless another period or range of days is given.
+    Stats {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the configuration file. Used to list apps without usage,
+        /// and to show groups.
+        config: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the usage db
+        db: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            possible_values = &["today", "week", "month"],
+            conflicts_with_all = &["from", "to"]
+        )]
+        /// Show usage of today, this week or this month
+        period: Option<Period>,
+
+        #[structopt(long)]
+        /// First day to show usage of, as YYYY-MM-DD
+        from: Option<NaiveDate>,
+
+        #[structopt(long, requires = "from")]
+        /// Last day to show usage of, as YYYY-MM-DD. Defaults to today.
+        to: Option<NaiveDate>,
+
+        #[structopt(long, default_value = "table", possible_values = &["table", "json"])]
+        /// Print an aligned table, or JSON with durations in seconds
+        format: stats::Format
+    },
+    /// Write an HTML page with charts of the last days: the daily usage of
+    /// each app, usage by weekday and hour, and the usage that is limited
+    /// with `atmost ... in this day` in hooks against its limit. The page
+    /// loads nothing from elsewhere, so it can be viewed offline.
+    Report {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the configuration file. Used to show limits.
+        config: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the usage db
+        db: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), default_value = "report.html")]
+        /// Where to write the report
+        out: PathBuf,
+
+        #[structopt(long, default_value = "30D")]
+        /// Number of days up to today to show, like 30D, or weeks, like 4W.
+        /// At most about five years.
+        range: report::Days
+    },
+    /// Print the recorded sessions, to keep them elsewhere or move them to
+    /// another db with `import`. Sessions that reach beyond the given days
+    /// are cut off.
+    Export {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the usage db
+        db: Option<PathBuf>,
+
+        #[structopt(long, default_value = "csv", possible_values = &["csv", "json", "ndjson"])]
+        /// Print CSV with a header, a JSON array, or one JSON object per line
+        format: export::Format,
+
+        #[structopt(long)]
+        /// Only export sessions of this app
+        app: Option<String>,
+
+        #[structopt(long)]
+        /// First day to export sessions of, as YYYY-MM-DD
+        from: Option<NaiveDate>,
+
+        #[structopt(long)]
+        /// Last day to export sessions of, as YYYY-MM-DD
+        to: Option<NaiveDate>
+    },
+    /// Add sessions written by `export` to the db. Sessions that are in the
+    /// db already are skipped. If any session is invalid, none are added.
+    /// Exit 0: if the sessions were imported.
+    /// Exit 1: if the file or its sessions are invalid.
+    /// Exit 2: if the file can't be opened.
+    Import {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the usage db
+        db: Option<PathBuf>,
+
+        #[structopt(long, possible_values = &["csv", "json", "ndjson"])]
+        /// Format of the file. Guessed from its extension if not given.
+        format: Option<export::Format>,
+
+        #[structopt(parse(from_os_str))]
+        /// File to import
+        file: PathBuf
+    },
+    /// Check a config file for mistakes, without running anything. Reports
+    /// expressions that can't be parsed, unknown fields, duplicate app ids,
+    /// commands that aren't on the PATH, and conditions that are always true
+    /// or always false.
+    /// Exit 0: if the config has no errors. It may still have warnings.
+    /// Exit 1: if the config has errors.
+    /// Exit 2: if the config file can't be read.
+    Check {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the configuration file
+        config: Option<PathBuf>
+    },
+    /// Maintain the usage db
+    Db(DbCommand),
     /// Show a window with a countdown to delay program start. Use as a
     /// startup hook, in combination with `leakbuster run`.
     /// Exit 0: If the user lets the countdown elapse.
@@ -54,13 +186,51 @@ enum Leakbuster {
     }
 }
 
+#[derive(StructOpt)]
+enum DbCommand {
+    /// Upgrade the db to the schema of this version of leakbuster. Other
+    /// commands do this on their own, this one shows what is changed.
+    /// Exit 0: if the db is up to date, or was upgraded.
+    /// Exit 1: if the upgrade failed, or the db was written by a newer
+    /// version of leakbuster.
+    /// Exit 2: if the db can't be opened.
+    Migrate {
+        #[structopt(long, parse(from_os_str))]
+        /// Path to the usage db
+        db: Option<PathBuf>,
+
+        #[structopt(long)]
+        /// Only show which changes would be made, without making them
+        dry_run: bool
+    }
+}
+
 fn main() {
     let leakbuster = Leakbuster::from_args();
     match leakbuster {
         Leakbuster::Run{ config, db, app_id, args } =>
-            run::run(config, db, &app_id, &args),
-        Leakbuster::Eval{ db, app_id, condition } =>
-            eval::eval(db, &app_id, &condition),
+            run::run(config, db, &app_id, &args, &SystemClock),
+        Leakbuster::Watch{ config, db } =>
+            watch::watch(config, db, &SystemClock),
+        Leakbuster::Eval{ config, db, app_id, condition } =>
+            eval::eval(config, db, &app_id, &condition, &SystemClock),
+        Leakbuster::Stats{ config, db, period, from, to, format } => {
+            let range = match (period, from) {
+                (None, Some(from)) => Range::Dates { from, to },
+                (period, _) => Range::Period(period.unwrap_or(Period::Today))
+            };
+            stats::stats(config, db, range, format, &SystemClock)
+        },
+        Leakbuster::Report{ config, db, out, range } =>
+            report::report(config, db, out, range, &SystemClock),
+        Leakbuster::Export{ db, format, app, from, to } =>
+            export::export(db, format, app, from, to),
+        Leakbuster::Import{ db, format, file } =>
+            import::import(db, format, file),
+        Leakbuster::Check{ config } =>
+            check::check(config),
+        Leakbuster::Db(DbCommand::Migrate{ db, dry_run }) =>
+            migrate::migrate(db, dry_run),
         Leakbuster::Delay{ duration, message } =>
             delay::delay(duration, message)
     }
@@ -0,0 +1,350 @@
+pub mod file;
+
+use chrono::prelude::*;
+use chrono::{Duration, LocalResult};
+use serde::Deserialize;
+use std::convert::TryInto;
+
+use crate::expressions::parser::TimeUnit;
+
+/// Day that weeks start on, for `this week` and the week of `leakbuster stats`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WeekStart {
+    /// As in ISO 8601
+    #[default]
+    Monday,
+    Sunday
+}
+
+/// Days from `first` to `last`, both included
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateRange {
+    pub first: NaiveDate,
+    pub last: NaiveDate
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.first <= date && date <= self.last
+    }
+}
+
+/// Start of the calendar unit that `time` is in, in the time zone of `time`,
+/// e.g. midnight for `TimeUnit::Day`. Never later than `time`.
+pub fn start_of<Z: TimeZone>(time: &DateTime<Z>, unit: TimeUnit, week_start: WeekStart)
+    -> DateTime<Z> {
+    // Seconds and minutes are cut off from the instant rather than the local
+    // time, which may be ambiguous while a DST change repeats an hour
+    let back = |seconds: u32| time.clone()
+        - Duration::seconds(seconds.into())
+        - Duration::nanoseconds(time.nanosecond().into());
+    let date = time.naive_local().date();
+    let tz = time.timezone();
+    match unit {
+        TimeUnit::Second => back(0),
+        TimeUnit::Minute => back(time.second()),
+        TimeUnit::Hour => {
+            let hour = date.and_hms(time.hour(), 0, 0);
+            match tz.from_local_datetime(&hour) {
+                LocalResult::Single(start) => start,
+                // The hour is repeated, and `time` is in one of them
+                LocalResult::Ambiguous(first, second) =>
+                    if second <= *time { second } else { first },
+                LocalResult::None => first_existing(hour, &tz)
+            }
+        },
+        TimeUnit::Day => start_of_day(date, &tz),
+        TimeUnit::Week => start_of_day(start_of_week(date, week_start), &tz),
+        TimeUnit::Month => start_of_day(NaiveDate::from_ymd(date.year(), date.month(), 1), &tz),
+        TimeUnit::Year => start_of_day(NaiveDate::from_ymd(date.year(), 1, 1), &tz)
+    }
+}
+
+/// Start of the unit that is `count` units before the one that `time` is in,
+/// e.g. midnight of yesterday for one `TimeUnit::Day`. None if that is too
+/// far back to be represented.
+pub fn start_of_earlier<Z: TimeZone>(
+    time: &DateTime<Z>,
+    unit: TimeUnit,
+    count: u64,
+    week_start: WeekStart
+) -> Option<DateTime<Z>> {
+    let current = start_of(time, unit, week_start);
+    let date = current.naive_local().date();
+    let tz = time.timezone();
+    match unit {
+        TimeUnit::Second => current.checked_sub_signed(times(count, 1)?),
+        TimeUnit::Minute => current.checked_sub_signed(times(count, 60)?),
+        // Where DST shifts by half an hour, an hour can be half an hour or one
+        // and a half hours long, so they are stepped back one by one. Over a
+        // year back, being off by an hour doesn't matter.
+        TimeUnit::Hour if count > 366 * 24 => current.checked_sub_signed(times(count, 3600)?),
+        TimeUnit::Hour => (0..count).try_fold(current, |start, _| Some(start_of(
+            &start.checked_sub_signed(Duration::seconds(1))?,
+            TimeUnit::Hour,
+            week_start
+        ))),
+        TimeUnit::Day => Some(start_of_day(date.checked_sub_signed(times(count, DAY)?)?, &tz)),
+        TimeUnit::Week =>
+            Some(start_of_day(date.checked_sub_signed(times(count, 7 * DAY)?)?, &tz)),
+        TimeUnit::Month => Some(start_of_day(months_earlier(date, count)?, &tz)),
+        TimeUnit::Year => {
+            let year = i64::from(date.year()).checked_sub(count.try_into().ok()?)?;
+            Some(start_of_day(NaiveDate::from_ymd_opt(year.try_into().ok()?, 1, 1)?, &tz))
+        }
+    }
+}
+
+/// The same local time, the given number of months earlier. If the month is
+/// shorter, the day is its last day, e.g. one month before 31 March is 28 or
+/// 29 February. None if that is too far back to be represented.
+pub fn months_before<Z: TimeZone>(time: &DateTime<Z>, months: u64) -> Option<DateTime<Z>> {
+    // The local time may be ambiguous, the instant isn't
+    if months == 0 {
+        return Some(time.clone());
+    }
+    let local = time.naive_local();
+    let date = months_earlier(local.date(), months)?;
+    Some(first_existing(date.and_time(local.time()), &time.timezone()))
+}
+
+/// The date in the month that is `count` months before the date's month, on
+/// the same day or the month's last day
+fn months_earlier(date: NaiveDate, count: u64) -> Option<NaiveDate> {
+    let months = i64::from(date.year()) * 12 + i64::from(date.month0());
+    let months = months.checked_sub(count.try_into().ok()?)?;
+    let year = months.div_euclid(12).try_into().ok()?;
+    // Between 0 and 11, so the cast is lossless
+    let month = months.rem_euclid(12) as u32 + 1;
+    (1..=date.day()).rev().find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+}
+
+const DAY: u64 = 24 * 3600;
+
+/// `count` times the given number of seconds, if it's short enough to be
+/// subtracted from a date
+fn times(count: u64, seconds: u64) -> Option<Duration> {
+    // Far more than the range of dates that chrono supports, and far less
+    // than what makes `Duration::seconds` panic
+    const MAX_DAYS: u64 = u32::MAX as u64;
+    count.checked_mul(seconds)
+        .filter(|seconds| seconds / DAY <= MAX_DAYS)
+        .map(|seconds| Duration::seconds(seconds as i64))
+}
+
+/// First moment of the date: midnight, or if a DST change skips midnight,
+/// the first minute of the date that exists
+pub fn start_of_day<Z: TimeZone>(date: NaiveDate, tz: &Z) -> DateTime<Z> {
+    first_existing(date.and_hms(0, 0, 0), tz)
+}
+
+/// The local time, or the first minute after it that exists if a DST change
+/// skips it. The earlier one, if it exists twice.
+fn first_existing<Z: TimeZone>(local: NaiveDateTime, tz: &Z) -> DateTime<Z> {
+    (0..24 * 60)
+        .filter_map(|minute| {
+            tz.from_local_datetime(&(local + Duration::minutes(minute))).earliest()
+        })
+        .next()
+        .expect("DST changes skip less than a day")
+}
+
+/// First day of the week that the date is in
+pub fn start_of_week(date: NaiveDate, week_start: WeekStart) -> NaiveDate {
+    let days_since_start = match week_start {
+        WeekStart::Monday => date.weekday().num_days_from_monday(),
+        WeekStart::Sunday => date.weekday().num_days_from_sunday()
+    };
+    date - Duration::days(days_since_start.into())
+}
+
+// chrono-tz is only a dev-dependency
+#[cfg(test)]
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::calendar::*;
+
+    use chrono_tz::America::Santiago;
+    use chrono_tz::Asia::Kolkata;
+    use chrono_tz::Australia::Lord_Howe;
+    use chrono_tz::Europe::Berlin;
+    use chrono_tz::Tz;
+
+    const ALL_UNITS: [TimeUnit; 7] = [
+        TimeUnit::Second, TimeUnit::Minute, TimeUnit::Hour, TimeUnit::Day, TimeUnit::Week,
+        TimeUnit::Month, TimeUnit::Year
+    ];
+
+    /// Checks the start of each unit, given as local times in the same order
+    /// as `ALL_UNITS`
+    fn check_all_units(tz: Tz, time: DateTime<Tz>, week_start: WeekStart, expected: [&str; 7]) {
+        for (unit, expected) in ALL_UNITS.iter().zip(expected.iter()) {
+            let start = start_of(&time, *unit, week_start);
+            assert_eq!(
+                *expected,
+                start.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+                "{:?} of {} in {}", unit, time, tz
+            );
+            assert!(start <= time);
+        }
+    }
+
+    #[test]
+    fn all_units_start_in_local_time() {
+        // A Wednesday
+        let time = Kolkata.ymd(2021, 12, 8).and_hms_milli(14, 23, 10, 500);
+        check_all_units(Kolkata, time, WeekStart::Monday, [
+            "2021-12-08 14:23:10 +0530",
+            "2021-12-08 14:23:00 +0530",
+            "2021-12-08 14:00:00 +0530",
+            "2021-12-08 00:00:00 +0530",
+            "2021-12-06 00:00:00 +0530",
+            "2021-12-01 00:00:00 +0530",
+            "2021-01-01 00:00:00 +0530"
+        ]);
+        check_all_units(Kolkata, time, WeekStart::Sunday, [
+            "2021-12-08 14:23:10 +0530",
+            "2021-12-08 14:23:00 +0530",
+            "2021-12-08 14:00:00 +0530",
+            "2021-12-08 00:00:00 +0530",
+            "2021-12-05 00:00:00 +0530",
+            "2021-12-01 00:00:00 +0530",
+            "2021-01-01 00:00:00 +0530"
+        ]);
+    }
+
+    #[test]
+    fn units_reach_back_over_dst_changes() {
+        // Summer time started on Sunday, 2021-03-28 at 02:00
+        let time = Berlin.ymd(2021, 3, 31).and_hms(12, 0, 0);
+        check_all_units(Berlin, time, WeekStart::Monday, [
+            "2021-03-31 12:00:00 +0200",
+            "2021-03-31 12:00:00 +0200",
+            "2021-03-31 12:00:00 +0200",
+            "2021-03-31 00:00:00 +0200",
+            "2021-03-29 00:00:00 +0200",
+            "2021-03-01 00:00:00 +0100",
+            "2021-01-01 00:00:00 +0100"
+        ]);
+    }
+
+    #[test]
+    fn days_start_after_a_gap_at_midnight() {
+        // Summer time started on Sunday, 2021-09-05, skipping 00:00 to 01:00
+        let time = Santiago.ymd(2021, 9, 5).and_hms(10, 30, 0);
+        check_all_units(Santiago, time, WeekStart::Sunday, [
+            "2021-09-05 10:30:00 -0300",
+            "2021-09-05 10:30:00 -0300",
+            "2021-09-05 10:00:00 -0300",
+            "2021-09-05 01:00:00 -0300",
+            "2021-09-05 01:00:00 -0300",
+            "2021-09-01 00:00:00 -0400",
+            "2021-01-01 00:00:00 -0300"
+        ]);
+    }
+
+    #[test]
+    fn repeated_hours_are_not_ambiguous() {
+        // Summer time ended on 2021-10-31, repeating 02:00 to 03:00
+        let first = Berlin.ymd(2021, 10, 31).and_hms(0, 30, 0) + Duration::hours(2);
+        let second = first + Duration::hours(1);
+        assert_eq!("02:30 +0200", first.format("%H:%M %z").to_string());
+        assert_eq!("02:30 +0100", second.format("%H:%M %z").to_string());
+        let start = |time, unit| start_of(time, unit, WeekStart::Monday);
+        assert_eq!(first - Duration::minutes(30), start(&first, TimeUnit::Hour));
+        assert_eq!(second - Duration::minutes(30), start(&second, TimeUnit::Hour));
+        let earlier = second - Duration::seconds(5);
+        assert_eq!(earlier, start(&earlier, TimeUnit::Second));
+        let day = Berlin.ymd(2021, 10, 31).and_hms(0, 0, 0);
+        assert_eq!(day, start(&second, TimeUnit::Day));
+        // 25 hours long
+        let next_day = start_of_day(NaiveDate::from_ymd(2021, 11, 1), &Berlin);
+        assert_eq!(day + Duration::hours(25), next_day);
+    }
+
+    #[test]
+    fn half_hour_dst_changes() {
+        // Summer time started on 2021-10-03, skipping 02:00 to 02:30
+        let time = Lord_Howe.ymd(2021, 10, 3).and_hms(2, 45, 0);
+        let hour = start_of(&time, TimeUnit::Hour, WeekStart::Monday);
+        assert_eq!("2021-10-03 02:30:00 +1100", hour.format("%Y-%m-%d %H:%M:%S %z").to_string());
+        assert_eq!(
+            Lord_Howe.ymd(2021, 10, 3).and_hms(0, 0, 0),
+            start_of(&time, TimeUnit::Day, WeekStart::Monday)
+        );
+    }
+
+    #[test]
+    fn earlier_units_are_counted_back_in_local_time() {
+        // A Wednesday, in summer time, which started on 2021-03-28
+        let time = Berlin.ymd(2021, 3, 31).and_hms_milli(1, 20, 30, 500);
+        let earlier = |unit, count| start_of_earlier(&time, unit, count, WeekStart::Monday)
+            .unwrap()
+            .format("%Y-%m-%d %H:%M:%S %z")
+            .to_string();
+        assert_eq!("2021-03-31 01:20:30 +0200", earlier(TimeUnit::Second, 0));
+        assert_eq!("2021-03-31 01:20:28 +0200", earlier(TimeUnit::Second, 2));
+        assert_eq!("2021-03-31 00:20:00 +0200", earlier(TimeUnit::Minute, 60));
+        assert_eq!("2021-03-28 03:00:00 +0200", earlier(TimeUnit::Hour, 70));
+        assert_eq!("2021-03-28 01:00:00 +0100", earlier(TimeUnit::Hour, 71));
+        assert_eq!("2021-03-30 00:00:00 +0200", earlier(TimeUnit::Day, 1));
+        assert_eq!("2021-03-27 00:00:00 +0100", earlier(TimeUnit::Day, 4));
+        assert_eq!("2021-03-22 00:00:00 +0100", earlier(TimeUnit::Week, 1));
+        assert_eq!("2021-02-01 00:00:00 +0100", earlier(TimeUnit::Month, 1));
+        assert_eq!("2020-12-01 00:00:00 +0100", earlier(TimeUnit::Month, 3));
+        assert_eq!("2019-02-01 00:00:00 +0100", earlier(TimeUnit::Month, 25));
+        assert_eq!("2020-01-01 00:00:00 +0100", earlier(TimeUnit::Year, 1));
+        // Summer time started on 2021-10-03, skipping 02:00 to 02:30
+        let time = Lord_Howe.ymd(2021, 10, 3).and_hms(3, 15, 0);
+        let hour = start_of_earlier(&time, TimeUnit::Hour, 1, WeekStart::Monday).unwrap();
+        assert_eq!("02:30 +1100", hour.format("%H:%M %z").to_string());
+        let hour = start_of_earlier(&time, TimeUnit::Hour, 2, WeekStart::Monday).unwrap();
+        assert_eq!("01:00 +1030", hour.format("%H:%M %z").to_string());
+    }
+
+    #[test]
+    fn units_too_far_back_are_none() {
+        let time = Berlin.ymd(2021, 3, 31).and_hms(12, 0, 0);
+        for unit in ALL_UNITS.iter() {
+            assert_eq!(None, start_of_earlier(&time, *unit, u64::MAX, WeekStart::Monday));
+            assert!(start_of_earlier(&time, *unit, 1000, WeekStart::Monday).is_some());
+        }
+    }
+
+    #[test]
+    fn months_before_keep_the_local_time() {
+        let before = |time: DateTime<Tz>, months| months_before(&time, months)
+            .unwrap()
+            .format("%Y-%m-%d %H:%M:%S %z")
+            .to_string();
+        // Across the start of summer time on 2021-03-28
+        let time = Berlin.ymd(2021, 4, 15).and_hms(9, 30, 0);
+        assert_eq!("2021-04-15 09:30:00 +0200", before(time, 0));
+        assert_eq!("2021-03-15 09:30:00 +0100", before(time, 1));
+        assert_eq!("2020-04-15 09:30:00 +0200", before(time, 12));
+        assert_eq!("2019-12-15 09:30:00 +0100", before(time, 16));
+        // Shorter months end early
+        let noon = |year, month, day| Berlin.ymd(year, month, day).and_hms(12, 0, 0);
+        assert_eq!("2021-02-28 12:00:00 +0100", before(noon(2021, 3, 31), 1));
+        assert_eq!("2020-02-29 12:00:00 +0100", before(noon(2020, 3, 31), 1));
+        assert_eq!("2019-02-28 12:00:00 +0100", before(noon(2020, 2, 29), 12));
+        // The hour is skipped on that day
+        let time = Berlin.ymd(2021, 4, 28).and_hms(2, 30, 0);
+        assert_eq!("2021-03-28 03:00:00 +0200", before(time, 1));
+        assert_eq!(None, months_before(&time, u64::MAX));
+    }
+
+    #[test]
+    fn weeks_start_on_the_configured_day() {
+        let day = |d| NaiveDate::from_ymd(2021, 12, d);
+        // Sunday, 5th, to Saturday, 11th
+        for d in 5..=11 {
+            assert_eq!(day(5), start_of_week(day(d), WeekStart::Sunday), "{}", d);
+        }
+        assert_eq!(NaiveDate::from_ymd(2021, 11, 29), start_of_week(day(5), WeekStart::Monday));
+        for d in 6..=12 {
+            assert_eq!(day(6), start_of_week(day(d), WeekStart::Monday), "{}", d);
+        }
+    }
+}
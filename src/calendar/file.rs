@@ -0,0 +1,241 @@
+use chrono::prelude::*;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::calendar::DateRange;
+
+/// Reads the days of a calendar file, which is either an iCalendar file
+/// (`.ics`) or a YAML list of dates and date ranges (`.yaml` or `.yml`)
+pub fn load(path: &Path) -> Result<Vec<DateRange>, Error> {
+    let content = fs::read_to_string(path)?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("ics") => parse_ics(&content),
+        Some("yaml") | Some("yml") => parse_yaml(&content),
+        _ => Err(Error::UnknownFormat)
+    }
+}
+
+/// A list of dates and date ranges, like
+///
+/// ```yaml
+/// - 2026-12-24
+/// - 2026-12-20..2027-01-06
+/// ```
+pub fn parse_yaml(content: &str) -> Result<Vec<DateRange>, Error> {
+    let entries: Vec<String> = serde_yaml::from_str(content)?;
+    entries.iter().enumerate()
+        .map(|(index, entry)| parse_range(entry).ok_or_else(|| Error::Invalid(format!(
+            "Entry {}: expected a date like 2026-12-24 or a range like \
+            2026-12-20..2027-01-06, found `{}`",
+            index + 1, entry
+        ))))
+        .collect()
+}
+
+/// A date, or a range of dates with both ends included
+fn parse_range(entry: &str) -> Option<DateRange> {
+    let date = |s: &str| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok();
+    let (first, last) = match entry.split_once("..") {
+        Some((first, last)) => (date(first)?, date(last)?),
+        None => (date(entry)?, date(entry)?)
+    };
+    Some(DateRange { first, last }).filter(|range| range.first <= range.last)
+}
+
+/// The days of each event, from the date of its `DTSTART` to the date of its
+/// `DTEND`. As in iCalendar, the end is excluded, so an all-day event on the
+/// 24th ends on the 25th. Times are taken as written, without converting
+/// time zones. Recurring events are rejected, rather than only counting
+/// their first day.
+pub fn parse_ics(content: &str) -> Result<Vec<DateRange>, Error> {
+    let mut ranges = vec![];
+    // Start and end of the event that is being read
+    let mut event: Option<(Option<NaiveDateTime>, Option<NaiveDateTime>)> = None;
+    for (number, line) in unfold(content) {
+        let invalid = |message: &str| Error::Invalid(format!("Line {}: {}", number, message));
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid("expected `NAME:VALUE`"))?;
+        // Parameters, like `;VALUE=DATE`, don't change the value
+        let name = name.split(';').next().unwrap_or(name).to_ascii_uppercase();
+        match (name.as_str(), &mut event) {
+            ("BEGIN", None) if value == "VEVENT" => event = Some((None, None)),
+            ("END", Some((start, end))) if value == "VEVENT" => {
+                let start = start.ok_or_else(|| invalid("event has no DTSTART"))?;
+                let end = end.unwrap_or(start);
+                // Only an end at midnight excludes the day, and an event
+                // without an end has the day of its start
+                let last = if end > start && end.time() == NaiveTime::from_hms(0, 0, 0) {
+                    end.date().pred()
+                } else {
+                    end.date()
+                };
+                if last < start.date() {
+                    return Err(invalid("event ends before it starts"));
+                }
+                ranges.push(DateRange { first: start.date(), last });
+                event = None;
+            },
+            ("DTSTART", Some((start, _))) => *start = Some(
+                parse_ics_time(value).ok_or_else(|| invalid("invalid DTSTART"))?
+            ),
+            ("DTEND", Some((_, end))) => *end = Some(
+                parse_ics_time(value).ok_or_else(|| invalid("invalid DTEND"))?
+            ),
+            ("RRULE", Some(_)) | ("RDATE", Some(_)) =>
+                return Err(invalid("recurring events are not supported")),
+            _ => {}
+        }
+    }
+    Ok(ranges)
+}
+
+/// A date like `20261224`, which is midnight, or a time like
+/// `20261224T180000`, which may be followed by `Z`
+fn parse_ics_time(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim().trim_end_matches('Z');
+    match value.len() {
+        8 => NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(|date| date.and_hms(0, 0, 0)),
+        _ => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()
+    }
+}
+
+/// Joins lines that iCalendar folded by starting the continuation with a
+/// space or tab. Returns each line with the number of its first line.
+fn unfold(content: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+    for (index, line) in content.lines().enumerate() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continuation), Some((_, previous))) => previous.push_str(continuation),
+            _ if line.trim().is_empty() => {},
+            _ => lines.push((index + 1, line.to_string()))
+        }
+    }
+    lines
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IoError(io::Error),
+    YamlError(serde_yaml::Error),
+    /// The file name doesn't end in `.ics`, `.yaml` or `.yml`
+    UnknownFormat,
+    Invalid(String)
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Error {
+        Error::YamlError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IoError(e) => write!(f, "{}", e),
+            Error::YamlError(e) => write!(f, "expected a list of dates: {}", e),
+            Error::UnknownFormat => write!(f, "expected a file ending in .ics, .yaml or .yml"),
+            Error::Invalid(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::calendar::file::*;
+
+    use std::io::Write;
+    use tempfile::Builder;
+
+    fn range(first: (i32, u32, u32), last: (i32, u32, u32)) -> DateRange {
+        DateRange {
+            first: NaiveDate::from_ymd(first.0, first.1, first.2),
+            last: NaiveDate::from_ymd(last.0, last.1, last.2)
+        }
+    }
+
+    #[test]
+    fn yaml_lists_dates_and_ranges() {
+        let ranges = parse_yaml("- 2026-12-24\n- '2026-12-20 .. 2027-01-06'\n").unwrap();
+        assert_eq!(
+            vec![range((2026, 12, 24), (2026, 12, 24)), range((2026, 12, 20), (2027, 1, 6))],
+            ranges
+        );
+        assert_eq!(Vec::<DateRange>::new(), parse_yaml("[]").unwrap());
+        for invalid in &["- 2026-02-30", "- 2027-01-06..2026-12-20", "- 24.12.2026", "vacation: 1"] {
+            assert!(parse_yaml(invalid).is_err(), "{}", invalid);
+        }
+        let err = parse_yaml("- 2026-12-24\n- tomorrow").unwrap_err();
+        assert!(err.to_string().starts_with("Entry 2: "), "{}", err);
+    }
+
+    #[test]
+    fn ics_events_end_before_their_end() {
+        let ics = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+SUMMARY:Christmas\r
+DTSTART;VALUE=DATE:20261224\r
+DTEND;VALUE=DATE:20261227\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Exam\r
+DTSTART:20270112T090000Z\r
+DTEND:20270112T120000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:A very long description that is folded onto\r
+  the next line\r
+DTSTART;VALUE=DATE:20270201\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART;TZID=Europe/Berlin:20270301T220000\r
+DTEND;TZID=Europe/Berlin:\r
+ 20270302T000000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        assert_eq!(
+            vec![
+                range((2026, 12, 24), (2026, 12, 26)),
+                range((2027, 1, 12), (2027, 1, 12)),
+                range((2027, 2, 1), (2027, 2, 1)),
+                range((2027, 3, 1), (2027, 3, 1))
+            ],
+            parse_ics(ics).unwrap()
+        );
+    }
+
+    #[test]
+    fn unsupported_ics_events_are_rejected() {
+        let event = |lines: &str| format!("BEGIN:VEVENT\n{}\nEND:VEVENT\n", lines);
+        let test_cases = vec![
+            (event("DTSTART:20261224\nRRULE:FREQ=YEARLY"), "Line 3: recurring events are not supported"),
+            (event("SUMMARY:No start"), "Line 3: event has no DTSTART"),
+            (event("DTSTART:20261224\nDTEND:20261220"), "Line 4: event ends before it starts"),
+            (event("DTSTART:2026-12-24"), "Line 2: invalid DTSTART"),
+            (event("DTSTART"), "Line 2: expected `NAME:VALUE`"),
+        ];
+        for (ics, expected) in test_cases {
+            assert_eq!(expected, parse_ics(&ics).unwrap_err().to_string(), "{}", ics);
+        }
+    }
+
+    #[test]
+    fn format_depends_on_the_extension() {
+        let mut yaml = Builder::new().suffix(".yml").tempfile().unwrap();
+        write!(yaml, "- 2026-12-24").unwrap();
+        assert_eq!(vec![range((2026, 12, 24), (2026, 12, 24))], load(yaml.path()).unwrap());
+        let mut ics = Builder::new().suffix(".ics").tempfile().unwrap();
+        write!(ics, "BEGIN:VEVENT\nDTSTART:20261224\nEND:VEVENT").unwrap();
+        assert_eq!(vec![range((2026, 12, 24), (2026, 12, 24))], load(ics.path()).unwrap());
+        let txt = Builder::new().suffix(".txt").tempfile().unwrap();
+        assert!(matches!(load(txt.path()), Err(Error::UnknownFormat)));
+    }
+}
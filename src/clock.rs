@@ -0,0 +1,97 @@
+use chrono::prelude::*;
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where leakbuster gets the time from. Everything that schedules hooks,
+/// records usage or evaluates conditions asks the clock, so tests can use a
+/// `SimulatedClock` instead of waiting in real time.
+pub trait Clock {
+    /// Monotonic time, for scheduling
+    fn instant(&self) -> Instant;
+
+    /// Wall clock time, for usage and conditions
+    fn now(&self) -> DateTime<Local>;
+
+    /// Waits for the duration to pass
+    fn sleep(&self, duration: Duration);
+
+    /// Seconds since the Unix epoch
+    fn timestamp(&self) -> u64 {
+        // We'll never have negative time stamps in a real use case
+        self.now().timestamp().max(0) as u64
+    }
+}
+
+/// The actual time of the computer
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when it is told to. Sleeping returns right away,
+/// and advances the clock by the duration.
+pub struct SimulatedClock {
+    start_instant: Instant,
+    start: DateTime<Local>,
+    elapsed: Cell<Duration>
+}
+
+impl SimulatedClock {
+    /// A clock that starts at the given time
+    pub fn new(start: DateTime<Local>) -> SimulatedClock {
+        SimulatedClock { start_instant: Instant::now(), start, elapsed: Cell::new(Duration::ZERO) }
+    }
+
+    /// A clock that starts at the given number of seconds since the epoch
+    pub fn at_timestamp(timestamp: i64) -> SimulatedClock {
+        SimulatedClock::new(Local.timestamp(timestamp, 0))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed.get()
+    }
+
+    fn now(&self) -> DateTime<Local> {
+        self.start + chrono::Duration::from_std(self.elapsed.get())
+            .expect("Simulated clock ran too far")
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::clock::*;
+
+    #[test]
+    fn simulated_clock_moves_when_advanced() {
+        let clock = SimulatedClock::at_timestamp(1000);
+        let instant = clock.instant();
+        assert_eq!(1000, clock.timestamp());
+        clock.sleep(Duration::from_secs(3600));
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(4601, clock.timestamp());
+        assert_eq!(Duration::from_millis(3601500), clock.instant() - instant);
+        assert_eq!(Local.timestamp(4601, 500_000_000), clock.now());
+    }
+}
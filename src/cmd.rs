@@ -1,15 +1,24 @@
+pub mod check;
 pub mod delay;
 pub mod eval;
+pub mod export;
+mod hooks;
+pub mod import;
+pub mod migrate;
+pub mod report;
 pub mod run;
+pub mod stats;
+pub mod watch;
 
 use crate::config;
 use crate::config::Config;
 use crate::db;
-use crate::db::Db;
+use crate::db::UsageStore;
 
 use home;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
 
 /// Attempts to load configuration either from a default location, or from a
 /// manual parameter. Will panic on failure and display a (hopefully) helpful
@@ -30,6 +39,12 @@ fn get_config(manual: Option<PathBuf>) -> Config {
                 },
                 config::Error::DeserializationError(cause) => {
                     panic!("Config file is malformed: {:#?}", cause);
+                },
+                config::Error::ExpressionError(cause) => {
+                    eprintln!("{}", cause);
+                },
+                config::Error::InvalidConfig(cause) => {
+                    eprintln!("Config file is invalid: {}", cause);
                 }
             }
             eprintln!(
@@ -38,13 +53,94 @@ fn get_config(manual: Option<PathBuf>) -> Config {
             );
             panic!("Could not load config file!");
         },
-        Ok(config) => config
+        Ok(config) => {
+            print_config_warnings(&config);
+            config
+        }
+    }
+}
+
+/// Loads the config for commands that can do without it. Only complains
+/// about a missing config if the user asked for a specific one, saying what
+/// the `consequence` is.
+fn load_optional_config(config_path: Option<PathBuf>, consequence: &str) -> Option<Config> {
+    let explicit = config_path.is_some();
+    let path = config_path.unwrap_or_else(default_config_path);
+    match Config::load(&path) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            if explicit {
+                eprintln!("Warning: Could not load config, {}: {:?}", consequence, e);
+            }
+            None
+        }
+    }
+}
+
+/// Apps in the order of the config, followed by those that only have usage
+fn app_ids<S: UsageStore + ?Sized>(config: Option<&Config>, db: &S) -> db::Result<Vec<String>> {
+    let mut app_ids: Vec<String> = config
+        .map(|config| config.apps.iter().map(|app| app.id.clone()).collect())
+        .unwrap_or_default();
+    for key in db.app_keys()? {
+        if !app_ids.contains(&key) {
+            app_ids.push(key);
+        }
+    }
+    Ok(app_ids)
+}
+
+fn print_config_warnings(config: &Config) {
+    for warning in config.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// Opens the db for the commands that record usage, upgrading its schema if
+/// necessary
+fn get_db(manual: Option<PathBuf>) -> Box<dyn UsageStore + Send> {
+    let path = manual.unwrap_or_else(default_db_path);
+    match db::open_store(&path) {
+        Ok(db) => db,
+        Err(e) => db_error(e, &path)
     }
 }
 
-fn get_db(manual: Option<PathBuf>) -> Db {
+/// Opens the db for all other commands, which exit if the db needs to be
+/// upgraded first
+fn get_current_db(manual: Option<PathBuf>) -> Box<dyn UsageStore + Send> {
     let path = manual.unwrap_or_else(default_db_path);
-    db::connect_sqlite(path).expect("Could not load db!")
+    match db::open_current_store(&path) {
+        Ok(db) => db,
+        Err(db::Error::OutdatedSchemaError { version, latest }) => {
+            eprintln!(
+                "The db at {} has schema version {}, and needs to be upgraded to version {} \
+                first. Run `leakbuster db migrate` to upgrade it.",
+                path.display(), version, latest
+            );
+            process::exit(1)
+        },
+        Err(e) => db_error(e, &path)
+    }
+}
+
+fn db_error(e: db::Error, path: &Path) -> ! {
+    match e {
+        db::Error::NewerSchemaError { version, supported } => {
+            eprintln!("{}", newer_schema_message(version, supported));
+            eprintln!("Could not load db at location: {}", path.display());
+            panic!("Could not load db!");
+        },
+        e => panic!("Could not load db: {:?}", e)
+    }
+}
+
+fn newer_schema_message(version: u32, supported: u32) -> String {
+    format!(
+        "The db was written by a newer version of leakbuster (schema version {}, this version \
+        supports up to {}). Please upgrade leakbuster.",
+        version, supported
+    )
 }
 
 fn default_config_path() -> PathBuf {
@@ -0,0 +1,252 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::cmd;
+use crate::config;
+use crate::config::{App, Config};
+use crate::expressions::lint;
+
+pub fn check(config_path: Option<PathBuf>) {
+    let path = config_path.unwrap_or_else(cmd::default_config_path);
+    let config_str = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Could not read config file {}: {}", path.display(), e);
+        std::process::exit(2)
+    });
+    let search_path: Vec<PathBuf> = env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    let config_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let problems = problems(&config_str, config_dir, &search_path);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let errors = problems.iter()
+        .filter(|p| matches!(p, Problem::Error(_)))
+        .count();
+    if errors > 0 {
+        eprintln!(
+            "{} error(s), {} warning(s) in {}",
+            errors, problems.len() - errors, path.display()
+        );
+        std::process::exit(1)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Problem {
+    /// The config can't be loaded, or will fail at runtime
+    Error(String),
+    /// Likely a mistake, but leakbuster can work with it
+    Warning(String)
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Error(message) => write!(f, "Error: {}", message),
+            Problem::Warning(message) => write!(f, "Warning: {}", message)
+        }
+    }
+}
+
+/// Everything that is wrong with the config. Calendar files are looked up
+/// relative to `config_dir`, and commands in `search_path`.
+fn problems(config_str: &str, config_dir: &Path, search_path: &[PathBuf]) -> Vec<Problem> {
+    let expression_errors = config::expression_errors(config_str);
+    let mut problems: Vec<Problem> = expression_errors.iter()
+        .map(|e| Problem::Error(e.to_string()))
+        .collect();
+    problems.extend(
+        config::unknown_fields(config_str).iter().map(|f| Problem::Error(f.to_string()))
+    );
+    let config = match serde_yaml::from_str::<Config>(config_str) {
+        Ok(config) => config,
+        Err(e) => {
+            // Broken expressions were already reported above, with more detail
+            if expression_errors.is_empty() {
+                problems.push(Problem::Error(format!("Config file is malformed: {}", e)));
+            }
+            return problems;
+        }
+    };
+    problems.extend(config.errors().into_iter().map(Problem::Error));
+    let (_, calendar_errors) = config.load_calendars(config_dir);
+    problems.extend(calendar_errors.iter().map(|e| Problem::Error(e.to_string())));
+    problems.extend(
+        duplicate_app_ids(&config).into_iter()
+            .map(|id| Problem::Error(format!("App id '{}' is defined more than once", id)))
+    );
+    for app in &config.apps {
+        for (context, command) in commands(app) {
+            if !command_exists(command, search_path) {
+                problems.push(Problem::Error(format!(
+                    "{} runs '{}', which was not found", context, command
+                )));
+            }
+        }
+        // Only warnings, since an app that is tracked by several `run`s or
+        // a `watch` at once can be used for longer than a window lasts
+        for condition in app.conditions() {
+            problems.extend(
+                lint::constant_comparisons(condition, &config.groups).into_iter()
+                    .map(|message| Problem::Warning(format!(
+                        "Condition of app '{}': {}", app.id, message
+                    )))
+            );
+        }
+    }
+    problems.extend(config.warnings().into_iter().map(Problem::Warning));
+    problems
+}
+
+fn duplicate_app_ids(config: &Config) -> BTreeSet<&str> {
+    let mut seen = BTreeSet::new();
+    config.apps.iter()
+        .map(|app| app.id.as_str())
+        .filter(|id| !seen.insert(*id))
+        .collect()
+}
+
+/// All commands that leakbuster may run for this app, with a description for
+/// the user
+fn commands(app: &App) -> Vec<(String, &str)> {
+    let mut commands = vec![(format!("App '{}'", app.id), app.cmd.as_str())];
+    for hook in &app.startup_hooks {
+        commands.push((format!("Startup hook of app '{}'", app.id), &hook.cmd));
+    }
+    for hook in &app.time_hooks {
+        if let Some(cmd) = &hook.cmd {
+            commands.push((format!("Time hook of app '{}'", app.id), cmd));
+        }
+        if let Some(condition_cmd) = &hook.condition_cmd {
+            commands.push((
+                format!("Condition command of time hook of app '{}'", app.id),
+                condition_cmd
+            ));
+        }
+    }
+    for hook in &app.shutdown_hooks {
+        commands.push((format!("Shutdown hook of app '{}'", app.id), &hook.cmd));
+    }
+    commands
+}
+
+/// Resolves the command the same way as `std::process::Command`: commands
+/// containing a slash are paths, all others are looked up in `search_path`.
+fn command_exists(command: &str, search_path: &[PathBuf]) -> bool {
+    if command.contains('/') {
+        is_executable(Path::new(command))
+    } else {
+        search_path.iter().any(|dir| is_executable(&dir.join(command)))
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::cmd::check::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        let bin = bin_dir(&["firefox", "notify-send"]);
+        let config_str = "
+apps:
+  - id: firefox
+    cmd: firefox
+    time_hooks:
+      - cmd: notify-send
+        condition: not (atmost 1 h in this day)";
+        assert_eq!(
+            Vec::<Problem>::new(),
+            problems(config_str, bin.path(), &[bin.path().to_path_buf()])
+        );
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        let bin = bin_dir(&["firefox", "not-executable"]);
+        fs::set_permissions(
+            bin.path().join("not-executable"),
+            fs::Permissions::from_mode(0o644)
+        ).unwrap();
+        let config_str = "
+apps:
+  - id: firefox
+    cmd: firefox
+    startup_hooks:
+      - cmd: not-executable
+    time_hooks:
+      - cmd: /nonexistent/notify
+        condition_cmd: firefox
+        comdition_args: []
+        condition: atmost 2 h in sliding 1 h
+  - id: firefox
+    cmd: firefox
+    shutdown_hooks:
+      - cmd: firefox
+        condition: atmost 1 h of discord in this day
+calendars:
+  vacation: vacation.ics";
+        let problems: Vec<String> = problems(config_str, bin.path(), &[bin.path().to_path_buf()])
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "Error: Unknown field `comdition_args` in TimeHook at line 10, column 9, did you mean `condition_args`?",
+                "Error: Couldn't load calendar 'vacation' from vacation.ics: No such file or directory (os error 2)",
+                "Error: App id 'firefox' is defined more than once",
+                "Error: Startup hook of app 'firefox' runs 'not-executable', which was not found",
+                "Error: Time hook of app 'firefox' runs '/nonexistent/notify', which was not found",
+                "Warning: Condition of app 'firefox': Comparison `usage(sliding 1 h) < 2 h` is always true",
+                "Warning: Condition of app 'firefox' refers to app 'discord', which is not defined in config",
+            ],
+            problems
+        );
+    }
+
+    #[test]
+    fn reports_every_expression_error() {
+        let config_str = "
+apps:
+  - id: firefox
+    cmd: firefox
+    time_hooks:
+      - cmd: true
+        condition: atmost 1 h in this dya
+        interval: 10 min
+      - cmd: true
+        condition: Mon adn Tue";
+        let problems = problems(config_str, Path::new(""), &[]);
+        assert_eq!(3, problems.len(), "{:?}", problems);
+        assert!(problems.iter().all(|p| matches!(p, Problem::Error(_))));
+    }
+
+    #[test]
+    fn reports_malformed_config() {
+        let problems = problems("apps: [{id: firefox}]", Path::new(""), &[]);
+        assert_eq!(1, problems.len(), "{:?}", problems);
+        assert!(problems[0].to_string().starts_with("Error: Config file is malformed"));
+    }
+
+    /// A directory with an empty executable file for each command
+    fn bin_dir(commands: &[&str]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for command in commands {
+            let path = dir.path().join(command);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        dir
+    }
+}
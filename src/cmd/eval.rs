@@ -1,23 +1,53 @@
 use std::path::PathBuf;
+use crate::clock::Clock;
 use crate::cmd;
-use crate::db;
 use crate::expressions;
 use crate::expressions::{parse_condition, parser::Condition};
 
-pub fn eval(db_path: Option<PathBuf>, app_id: &str, condition_str: &str) {
-    let db = db::connect_sqlite(
-        db_path.unwrap_or_else(cmd::default_db_path)
-    ).unwrap();
-    let condition: Condition = parse_condition(condition_str).unwrap();
-    match expressions::check_condition(&condition, &db, app_id) {
+pub fn eval(
+    config_path: Option<PathBuf>,
+    db_path: Option<PathBuf>,
+    app_id: &str,
+    condition_str: &str,
+    clock: &dyn Clock
+) {
+    let db = cmd::get_current_db(db_path);
+    let condition: Condition = parse_condition(condition_str)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(2)
+        });
+    // The config is only needed to check app references, and to look up
+    // groups, calendars and the start of the week, so evaluation goes ahead
+    // even if it can't be loaded
+    let config = cmd::load_optional_config(config_path, "app ids won't be checked");
+    if let Some(config) = &config {
+        let warnings = config.calendar_warnings().into_iter()
+            .chain(config.unknown_app_warnings(&condition, "Condition"));
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    let week_start = config.as_ref().map(|config| config.week_start).unwrap_or_default();
+    let (groups, calendars) = config
+        .map(|config| (config.groups, config.calendar_dates))
+        .unwrap_or_default();
+    let result = expressions::check_condition(
+        &condition, &*db, app_id, &groups, &calendars, week_start, clock
+    );
+    match result {
         Ok(b) => if b {
             std::process::exit(0)
         } else {
             std::process::exit(1)
         },
-        Err(db_error) => {
+        Err(expressions::Error::DbError(db_error)) => {
             println!("Database read error while evaluating expression: {:?}", db_error);
             std::process::exit(3)
+        },
+        Err(error) => {
+            println!("Could not evaluate expression: {:?}", error);
+            std::process::exit(3)
         }
     }
 }
@@ -0,0 +1,177 @@
+use chrono::{Local, NaiveDate};
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+use crate::calendar;
+use crate::cmd;
+use crate::db;
+use crate::db::{Session, SessionFilter};
+
+/// Format of exported and imported sessions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// With a header of `app,start,end,pid`
+    Csv,
+    /// One array of all sessions
+    Json,
+    /// One session per line
+    Ndjson
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            other => Err(format!("Unknown format `{}`, expected csv, json or ndjson", other))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    DbError(db::Error),
+    IoError(io::Error),
+    CsvError(csv::Error),
+    JsonError(serde_json::Error)
+}
+
+impl From<db::Error> for Error {
+    fn from(e: db::Error) -> Error {
+        Error::DbError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Error {
+        Error::CsvError(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::JsonError(e)
+    }
+}
+
+/// Prints the sessions of the app, or of all apps, that lie between the
+/// start of `from` and the end of `to`. Sessions that reach beyond either
+/// day are cut off there.
+pub fn export(
+    db_path: Option<PathBuf>,
+    format: Format,
+    app: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>
+) {
+    let db = cmd::get_current_db(db_path);
+    let filter = SessionFilter {
+        app_key: app,
+        from: from.map(|from| calendar::start_of_day(from, &Local).timestamp() as u64),
+        to: to.map(|to| calendar::start_of_day(to.succ(), &Local).timestamp() as u64)
+    };
+    let stdout = io::stdout();
+    let result = db.sessions(&filter)
+        .map_err(Error::from)
+        .and_then(|sessions| write_sessions(sessions, format, stdout.lock()));
+    if let Err(e) = result {
+        eprintln!("Could not export usage: {:?}", e);
+        process::exit(1)
+    }
+}
+
+/// Writes the sessions as they are read, without collecting them first
+pub(crate) fn write_sessions<I, W>(sessions: I, format: Format, out: W) -> Result<(), Error>
+    where I: Iterator<Item = db::Result<Session>>, W: Write {
+    let mut out = BufWriter::new(out);
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut out);
+            // Written by hand, so that the header is there even without
+            // any sessions
+            writer.write_record(["app", "start", "end", "pid"])?;
+            for session in sessions {
+                let session = session?;
+                writer.write_record(&[
+                    session.app,
+                    session.start.to_string(),
+                    session.end.to_string(),
+                    session.pid.map(|pid| pid.to_string()).unwrap_or_default()
+                ])?;
+            }
+            writer.flush()?;
+        },
+        Format::Json => {
+            out.write_all(b"[")?;
+            for (index, session) in sessions.enumerate() {
+                out.write_all(if index == 0 { b"\n  " } else { b",\n  " })?;
+                serde_json::to_writer(&mut out, &session?)?;
+            }
+            out.write_all(b"\n]\n")?;
+        },
+        Format::Ndjson => {
+            for session in sessions {
+                serde_json::to_writer(&mut out, &session?)?;
+                out.write_all(b"\n")?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::cmd::export::*;
+
+    fn exported(sessions: &[Session], format: Format) -> String {
+        let mut out = vec![];
+        write_sessions(sessions.iter().cloned().map(Ok), format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn sessions() -> Vec<Session> {
+        vec![
+            Session { app: "firefox".to_string(), start: 100, end: 160, pid: Some(42) },
+            Session { app: "discord".to_string(), start: 200, end: 230, pid: None }
+        ]
+    }
+
+    #[test]
+    fn sessions_are_exported_in_all_formats() {
+        assert_eq!(
+            "app,start,end,pid\nfirefox,100,160,42\ndiscord,200,230,\n",
+            exported(&sessions(), Format::Csv)
+        );
+        assert_eq!(
+            "[\n  {\"app\":\"firefox\",\"start\":100,\"end\":160,\"pid\":42},\n  \
+            {\"app\":\"discord\",\"start\":200,\"end\":230,\"pid\":null}\n]\n",
+            exported(&sessions(), Format::Json)
+        );
+        assert_eq!(
+            "{\"app\":\"firefox\",\"start\":100,\"end\":160,\"pid\":42}\n\
+            {\"app\":\"discord\",\"start\":200,\"end\":230,\"pid\":null}\n",
+            exported(&sessions(), Format::Ndjson)
+        );
+    }
+
+    #[test]
+    fn empty_exports_are_valid() {
+        assert_eq!("app,start,end,pid\n", exported(&[], Format::Csv));
+        let json: Vec<Session> = serde_json::from_str(&exported(&[], Format::Json)).unwrap();
+        assert!(json.is_empty());
+        assert_eq!("", exported(&[], Format::Ndjson));
+    }
+}
@@ -0,0 +1,801 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::process::{Child, Command, self};
+use std::thread;
+use std::time::{self, Instant};
+
+use by_address::ByAddress;
+use priority_queue::PriorityQueue;
+
+use crate::calendar::WeekStart;
+use crate::clock::Clock;
+use crate::config::{Action, App, SignalTarget, StartupHook, TimeHook, ShutdownHook};
+use crate::db;
+use crate::db::UsageStore;
+use crate::db::recorder::UsageRecorder;
+use crate::expressions;
+use crate::expressions::{Calendars, Groups};
+use crate::expressions::parser::Condition;
+use crate::process::{
+    run_with_timeout, signal_group, signal_process, with_spawned, Children, Finished, Signal
+};
+use crate::process::scanner;
+use crate::process::scanner::ProcessInfo;
+use crate::process::tree;
+use crate::process::tree::ProcessTree;
+
+/// What hook conditions are evaluated against
+pub struct HookContext<'a> {
+    pub db: &'a dyn UsageStore,
+    pub app_id: &'a str,
+    pub groups: &'a Groups,
+    pub calendars: &'a Calendars,
+    pub week_start: WeekStart,
+    pub clock: &'a dyn Clock
+}
+
+/// The processes of the app, which hook actions are sent to
+pub struct RunningApp {
+    tree: ProcessTree,
+    /// Process group that `group` actions are sent to. Only set if the app
+    /// has a process group of its own, otherwise they are sent to the
+    /// processes of the app, as `process` actions are.
+    group: Option<u32>,
+    /// Whether the app was stopped by a `stop` action. The time until it is
+    /// resumed doesn't count as usage.
+    pub stopped: bool,
+    /// When to kill the app after a `terminate` action, and what to kill
+    kill_at: Option<(Instant, SignalTarget)>,
+    usage: UsageRecorder
+}
+
+/// How often the usage that is left when the app exits is written, before
+/// giving up on it
+const FINAL_WRITE_ATTEMPTS: u32 = 3;
+
+impl RunningApp {
+    pub fn new(app_id: &str, tree: ProcessTree, group: Option<u32>) -> RunningApp {
+        let usage = UsageRecorder::new(app_id, Some(tree.root()));
+        RunningApp { tree, group, stopped: false, kill_at: None, usage }
+    }
+
+    /// Records that the app ran for the `duration` seconds up to
+    /// `timestamp`, unless it was stopped by a hook. Usage is added to the
+    /// current session, unless there is a gap since its end, e.g. because
+    /// the app was stopped or the computer was suspended. If writing fails,
+    /// the usage is written along with the next one.
+    pub fn record_usage(&mut self, db: &dyn UsageStore, timestamp: u64, duration: u64) -> db::Result<()> {
+        if self.stopped {
+            self.usage.pause();
+            return self.usage.flush(db);
+        }
+        self.usage.record(db, timestamp, duration)
+    }
+
+    /// Writes the usage that is left once the app has exited, retrying for
+    /// a while if the db is busy.
+    pub fn finish_recording(&mut self, db: &dyn UsageStore, clock: &dyn Clock) {
+        self.usage.pause();
+        for attempt in 1..=FINAL_WRITE_ATTEMPTS {
+            match self.usage.flush(db) {
+                Ok(()) => return,
+                Err(e) if attempt < FINAL_WRITE_ATTEMPTS => {
+                    eprintln!("Failed to record usage, retrying: {:?}", e);
+                    clock.sleep(time::Duration::from_secs(1));
+                },
+                Err(e) => eprintln!(
+                    "Failed to record usage, {} s are lost: {:?}",
+                    self.usage.unwritten_seconds(),
+                    e
+                )
+            }
+        }
+    }
+
+    /// Finds the processes that currently belong to the app, and reaps those
+    /// that have exited. `app_cmd` is the process that leakbuster started.
+    /// Returns whether the app is still running.
+    pub fn update(&mut self, app_cmd: &mut Child) -> bool {
+        // The process that leakbuster started counts as running until it is
+        // reaped, since its children may not have shown up in /proc yet
+        let root_running = matches!(app_cmd.try_wait(), Ok(None));
+        self.scan(app_cmd.id(), root_running);
+        if !self.tree.is_alive() && !root_running {
+            // The listing of /proc is read before the processes themselves,
+            // so it may lack children that the last process started right
+            // before it exited. Those were re-parented to leakbuster by now.
+            self.scan(app_cmd.id(), root_running);
+        }
+        root_running || self.tree.is_alive()
+    }
+
+    /// Hooks, including the shutdown hooks that the Ctrl-C handler starts on
+    /// its own thread, are children of leakbuster as well. They are neither
+    /// part of the app nor reaped here.
+    fn scan(&mut self, root: u32, root_running: bool) {
+        let reaper = Some(process::id());
+        match scanner::scan() {
+            Ok(processes) => with_spawned(|hook_pids| {
+                let mut own_children = hook_pids.clone();
+                own_children.insert(root);
+                tree::reap_orphans(&processes, &own_children);
+                self.tree.update(&processes, reaper, hook_pids);
+            }),
+            // Without /proc, only the process that leakbuster started can
+            // be followed
+            Err(_) if !root_running =>
+                with_spawned(|hook_pids| self.tree.update(&[], reaper, hook_pids)),
+            Err(_) => {}
+        }
+    }
+
+    /// Updates the processes of an app that leakbuster didn't start, from a
+    /// snapshot of all processes. `matches` are the processes that were
+    /// recognized as the app, which join the tree along with their
+    /// descendants. Returns whether the app is still running.
+    pub fn update_watched(&mut self, processes: &[ProcessInfo], matches: &[u32]) -> bool {
+        for pid in matches {
+            self.tree.add(*pid);
+        }
+        self.tree.update(processes, None, &BTreeSet::new());
+        self.tree.is_alive()
+    }
+
+    /// Performs the action of a time hook, which is due at `now`
+    pub fn perform(
+        &mut self,
+        action: Action,
+        target: SignalTarget,
+        grace_period: time::Duration,
+        now: Instant
+    ) {
+        let target = match target {
+            SignalTarget::Group if self.group.is_none() => SignalTarget::Process,
+            target => target
+        };
+        let result = match action {
+            Action::Terminate => {
+                self.kill_at = Some((now + grace_period, target));
+                self.stopped = false;
+                // A stopped app can only handle SIGTERM once it is resumed
+                self.signal(Signal::Term, target)
+                    .and_then(|_| self.signal(Signal::Cont, target))
+            },
+            Action::Kill => {
+                self.kill_at = None;
+                self.signal(Signal::Kill, target)
+            },
+            Action::Stop => {
+                self.stopped = true;
+                self.signal(Signal::Stop, target)
+            },
+            Action::Cont => {
+                self.stopped = false;
+                self.signal(Signal::Cont, target)
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Could not send {:?} to app: {:?}", action, e);
+        }
+    }
+
+    pub fn kill_if_grace_period_over(&mut self, now: Instant) {
+        if let Some((deadline, target)) = self.kill_at {
+            if now >= deadline {
+                self.kill_at = None;
+                self.signal(Signal::Kill, target)
+                    .unwrap_or_else(|e| eprintln!("Could not kill app: {:?}", e));
+            }
+        }
+    }
+
+    /// Once the app has exited, other processes in its group may still be
+    /// within their grace period. Waits for it to end, and kills what's left.
+    pub fn finish_termination(&mut self, clock: &dyn Clock) {
+        if let Some((deadline, SignalTarget::Group)) = self.kill_at {
+            clock.sleep(deadline.saturating_duration_since(clock.instant()));
+            // Fails if all processes in the group are gone, which is fine
+            let _ = self.signal(Signal::Kill, SignalTarget::Group);
+            self.kill_at = None;
+        }
+    }
+
+    /// Sends the signal to all processes of the app, or to its process
+    /// group. Only fails if the signal didn't reach any process.
+    fn signal(&self, signal: Signal, target: SignalTarget) -> io::Result<()> {
+        match (target, self.group) {
+            (SignalTarget::Group, Some(group)) => signal_group(group, signal),
+            _ => {
+                let mut result = Err(io::Error::from_raw_os_error(libc::ESRCH));
+                for pid in self.tree.members() {
+                    // Processes may have exited since the last update
+                    if signal_process(*pid, signal).is_ok() {
+                        result = Ok(());
+                    }
+                }
+                result
+            }
+        }
+    }
+}
+
+/// When each time hook of an app is due next, and the condition commands of
+/// the hooks that are waiting for them
+pub struct TimeHookSchedule<'a> {
+    due: PriorityQueue<ByAddress<&'a TimeHook>, Reverse<Instant>>,
+    condition_cmds: Children,
+    /// Hook of each running condition command, by its pid
+    checked: BTreeMap<u32, &'a TimeHook>
+}
+
+impl<'a> TimeHookSchedule<'a> {
+    /// Leaves the condition commands that are still running to `children`,
+    /// e.g. because the app exited. Their hooks won't be started anymore.
+    pub fn abandon_condition_cmds(&mut self, children: &mut Children) {
+        children.append(&mut self.condition_cmds);
+        self.checked.clear();
+    }
+}
+
+/// Schedules each time hook for after its initial delay, counted from `now`
+pub fn schedule_time_hooks<'a>(time_hooks: &'a [TimeHook], now: Instant) -> TimeHookSchedule<'a> {
+    let mut due = PriorityQueue::new();
+    for time_hook in time_hooks {
+        due.push(ByAddress(time_hook), Reverse(now + time_hook.initial_delay.into()));
+    }
+    TimeHookSchedule { due, condition_cmds: Children::new(), checked: BTreeMap::new() }
+}
+
+/// Checks the conditions of the time hooks that are due by the clock of
+/// `ctx`, and reschedules them, unless they only run once. Condition commands
+/// run in the background, so they don't hold up recording usage. Hooks whose
+/// condition commands succeeded since the last call are started.
+pub fn start_due_time_hooks<'a>(
+    schedule: &mut TimeHookSchedule<'a>,
+    ctx: &HookContext,
+    app: &mut RunningApp,
+    children: &mut Children
+) {
+    for finished in schedule.condition_cmds.reap() {
+        let hook = schedule.checked.remove(&finished.pid);
+        let success = finished.success();
+        log_finished(vec![finished]);
+        if let Some(hook) = hook.filter(|_| success) {
+            start_time_hook(hook, ctx, app, children);
+        }
+    }
+    let now = ctx.clock.instant();
+    while let Some(hook) = schedule.due.peek()
+        .and_then(|(hook_by_addr, prio)| {
+            if prio.0 > now {
+                None
+            } else {
+                Some(hook_by_addr.0)
+            }
+    }) {
+        check_time_hook(hook, schedule, ctx, app, children);
+        if hook.interval.seconds > 0 {
+            schedule.due.push_decrease(ByAddress(hook), Reverse(now + hook.interval.into()));
+        } else {
+            schedule.due.pop();
+        }
+    }
+}
+
+/// How long the condition command of a time hook without
+/// `condition_timeout` may run
+const CONDITION_CMD_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// Starts the hook if its condition holds, and it has no condition command.
+/// Otherwise, the condition command is started, unless the one of the last
+/// time that the hook was due still runs. A condition command that can't be
+/// started counts as failed.
+fn check_time_hook<'a>(
+    hook: &'a TimeHook,
+    schedule: &mut TimeHookSchedule<'a>,
+    ctx: &HookContext,
+    app: &mut RunningApp,
+    children: &mut Children
+) {
+    if !condition_holds(&hook.condition, ctx) {
+        return;
+    }
+    let cmd = match &hook.condition_cmd {
+        None => return start_time_hook(hook, ctx, app, children),
+        Some(cmd) => cmd
+    };
+    if schedule.checked.values().any(|checked| std::ptr::eq(*checked, hook)) {
+        return;
+    }
+    let spawned = schedule.condition_cmds.spawn(
+        format!("Condition command `{}`", cmd),
+        Command::new(cmd).args(&hook.condition_args),
+        Some(hook.condition_timeout.map_or(CONDITION_CMD_TIMEOUT, Into::into))
+    );
+    match spawned {
+        Ok(pid) => {
+            schedule.checked.insert(pid, hook);
+        },
+        Err(e) => eprintln!("Could not run condition command {:?}, skipping hook: {:?}", cmd, e)
+    }
+}
+
+/// Runs the command of the time hook, or performs its action, once its
+/// conditions were checked
+pub fn start_time_hook(
+    hook: &TimeHook,
+    ctx: &HookContext,
+    app: &mut RunningApp,
+    children: &mut Children
+) {
+    match (&hook.action, &hook.cmd) {
+        (Some(action), _) => app.perform(
+            *action,
+            hook.target,
+            hook.grace_period.into(),
+            ctx.clock.instant()
+        ),
+        (None, Some(cmd)) => {
+            children.spawn(
+                format!("Time hook `{}`", cmd),
+                Command::new(cmd).args(&hook.args),
+                hook.timeout.map(Into::into)
+            ).expect("Couldn't start time hook!");
+        },
+        // Rejected when the config is loaded
+        (None, None) => {}
+    }
+}
+
+pub fn start_shutdown_hooks(hooks: &[ShutdownHook], ctx: &HookContext, children: &mut Children) {
+    for hook in hooks {
+        if !condition_holds(&hook.condition, ctx) {
+            continue;
+        }
+        children.spawn(
+            format!("Shutdown hook `{}`", hook.cmd),
+            Command::new(&hook.cmd).args(&hook.args),
+            hook.timeout.map(Into::into)
+        ).expect("Failed to start shutdown hook");
+    }
+}
+
+pub fn check_startup_hooks<'a>(app: &'a App, ctx: &HookContext)
+    -> Result<(), &'a StartupHook> {
+    for hook in &app.startup_hooks {
+        if !condition_holds(&hook.condition, ctx) {
+            continue;
+        }
+        let finished = run_with_timeout(
+            format!("Startup hook `{}`", hook.cmd),
+            Command::new(&hook.cmd).args(&hook.args),
+            hook.timeout.map(Into::into)
+        ).unwrap_or_else(|_| panic!("Failed to run startup hook: {:?}", hook));
+        let success = finished.success();
+        log_finished(vec![finished]);
+        if !success {
+            return Err(hook)
+        }
+    }
+    Ok(())
+}
+
+/// Reports the exit status and runtime of hooks that are done
+pub fn log_finished(finished: Vec<Finished>) {
+    for f in finished {
+        eprintln!("{}", f);
+    }
+}
+
+/// Evaluates the condition of a hook. A hook without condition always runs.
+/// If the condition can't be evaluated (e.g. because the db is locked), the
+/// error is reported and the condition is treated as false, so the hook is
+/// skipped rather than fired on incomplete information.
+fn condition_holds(condition: &Option<Condition>, ctx: &HookContext) -> bool {
+    match condition {
+        None => true,
+        Some(c) => {
+            let holds = expressions::check_condition(
+                c, ctx.db, ctx.app_id, ctx.groups, ctx.calendars, ctx.week_start, ctx.clock
+            );
+            holds.unwrap_or_else(|e| {
+                eprintln!("Could not evaluate hook condition, skipping hook: {:?}", e);
+                false
+            })
+        }
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::clock::{SimulatedClock, SystemClock};
+    use crate::cmd::hooks::*;
+    use crate::db;
+    use crate::db::memory::MemoryStore;
+    use chrono::prelude::*;
+    use crate::expressions::parse_condition;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Stdio;
+    use std::time::SystemTime;
+
+    // Conditions whose value doesn't depend on the current time, as long
+    // as the db is empty
+    const TRUE: &str = "atmost 1 h in sliding 1 D";
+    const FALSE: &str = "atmost 0 s in sliding 1 D";
+
+    #[test]
+    fn condition_holds_without_condition() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        assert!(condition_holds(&None, &ctx(&db, "app", &groups)));
+    }
+
+    #[test]
+    fn condition_holds_is_evaluated_against_db() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let ctx = ctx(&db, "app", &groups);
+        assert!(condition_holds(&Some(parse_condition(TRUE).unwrap()), &ctx));
+        assert!(!condition_holds(&Some(parse_condition(FALSE).unwrap()), &ctx));
+    }
+
+    #[test]
+    fn condition_holds_is_false_on_evaluation_error() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let condition = parse_condition("atmost 1 h of group:unknown in this day").unwrap();
+        assert!(!condition_holds(&Some(condition), &ctx(&db, "app", &groups)));
+    }
+
+    #[test]
+    fn condition_holds_uses_app_id() {
+        let db = db::open_in_memory().unwrap();
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        db.record_usage("busy-app", now - 10, 7200).unwrap();
+        let groups = Groups::new();
+        let condition = Some(parse_condition(TRUE).unwrap());
+        assert!(!condition_holds(&condition, &ctx(&db, "busy-app", &groups)));
+        assert!(condition_holds(&condition, &ctx(&db, "idle-app", &groups)));
+    }
+
+    #[test]
+    fn time_hooks_wait_for_their_condition_cmds() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let ctx = ctx(&db, "app", &groups);
+        let mut app = running_app(process::id());
+        let mut children = Children::new();
+        let hooks = [
+            condition_cmd_hook("true", "sh", &["-c", "sleep 0.2; exit 0"]),
+            condition_cmd_hook("false", "sh", &["-c", "exit 1"]),
+            condition_cmd_hook("echo", "/nonexistent/leakbuster-condition", &[]),
+        ];
+        let mut schedule = schedule_time_hooks(&hooks, Instant::now());
+        start_due_time_hooks(&mut schedule, &ctx, &mut app, &mut children);
+        assert!(children.is_empty());
+        assert_eq!(2, schedule.checked.len());
+        while !schedule.checked.is_empty() {
+            thread::sleep(time::Duration::from_millis(10));
+            start_due_time_hooks(&mut schedule, &ctx, &mut app, &mut children);
+        }
+        let labels: Vec<String> = children.wait_all().into_iter().map(|f| f.label).collect();
+        assert_eq!(vec!["Time hook `true`"], labels);
+    }
+
+    #[test]
+    fn hung_condition_cmd_runs_in_background_until_timeout() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let ctx = ctx(&db, "app", &groups);
+        let mut app = running_app(process::id());
+        let mut children = Children::new();
+        let hooks = [TimeHook {
+            condition_timeout: Some(crate::expressions::Duration { seconds: 1 }),
+            ..condition_cmd_hook("true", "sleep", &["10"])
+        }];
+        let started = Instant::now();
+        let mut schedule = schedule_time_hooks(&hooks, started);
+        start_due_time_hooks(&mut schedule, &ctx, &mut app, &mut children);
+        assert!(started.elapsed() < time::Duration::from_millis(500));
+        while !schedule.checked.is_empty() {
+            thread::sleep(time::Duration::from_millis(10));
+            start_due_time_hooks(&mut schedule, &ctx, &mut app, &mut children);
+        }
+        assert!(started.elapsed() < time::Duration::from_secs(5));
+        assert!(children.is_empty());
+    }
+
+    #[test]
+    fn abandoned_condition_cmds_are_reaped_with_the_hooks() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let ctx = ctx(&db, "app", &groups);
+        let mut app = running_app(process::id());
+        let mut children = Children::new();
+        let hooks = [condition_cmd_hook("true", "true", &[])];
+        let mut schedule = schedule_time_hooks(&hooks, Instant::now());
+        start_due_time_hooks(&mut schedule, &ctx, &mut app, &mut children);
+        schedule.abandon_condition_cmds(&mut children);
+        assert!(schedule.checked.is_empty());
+        let labels: Vec<String> = children.wait_all().into_iter().map(|f| f.label).collect();
+        assert_eq!(vec!["Condition command `true`"], labels);
+    }
+
+    #[test]
+    fn startup_hooks_with_false_condition_are_skipped() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let app = app_with_startup_hooks(vec![
+            startup_hook(&["-c", "exit 1"], Some(FALSE)),
+            startup_hook(&["-c", "exit 0"], Some(TRUE)),
+            startup_hook(&["-c", "exit 0"], None),
+        ]);
+        assert!(check_startup_hooks(&app, &ctx(&db, "app", &groups)).is_ok());
+    }
+
+    #[test]
+    fn startup_hooks_with_true_condition_can_prevent_run() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let app = app_with_startup_hooks(vec![
+            startup_hook(&["-c", "exit 0"], None),
+            startup_hook(&["-c", "exit 1"], Some(TRUE)),
+        ]);
+        assert_eq!(
+            Err(&app.startup_hooks[1]),
+            check_startup_hooks(&app, &ctx(&db, "app", &groups))
+        );
+    }
+
+    #[test]
+    fn hung_startup_hook_times_out_and_prevents_run() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let mut hung = startup_hook(&["-c", "sleep 10"], None);
+        hung.timeout = Some(crate::expressions::Duration { seconds: 1 });
+        let app = app_with_startup_hooks(vec![hung]);
+        let started = Instant::now();
+        assert_eq!(
+            Err(&app.startup_hooks[0]),
+            check_startup_hooks(&app, &ctx(&db, "app", &groups))
+        );
+        assert!(started.elapsed() < time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn background_hooks_are_tracked() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let ctx = ctx(&db, "app", &groups);
+        let mut app = running_app(process::id());
+        let mut children = Children::new();
+        let shutdown_hooks = vec![
+            ShutdownHook {
+                cmd: "true".to_string(),
+                args: vec![],
+                timeout: None,
+                condition: None
+            },
+            ShutdownHook {
+                cmd: "true".to_string(),
+                args: vec![],
+                timeout: None,
+                condition: Some(parse_condition(FALSE).unwrap())
+            },
+        ];
+        start_time_hook(&time_hook(Some("true"), None), &ctx, &mut app, &mut children);
+        start_shutdown_hooks(&shutdown_hooks, &ctx, &mut children);
+        assert_eq!(2, children.len());
+        // Hooks finish in any order
+        let mut labels: Vec<String> = children.wait_all().into_iter().map(|f| f.label).collect();
+        labels.sort();
+        assert_eq!(vec!["Shutdown hook `true`", "Time hook `true`"], labels);
+    }
+
+    #[test]
+    fn usage_is_recorded_in_sessions() {
+        let db = db::open_in_memory().unwrap();
+        let mut app = running_app(process::id());
+        for timestamp in [101, 102, 104, 105] {
+            app.record_usage(&db, timestamp, 1).unwrap();
+        }
+        // Time while the app is stopped doesn't count
+        app.stopped = true;
+        app.record_usage(&db, 106, 1).unwrap();
+        app.stopped = false;
+        app.record_usage(&db, 110, 1).unwrap();
+        app.record_usage(&db, 111, 1).unwrap();
+        // Suspended for an hour
+        app.record_usage(&db, 3711, 1).unwrap();
+        assert_eq!(5, db.get_usage("app", 0, 106).unwrap());
+        assert_eq!(2, db.get_usage("app", 106, 3000).unwrap());
+        assert_eq!(8, db.get_usage("app", 0, 4000).unwrap());
+    }
+
+    #[test]
+    fn terminate_kills_after_grace_period() {
+        // Ignored signals stay ignored across exec
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; echo ready; exec sleep 10"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut ready = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut ready).unwrap();
+
+        let mut app = running_app(child.id());
+        let grace_period = time::Duration::from_secs(60);
+        let now = Instant::now();
+        app.perform(Action::Terminate, SignalTarget::Process, grace_period, now);
+        app.kill_if_grace_period_over(now);
+        assert!(child.try_wait().unwrap().is_none(), "App should survive SIGTERM");
+        app.kill_if_grace_period_over(now + grace_period);
+        assert_eq!(Some(libc::SIGKILL), child.wait().unwrap().signal());
+    }
+
+    #[test]
+    fn stop_and_cont_pause_and_resume_the_app() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let mut app = running_app(child.id());
+        let no_grace = time::Duration::from_secs(0);
+
+        app.perform(Action::Stop, SignalTarget::Process, no_grace, Instant::now());
+        assert!(app.stopped);
+        assert_eq!('T', wait_for_state(child.id(), 'T'));
+        app.perform(Action::Cont, SignalTarget::Process, no_grace, Instant::now());
+        assert!(!app.stopped);
+        assert_eq!('S', wait_for_state(child.id(), 'S'));
+
+        app.perform(Action::Kill, SignalTarget::Process, no_grace, Instant::now());
+        assert_eq!(Some(libc::SIGKILL), child.wait().unwrap().signal());
+    }
+
+    #[test]
+    fn time_hook_action_signals_app() {
+        let db = db::open_in_memory().unwrap();
+        let groups = Groups::new();
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let mut app = running_app(child.id());
+        let mut children = Children::new();
+        let mut hook = time_hook(None, Some(Action::Terminate));
+        start_time_hook(&hook, &ctx(&db, "app", &groups), &mut app, &mut children);
+        assert_eq!(Some(libc::SIGTERM), child.wait().unwrap().signal());
+
+        // Signalling an app that is gone is reported, but isn't fatal. The
+        // reaped pid may have been reused, so the tree is emptied instead.
+        app.tree.update(&[], None, &BTreeSet::new());
+        assert!(!app.tree.is_alive());
+        hook.action = Some(Action::Kill);
+        start_time_hook(&hook, &ctx(&db, "app", &groups), &mut app, &mut children);
+        assert!(children.is_empty());
+    }
+
+    #[test]
+    fn simulated_clock_drives_time_hooks_and_usage() {
+        let clock = SimulatedClock::new(Local.ymd(2021, 12, 6).and_hms(0, 0, 0));
+        let db = MemoryStore::new();
+        let groups = Groups::new();
+        let ctx = HookContext {
+            db: &db,
+            app_id: "app",
+            groups: &groups,
+            calendars: &NO_CALENDARS,
+            week_start: WeekStart::Monday,
+            clock: &clock
+        };
+        let mut app = running_app(process::id());
+        let mut children = Children::new();
+        let mut hook = time_hook(Some("true"), None);
+        hook.interval = crate::expressions::Duration { seconds: 600 };
+        hook.condition = Some(parse_condition("not atmost 2 h in this day").unwrap());
+        let hooks = [hook];
+        let mut schedule = schedule_time_hooks(&hooks, clock.instant());
+
+        // Four hours of use, after two of which the hook starts to fire
+        // every ten minutes
+        let delay = time::Duration::from_secs(1);
+        for _ in 0..4 * 3600 {
+            clock.sleep(delay);
+            app.record_usage(&db, clock.timestamp(), delay.as_secs()).unwrap();
+            start_due_time_hooks(&mut schedule, &ctx, &mut app, &mut children);
+        }
+        assert_eq!(4 * 3600, db.get_usage("app", 0, clock.timestamp()).unwrap());
+        assert_eq!(12, children.wait_all().len());
+
+        // The next day, the usage of the previous one doesn't count
+        clock.advance(time::Duration::from_secs(20 * 3600));
+        app.record_usage(&db, clock.timestamp(), delay.as_secs()).unwrap();
+        start_due_time_hooks(&mut schedule, &ctx, &mut app, &mut children);
+        assert!(children.is_empty());
+    }
+
+    fn running_app(pid: u32) -> RunningApp {
+        RunningApp::new("app", ProcessTree::new(pid, 0, false), None)
+    }
+
+    /// Polls the state of a process in /proc until it matches, or a second
+    /// has passed. Returns the last state.
+    fn wait_for_state(pid: u32, expected: char) -> char {
+        let mut state = '?';
+        for _ in 0..100 {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+            // The state follows the command name, which is in brackets
+            state = stat[stat.rfind(')').unwrap() + 2..].chars().next().unwrap();
+            if state == expected {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        state
+    }
+
+    fn time_hook(cmd: Option<&str>, action: Option<Action>) -> TimeHook {
+        TimeHook {
+            cmd: cmd.map(|c| c.to_string()),
+            args: vec![],
+            timeout: None,
+            action,
+            target: SignalTarget::Process,
+            grace_period: crate::expressions::Duration { seconds: 10 },
+            condition_cmd: None,
+            condition_args: vec![],
+            condition_timeout: None,
+            condition: None,
+            interval: crate::expressions::Duration { seconds: 10 },
+            initial_delay: crate::expressions::Duration { seconds: 0 }
+        }
+    }
+
+    fn condition_cmd_hook(cmd: &str, condition_cmd: &str, condition_args: &[&str]) -> TimeHook {
+        TimeHook {
+            condition_cmd: Some(condition_cmd.to_string()),
+            condition_args: args(condition_args),
+            ..time_hook(Some(cmd), None)
+        }
+    }
+
+    static NO_CALENDARS: Calendars = Calendars::new();
+
+    fn ctx<'a>(db: &'a dyn UsageStore, app_id: &'a str, groups: &'a Groups) -> HookContext<'a> {
+        HookContext {
+            db,
+            app_id,
+            groups,
+            calendars: &NO_CALENDARS,
+            week_start: WeekStart::Monday,
+            clock: &SystemClock
+        }
+    }
+
+    fn app_with_startup_hooks(startup_hooks: Vec<StartupHook>) -> App {
+        App {
+            id: "app".to_string(),
+            cmd: "true".to_string(),
+            args: vec![],
+            count_daemons: false,
+            watch: Default::default(),
+            startup_hooks,
+            time_hooks: vec![],
+            shutdown_hooks: vec![],
+        }
+    }
+
+    fn startup_hook(sh_args: &[&str], condition: Option<&str>) -> StartupHook {
+        StartupHook {
+            cmd: "sh".to_string(),
+            args: args(sh_args),
+            timeout: None,
+            condition: condition.map(|c| parse_condition(c).unwrap()),
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+}
+
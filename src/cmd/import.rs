@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::cmd;
+use crate::cmd::export::Format;
+use crate::db::Session;
+
+/// Adds the sessions in the file to the db, in the format that `export`
+/// writes. Sessions that the db has already are skipped, and those that
+/// overlap stored sessions are merged with them, so a file can be imported
+/// more than once. If any session is invalid, none are imported.
+/// The format is guessed from the file extension if it isn't given.
+pub fn import(db_path: Option<PathBuf>, format: Option<Format>, path: PathBuf) {
+    let format = format.or_else(|| format_of(&path)).unwrap_or_else(|| {
+        eprintln!(
+            "Can't tell the format of {} from its extension, please give --format",
+            path.display()
+        );
+        process::exit(2)
+    });
+    let file = File::open(&path).unwrap_or_else(|e| {
+        eprintln!("Could not open {}: {}", path.display(), e);
+        process::exit(2)
+    });
+    let sessions = read_sessions(BufReader::new(file), format).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", path.display(), e);
+        process::exit(1)
+    });
+    let db = cmd::get_current_db(db_path);
+    match db.import_sessions(&sessions) {
+        Ok(summary) => println!(
+            "Imported {} sessions, merged {} with sessions that overlap them, \
+            skipped {} that were there already",
+            summary.imported, summary.merged, summary.duplicates
+        ),
+        Err(e) => {
+            eprintln!("Nothing was imported: {:?}", e);
+            process::exit(1)
+        }
+    }
+}
+
+fn format_of(path: &Path) -> Option<Format> {
+    match path.extension()?.to_str()? {
+        "csv" => Some(Format::Csv),
+        "json" => Some(Format::Json),
+        "ndjson" | "jsonl" => Some(Format::Ndjson),
+        _ => None
+    }
+}
+
+/// Parses all sessions, naming the row of the first that can't be parsed
+fn read_sessions<R: BufRead>(input: R, format: Format) -> Result<Vec<Session>, String> {
+    match format {
+        Format::Csv => csv::Reader::from_reader(input).deserialize()
+            .enumerate()
+            .map(|(index, row)| row.map_err(|e| format!("Row {}: {}", index + 1, e)))
+            .collect(),
+        Format::Json => {
+            let rows: Vec<serde_json::Value> = serde_json::from_reader(input)
+                .map_err(|e| e.to_string())?;
+            rows.into_iter()
+                .enumerate()
+                .map(|(index, row)| serde_json::from_value(row)
+                    .map_err(|e| format!("Row {}: {}", index + 1, e)))
+                .collect()
+        },
+        Format::Ndjson => {
+            let mut sessions = vec![];
+            for (index, line) in input.lines().enumerate() {
+                let line = line.map_err(|e| e.to_string())?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                sessions.push(serde_json::from_str(&line)
+                    .map_err(|e| format!("Line {}: {}", index + 1, e))?);
+            }
+            Ok(sessions)
+        }
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::cmd::export;
+    use crate::cmd::import::*;
+    use crate::db::{ImportSummary, SessionFilter, UsageStore};
+    use crate::db::memory::MemoryStore;
+
+    fn session(app: &str, start: u64, end: u64, pid: Option<u32>) -> Session {
+        Session { app: app.to_string(), start, end, pid }
+    }
+
+    #[test]
+    fn formats_are_guessed_from_the_extension() {
+        assert_eq!(Some(Format::Csv), format_of(Path::new("usage.csv")));
+        assert_eq!(Some(Format::Json), format_of(Path::new("usage.json")));
+        assert_eq!(Some(Format::Ndjson), format_of(Path::new("usage.ndjson")));
+        assert_eq!(Some(Format::Ndjson), format_of(Path::new("usage.jsonl")));
+        assert_eq!(None, format_of(Path::new("usage")));
+    }
+
+    #[test]
+    fn all_formats_are_read() {
+        let expected = vec![session("firefox", 100, 160, Some(42)), session("discord", 200, 230, None)];
+        let csv = "app,start,end,pid\nfirefox,100,160,42\ndiscord,200,230,\n";
+        assert_eq!(Ok(expected.clone()), read_sessions(csv.as_bytes(), Format::Csv));
+        let json = "[{\"app\":\"firefox\",\"start\":100,\"end\":160,\"pid\":42},\
+            {\"app\":\"discord\",\"start\":200,\"end\":230}]";
+        assert_eq!(Ok(expected.clone()), read_sessions(json.as_bytes(), Format::Json));
+        let ndjson = "{\"app\":\"firefox\",\"start\":100,\"end\":160,\"pid\":42}\n\n\
+            {\"app\":\"discord\",\"start\":200,\"end\":230,\"pid\":null}\n";
+        assert_eq!(Ok(expected), read_sessions(ndjson.as_bytes(), Format::Ndjson));
+    }
+
+    #[test]
+    fn malformed_rows_are_named() {
+        let csv = "app,start,end,pid\nfirefox,100,160,42\ndiscord,soon,230,\n";
+        let error = read_sessions(csv.as_bytes(), Format::Csv).unwrap_err();
+        assert!(error.starts_with("Row 2:"), "{}", error);
+        let json = "[{\"app\":\"firefox\",\"start\":100,\"end\":160}, {\"app\":\"discord\"}]";
+        let error = read_sessions(json.as_bytes(), Format::Json).unwrap_err();
+        assert!(error.starts_with("Row 2:"), "{}", error);
+        let ndjson = "{\"app\":\"firefox\",\"start\":100,\"end\":160}\n\n{\"app\":";
+        let error = read_sessions(ndjson.as_bytes(), Format::Ndjson).unwrap_err();
+        assert!(error.starts_with("Line 3:"), "{}", error);
+    }
+
+    #[test]
+    fn exports_can_be_imported_elsewhere() {
+        let source = MemoryStore::new();
+        source.start_session("firefox", 100, 160, Some(42)).unwrap();
+        source.record_usage("discord", 230, 30).unwrap();
+        let target = MemoryStore::new();
+        target.record_usage("discord", 230, 30).unwrap();
+        for format in [Format::Csv, Format::Json, Format::Ndjson] {
+            let mut exported = vec![];
+            let sessions = source.sessions(&SessionFilter::default()).unwrap();
+            export::write_sessions(sessions, format, &mut exported).unwrap();
+            let sessions = read_sessions(exported.as_slice(), format).unwrap();
+            let summary = target.import_sessions(&sessions).unwrap();
+            if format == Format::Csv {
+                assert_eq!(ImportSummary { imported: 1, merged: 0, duplicates: 1 }, summary);
+            } else {
+                assert_eq!(ImportSummary { imported: 0, merged: 0, duplicates: 2 }, summary);
+            }
+        }
+        assert_eq!(60, target.get_usage("firefox", 0, 1000).unwrap());
+        assert_eq!(30, target.get_usage("discord", 0, 1000).unwrap());
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+use std::process;
+
+use crate::cmd;
+use crate::db;
+use crate::db::migrations::LATEST_VERSION;
+
+pub fn migrate(db_path: Option<PathBuf>, dry_run: bool) {
+    let path = db_path.unwrap_or_else(cmd::default_db_path);
+    let db = db::connect_sqlite_unmigrated(&path).unwrap_or_else(|e| {
+        eprintln!("Could not open db {}: {:?}", path.display(), e);
+        process::exit(2)
+    });
+    let version = db.schema_version().unwrap_or_else(|e| {
+        eprintln!("Could not read schema version of db {}: {:?}", path.display(), e);
+        process::exit(2)
+    });
+    match db.migrate(dry_run) {
+        Ok(applied) if applied.is_empty() =>
+            println!("Db is up to date, at schema version {}", version),
+        Ok(applied) => {
+            println!(
+                "{} schema version {} to {}:",
+                if dry_run { "Would upgrade from" } else { "Upgraded from" },
+                version, LATEST_VERSION
+            );
+            for (version, description) in applied {
+                println!("  {}: {}", version, description);
+            }
+        },
+        Err(db::Error::NewerSchemaError { version, supported }) => {
+            eprintln!("{}", cmd::newer_schema_message(version, supported));
+            process::exit(1)
+        },
+        Err(e) => {
+            eprintln!(
+                "Migration failed, the db was left at the last version that was reached: {:?}", e
+            );
+            process::exit(1)
+        }
+    }
+}
@@ -0,0 +1,341 @@
+mod svg;
+
+use chrono::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+use crate::calendar;
+use crate::clock::Clock;
+use crate::cmd;
+use crate::cmd::stats::format_duration;
+use crate::config::Config;
+use crate::db;
+use crate::db::{SessionFilter, UsageStore};
+use crate::expressions::parser::Subject;
+
+use svg::escape;
+
+/// Longest range that a report can cover, about five years. Each day is
+/// read from the db on its own.
+const MAX_DAYS: u32 = 5 * 366;
+
+/// Number of days that the report covers, up to and including today
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Days(pub u32);
+
+impl FromStr for Days {
+    type Err = String;
+
+    /// Parses a number of days or weeks, like `30D` or `4 W`
+    fn from_str(s: &str) -> Result<Days, String> {
+        let error = || format!(
+            "Invalid range `{}`, expected a number of days or weeks, e.g. 30D or 4W, \
+            of at most {} days",
+            s, MAX_DAYS
+        );
+        let (number, factor) = match (s.strip_suffix('D'), s.strip_suffix('W')) {
+            (Some(number), _) => (number, 1),
+            (_, Some(number)) => (number, 7),
+            _ => return Err(error())
+        };
+        let number: u32 = number.trim().parse().map_err(|_| error())?;
+        match number.checked_mul(factor) {
+            Some(days) if days > 0 && days <= MAX_DAYS => Ok(Days(days)),
+            _ => Err(error())
+        }
+    }
+}
+
+/// Writes an HTML page with charts of the usage of the last days. It needs
+/// nothing but itself to be viewed, so it can be opened offline or sent on.
+pub fn report(
+    config_path: Option<PathBuf>,
+    db_path: Option<PathBuf>,
+    out: PathBuf,
+    days: Days,
+    clock: &dyn Clock
+) {
+    let db = cmd::get_current_db(db_path);
+    let config = cmd::load_optional_config(config_path, "budgets won't be shown");
+    let now = clock.now();
+    let report = collect(&*db, config.as_ref(), days, now).unwrap_or_else(|e| {
+        eprintln!("Could not read usage: {:?}", e);
+        process::exit(1)
+    });
+    if let Err(e) = fs::write(&out, html(&report, now)) {
+        eprintln!("Could not write report to {}: {}", out.display(), e);
+        process::exit(1)
+    }
+    println!("Wrote report to {}", out.display());
+}
+
+/// Everything that the report shows
+#[derive(Debug, PartialEq)]
+struct Report {
+    days: Vec<NaiveDate>,
+    /// Usage of each app on each of the days
+    apps: Vec<Series>,
+    /// Usage of all apps by weekday, from Monday, and hour of the day
+    heatmap: [[u64; 24]; 7],
+    budgets: Vec<Budget>
+}
+
+#[derive(Debug, PartialEq)]
+struct Series {
+    id: String,
+    daily: Vec<u64>
+}
+
+/// A limit on daily usage from the hooks of an app, with the usage that it
+/// limits on each of the days
+#[derive(Debug, PartialEq)]
+struct Budget {
+    /// App or group that is limited
+    subject: String,
+    limit: u64,
+    daily: Vec<u64>
+}
+
+fn collect<S: UsageStore + ?Sized>(
+    db: &S,
+    config: Option<&Config>,
+    days: Days,
+    now: DateTime<Local>
+) -> db::Result<Report> {
+    let today = now.date().naive_local();
+    // Days before the earliest date that chrono supports are left out
+    let days: Vec<NaiveDate> = (0..days.0).rev()
+        .filter_map(|back| today.checked_sub_signed(chrono::Duration::days(back.into())))
+        .collect();
+    // Start and end of each day, where today ends now
+    let bounds: Vec<(u64, u64)> = days.iter()
+        .map(|day| (
+            calendar::start_of_day(*day, &Local).timestamp() as u64,
+            calendar::start_of_day(day.succ(), &Local).min(now).timestamp() as u64
+        ))
+        .collect();
+    let daily = |members: &[&str]| bounds.iter()
+        .map(|(from, to)| db.get_total_usage(members, *from, *to))
+        .collect::<db::Result<Vec<u64>>>();
+
+    let apps = cmd::app_ids(config, db)?.into_iter()
+        .map(|id| Ok(Series { daily: daily(&[&id])?, id }))
+        .collect::<db::Result<_>>()?;
+    let heatmap = heatmap(db, bounds[0].0, bounds[bounds.len() - 1].1)?;
+
+    let mut budgets: Vec<Budget> = vec![];
+    if let Some(config) = config {
+        for app in &config.apps {
+            for (subject, limit) in app.conditions().flat_map(|c| c.daily_limits()) {
+                let (subject, members) = match subject {
+                    Subject::Current => (app.id.clone(), vec![app.id.as_str()]),
+                    Subject::App(id) => (id.clone(), vec![id.as_str()]),
+                    Subject::Group(id) => (
+                        format!("group {}", id),
+                        config.groups.get(id).into_iter().flatten().map(String::as_str).collect()
+                    )
+                };
+                let limit = limit.seconds;
+                // Hooks of several apps may share a limit, e.g. on a group
+                if !budgets.iter().any(|b| b.subject == subject && b.limit == limit) {
+                    budgets.push(Budget { subject, limit, daily: daily(&members)? });
+                }
+            }
+        }
+    }
+    Ok(Report { days, apps, heatmap, budgets })
+}
+
+/// Usage of all apps between `from` and `to`, by weekday and hour.
+/// Sessions are split at each full hour.
+fn heatmap<S: UsageStore + ?Sized>(db: &S, from: u64, to: u64) -> db::Result<[[u64; 24]; 7]> {
+    let mut cells = [[0; 24]; 7];
+    let filter = SessionFilter { app_key: None, from: Some(from), to: Some(to) };
+    for session in db.sessions(&filter)? {
+        let session = session?;
+        let mut start = session.start;
+        while start < session.end {
+            let time = Local.timestamp(start as i64, 0);
+            let next_hour = start + 3600 - u64::from(time.minute() * 60 + time.second());
+            let end = next_hour.min(session.end);
+            let weekday = time.weekday().num_days_from_monday() as usize;
+            cells[weekday][time.hour() as usize] += end - start;
+            start = end;
+        }
+    }
+    Ok(cells)
+}
+
+fn html(report: &Report, now: DateTime<Local>) -> String {
+    let labels: Vec<String> = report.days.iter()
+        .map(|day| day.format("%m-%d").to_string())
+        .collect();
+    let first = report.days[0];
+    let mut body = format!(
+        "<h1>Usage from {} to {}</h1>\n<p class=\"note\">Created {}</p>\n",
+        first.format("%Y-%m-%d"),
+        now.format("%Y-%m-%d"),
+        now.format("%Y-%m-%d %H:%M")
+    );
+
+    body.push_str("<h2>Daily usage</h2>\n");
+    let used: Vec<&Series> = report.apps.iter()
+        .filter(|app| app.daily.iter().any(|seconds| *seconds > 0))
+        .collect();
+    if used.is_empty() {
+        body.push_str("<p>No usage was recorded in these days.</p>\n");
+    }
+    for app in used {
+        body.push_str(&format!(
+            "<h3>{} <span class=\"note\">{} in total</span></h3>\n{}\n",
+            escape(&app.id),
+            format_duration(app.daily.iter().sum()),
+            svg::daily_bars(&app.daily, &labels)
+        ));
+    }
+
+    body.push_str(&format!(
+        "<h2>Usage by time of the week</h2>\n{}\n",
+        svg::heatmap(&report.heatmap)
+    ));
+
+    if !report.budgets.is_empty() {
+        body.push_str("<h2>Daily budgets</h2>\n");
+    }
+    for budget in &report.budgets {
+        let over = budget.daily.iter().filter(|seconds| **seconds >= budget.limit).count();
+        body.push_str(&format!(
+            "<h3>{} <span class=\"note\">at most {} a day, reached on {} of {} days</span></h3>\n{}\n",
+            escape(&budget.subject),
+            format_duration(budget.limit),
+            over,
+            budget.daily.len(),
+            svg::budget_lines(&budget.daily, &labels, budget.limit)
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Leakbuster usage report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+h3 {{ margin-bottom: 0.2em; }}
+.note {{ color: #777; font-size: 0.8em; font-weight: normal; }}
+</style>
+</head>
+<body>
+{}</body>
+</html>
+",
+        body
+    )
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::cmd::report::*;
+    use crate::db::memory::MemoryStore;
+
+    #[test]
+    fn ranges_are_days_or_weeks() {
+        assert_eq!(Ok(Days(30)), "30D".parse());
+        assert_eq!(Ok(Days(28)), "4 W".parse());
+        assert!("30".parse::<Days>().is_err());
+        assert!("0D".parse::<Days>().is_err());
+        assert!("1M".parse::<Days>().is_err());
+        assert!("-1D".parse::<Days>().is_err());
+        assert_eq!(Ok(Days(MAX_DAYS)), format!("{}D", MAX_DAYS).parse());
+        assert!(format!("{}D", MAX_DAYS + 1).parse::<Days>().is_err());
+        assert!("100000000D".parse::<Days>().is_err());
+        assert!("1000000000W".parse::<Days>().is_err());
+    }
+
+    #[test]
+    fn sessions_are_split_into_hours() {
+        let db = MemoryStore::new();
+        // A Monday, from 13:30 to 15:10
+        let start = Local.ymd(2021, 12, 6).and_hms(13, 30, 0).timestamp() as u64;
+        db.start_session("firefox", start, start + 6000, None).unwrap();
+        let cells = heatmap(&db, 0, start + 86400).unwrap();
+        assert_eq!(1800, cells[0][13]);
+        assert_eq!(3600, cells[0][14]);
+        assert_eq!(600, cells[0][15]);
+        assert_eq!(6000, cells.iter().flatten().sum::<u64>());
+        // Only the part within the range counts
+        let cells = heatmap(&db, start + 3600, start + 86400).unwrap();
+        assert_eq!(2400, cells.iter().flatten().sum::<u64>());
+    }
+
+    #[test]
+    fn budgets_are_taken_from_hooks() {
+        let config = Config::parse("
+apps:
+  - id: firefox
+    cmd: firefox
+    time_hooks:
+      - action: kill
+        condition: not atmost 1 h in this day
+        interval: 10 m
+  - id: discord
+    cmd: discord
+    startup_hooks:
+      - cmd: notify-send
+        condition: atmost 2 h of group:social in this day
+    shutdown_hooks:
+      - cmd: notify-send
+        condition: usage(group:social, this day) < 2 h
+groups:
+  social: [discord, element]").unwrap();
+        let now = Local.ymd(2021, 12, 8).and_hms(12, 0, 0);
+        let day = |d| Local.ymd(2021, 12, d).and_hms(10, 0, 0).timestamp() as u64;
+        let db = MemoryStore::new();
+        db.record_usage("firefox", day(7), 3600).unwrap();
+        db.record_usage("element", day(8), 1800).unwrap();
+        db.record_usage("discord", day(8), 600).unwrap();
+
+        let report = collect(&db, Some(&config), Days(2), now).unwrap();
+        assert_eq!(
+            vec![NaiveDate::from_ymd(2021, 12, 7), NaiveDate::from_ymd(2021, 12, 8)],
+            report.days
+        );
+        assert_eq!(
+            vec![
+                Series { id: "firefox".to_string(), daily: vec![3600, 0] },
+                Series { id: "discord".to_string(), daily: vec![0, 600] },
+                Series { id: "element".to_string(), daily: vec![0, 1800] }
+            ],
+            report.apps
+        );
+        assert_eq!(
+            vec![
+                Budget { subject: "firefox".to_string(), limit: 3600, daily: vec![3600, 0] },
+                Budget { subject: "group social".to_string(), limit: 7200, daily: vec![0, 2400] }
+            ],
+            report.budgets
+        );
+
+        let html = html(&report, now);
+        assert!(html.contains("<h3>firefox <span class=\"note\">at most 1h 00m a day, \
+            reached on 1 of 2 days</span></h3>"), "{}", html);
+        assert_eq!(6, html.matches("<svg").count());
+        // Nothing is loaded from elsewhere
+        for external in &["src=", "href=", "<link", "url("] {
+            assert!(!html.contains(external), "{}", external);
+        }
+    }
+
+    #[test]
+    fn ids_are_escaped() {
+        let db = MemoryStore::new();
+        let now = Local.ymd(2021, 12, 8).and_hms(12, 0, 0);
+        db.record_usage("<b>", now.timestamp() as u64, 60).unwrap();
+        let html = html(&collect(&db, None, Days(1), now).unwrap(), now);
+        assert!(html.contains("<h3>&lt;b&gt; "), "{}", html);
+        assert!(!html.contains("<b>"));
+    }
+}
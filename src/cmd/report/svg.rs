@@ -0,0 +1,185 @@
+use crate::cmd::stats::format_duration;
+
+/// Width of each day in the charts over days
+const COLUMN_WIDTH: u64 = 16;
+/// Height of the area that values are drawn in
+const PLOT_HEIGHT: u64 = 120;
+/// Space for the labels left of and below the plot
+const LEFT_MARGIN: u64 = 64;
+const TOP_MARGIN: u64 = 10;
+const BOTTOM_MARGIN: u64 = 24;
+/// Size of each cell of the heatmap
+const CELL_SIZE: u64 = 18;
+
+// Colors are set on the elements themselves, rather than with CSS, so the
+// charts look the same wherever they are embedded
+const BAR_COLOR: &str = "#4a7ab5";
+const OVER_BUDGET_COLOR: &str = "#c0392b";
+const AXIS_COLOR: &str = "#999";
+const EMPTY_COLOR: &str = "#eee";
+
+/// Escapes text for use in HTML and SVG, in content and attributes
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// A bar for the usage of each day
+pub fn daily_bars(daily: &[u64], labels: &[String]) -> String {
+    let max = daily.iter().copied().max().unwrap_or(0);
+    let mut content = String::new();
+    for (index, (value, label)) in daily.iter().zip(labels).enumerate() {
+        let height = scale(*value, max);
+        content.push_str(&format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\">\
+            <title>{}: {}</title></rect>",
+            column_x(index) + 2, TOP_MARGIN + PLOT_HEIGHT - height, COLUMN_WIDTH - 4, height,
+            BAR_COLOR, escape(label), format_duration(*value)
+        ));
+    }
+    days_frame(labels, max, &content)
+}
+
+/// A line of the usage of each day, against a dashed line of the limit.
+/// Days on which the limit was reached are marked.
+pub fn budget_lines(daily: &[u64], labels: &[String], limit: u64) -> String {
+    let max = daily.iter().copied().max().unwrap_or(0).max(limit);
+    let y = |value| TOP_MARGIN + PLOT_HEIGHT - scale(value, max);
+    let center = |index| column_x(index) + COLUMN_WIDTH / 2;
+    let points: Vec<String> = daily.iter().enumerate()
+        .map(|(index, value)| format!("{},{}", center(index), y(*value)))
+        .collect();
+    let mut content = format!(
+        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-dasharray=\"4 3\">\
+        <title>Limit: {}</title></line>\
+        <polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+        LEFT_MARGIN, y(limit), column_x(daily.len()), y(limit), OVER_BUDGET_COLOR,
+        format_duration(limit), points.join(" "), BAR_COLOR
+    );
+    for (index, (value, label)) in daily.iter().zip(labels).enumerate() {
+        // `atmost` only holds below the limit
+        let color = if *value >= limit { OVER_BUDGET_COLOR } else { BAR_COLOR };
+        content.push_str(&format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"3\" fill=\"{}\"><title>{}: {} of {}</title></circle>",
+            center(index), y(*value), color, escape(label), format_duration(*value),
+            format_duration(limit)
+        ));
+    }
+    days_frame(labels, max, &content)
+}
+
+/// Usage by weekday, from Monday, and hour of the day. Cells are darker the
+/// more an app was used then.
+pub fn heatmap(cells: &[[u64; 24]; 7]) -> String {
+    const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    let max = cells.iter().flatten().copied().max().unwrap_or(0);
+    let left = 40;
+    let top = 20;
+    let mut content = String::new();
+    for hour in (0..24).step_by(3) {
+        content.push_str(&text(left + hour * CELL_SIZE, top - 6, "start", &format!("{}:00", hour)));
+    }
+    for (weekday, row) in cells.iter().enumerate() {
+        let y = top + weekday as u64 * CELL_SIZE;
+        content.push_str(&text(left - 6, y + CELL_SIZE - 5, "end", WEEKDAYS[weekday]));
+        for (hour, value) in row.iter().enumerate() {
+            let fill = if *value == 0 {
+                format!("fill=\"{}\"", EMPTY_COLOR)
+            } else {
+                // Faint cells stay visible
+                let opacity = 0.15 + 0.85 * *value as f64 / max as f64;
+                format!("fill=\"{}\" fill-opacity=\"{:.2}\"", BAR_COLOR, opacity)
+            };
+            content.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}>\
+                <title>{} {}:00: {}</title></rect>",
+                left + hour as u64 * CELL_SIZE + 1, y + 1, CELL_SIZE - 2, CELL_SIZE - 2, fill,
+                WEEKDAYS[weekday], hour, format_duration(*value)
+            ));
+        }
+    }
+    svg(left + 24 * CELL_SIZE + 8, top + 7 * CELL_SIZE + 8, &content)
+}
+
+/// Axes for charts with a column for each day, where `max` is the value at
+/// the top
+fn days_frame(labels: &[String], max: u64, content: &str) -> String {
+    let bottom = TOP_MARGIN + PLOT_HEIGHT;
+    let right = column_x(labels.len());
+    let mut frame = format!(
+        "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"{4}\"/>\
+        <line x1=\"{0}\" y1=\"{2}\" x2=\"{3}\" y2=\"{2}\" stroke=\"{4}\"/>",
+        LEFT_MARGIN, TOP_MARGIN, bottom, right, AXIS_COLOR
+    );
+    frame.push_str(&text(LEFT_MARGIN - 6, TOP_MARGIN + 10, "end", &format_duration(max)));
+    frame.push_str(&text(LEFT_MARGIN - 6, bottom, "end", "0"));
+    // A label for every week, so they don't overlap
+    for (index, label) in labels.iter().enumerate().step_by(7) {
+        frame.push_str(&text(column_x(index), bottom + 16, "start", label));
+    }
+    frame.push_str(content);
+    svg(right + 48, bottom + BOTTOM_MARGIN, &frame)
+}
+
+fn svg(width: u64, height: u64, content: &str) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+        viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"11\">{2}</svg>",
+        width, height, content
+    )
+}
+
+fn text(x: u64, y: u64, anchor: &str, text: &str) -> String {
+    format!(
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"{}\" fill=\"#555\">{}</text>",
+        x, y, anchor, escape(text)
+    )
+}
+
+fn column_x(index: usize) -> u64 {
+    LEFT_MARGIN + index as u64 * COLUMN_WIDTH
+}
+
+/// Height of the value in the plot, where `max` fills all of it
+fn scale(value: u64, max: u64) -> u64 {
+    (value * PLOT_HEIGHT).checked_div(max).unwrap_or(0)
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::cmd::report::svg::*;
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!("a &lt;b&gt; &amp; &quot;c&quot; &#39;d&#39;", escape("a <b> & \"c\" 'd'"));
+    }
+
+    #[test]
+    fn values_are_scaled_to_the_largest() {
+        let labels = vec!["12-06".to_string(), "12-07".to_string()];
+        let chart = daily_bars(&[1800, 3600], &labels);
+        assert!(chart.contains(&format!("height=\"{}\"", PLOT_HEIGHT / 2)), "{}", chart);
+        assert!(chart.contains(&format!("height=\"{}\"", PLOT_HEIGHT)), "{}", chart);
+        assert!(chart.contains("<title>12-07: 1h 00m</title>"), "{}", chart);
+        // Nothing to scale to
+        assert!(daily_bars(&[0, 0], &labels).contains("height=\"0\""));
+    }
+
+    #[test]
+    fn days_at_the_limit_are_over_budget() {
+        let labels = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let chart = budget_lines(&[600, 3600, 7200], &labels, 3600);
+        assert_eq!(2, chart.matches(&format!("fill=\"{}\"><title>", OVER_BUDGET_COLOR)).count());
+        assert!(chart.contains("<title>Limit: 1h 00m</title>"), "{}", chart);
+    }
+}
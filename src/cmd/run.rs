@@ -1,15 +1,22 @@
-use std::cmp::Reverse;
+use std::os::unix::process::CommandExt;
 use std::process::{Command, self};
 use std::sync::Arc;
-use std::time::{SystemTime, Instant};
-use std::{thread, time};
+use std::time;
 
-use by_address::ByAddress;
 use ctrlc;
-use priority_queue::PriorityQueue;
 
-use crate::config::{App, StartupHook, TimeHook, ShutdownHook};
+use crate::clock::{Clock, SystemClock};
+use crate::config::SignalTarget;
 use crate::cmd;
+use crate::cmd::hooks::{
+    check_startup_hooks, log_finished, schedule_time_hooks, start_due_time_hooks,
+    start_shutdown_hooks, HookContext, RunningApp
+};
+use crate::process::{
+    forward_signals_to_group, own_foreground_group, set_foreground_group, Children
+};
+use crate::process::tree;
+use crate::process::tree::ProcessTree;
 
 
 use std::path::PathBuf;
@@ -18,111 +25,126 @@ pub fn run(
     config_path: Option<PathBuf>,
     db_path: Option<PathBuf>,
     app_id: &str,
-    args: &[String]
+    args: &[String],
+    clock: &dyn Clock
 ) {
     let config = Arc::new(cmd::get_config(config_path));
-    let db = cmd::get_db(db_path);
+    let db = cmd::get_db(db_path.clone());
     let app = config.get_app(app_id)
         .unwrap_or_else(|| panic!("Unknown app: {:}", app_id));
-    check_startup_hooks(&app).expect("Startup hook prevented run");
-
-    // Create shutdown handler for SIGINT
-    let local_config = config.clone();
-    let local_app_id = app_id.to_string();
-    ctrlc::set_handler(move || {
-        start_shutdown_hooks(&local_config
-            .get_app(&local_app_id)
-            .unwrap()
-            .shutdown_hooks
-        );
-        process::exit(1);
-    }).expect("Could not set shutdown hook!");
+    let ctx = HookContext {
+        db: &*db,
+        app_id,
+        groups: &config.groups,
+        calendars: &config.calendar_dates,
+        week_start: config.week_start,
+        clock
+    };
+    check_startup_hooks(app, &ctx).expect("Startup hook prevented run");
+
+    // Signalling the app's process group only reaches the app if the group
+    // isn't shared with leakbuster.
+    let own_group = app.time_hooks.iter()
+        .any(|h| h.action.is_some() && h.target == SignalTarget::Group);
+
+    // Create shutdown handler for SIGINT. The handler runs on its own thread,
+    // so it gets its own db connection. Signals arrive in real time, so it
+    // uses the system clock. If the app has a process group of its own,
+    // SIGINT doesn't reach it from the terminal, so it is forwarded to the
+    // app instead, which is tracked until it exits, as usual.
+    if !own_group {
+        let local_config = config.clone();
+        let local_app_id = app_id.to_string();
+        let local_db = cmd::get_db(db_path);
+        ctrlc::set_handler(move || {
+            let ctx = HookContext {
+                db: &*local_db,
+                app_id: &local_app_id,
+                groups: &local_config.groups,
+                calendars: &local_config.calendar_dates,
+                week_start: local_config.week_start,
+                clock: &SystemClock
+            };
+            let mut children = Children::new();
+            start_shutdown_hooks(
+                &local_config
+                    .get_app(&local_app_id)
+                    .unwrap()
+                    .shutdown_hooks,
+                &ctx,
+                &mut children
+            );
+            log_finished(children.wait_all());
+            process::exit(1);
+        }).expect("Could not set shutdown hook!");
+    }
 
     // Calculate for each startup hook when it needs to be run
-    let mut time_hook_schedule = schedule_time_hooks(&app.time_hooks);
-
-    // Start the app
-    let mut app_cmd = Command::new(&app.cmd)
-        .args(app.args.iter().chain(args.iter()))
-        .spawn()
-        .expect("Failed to execute command");
-
-    // Loop every second while the app is running:
+    let mut time_hook_schedule = schedule_time_hooks(&app.time_hooks, clock.instant());
+
+    // Start the app. In its own process group, it only gets input from the
+    // terminal if it's in the foreground. It takes the terminal before
+    // running, so that it can't read from it too early.
+    let mut command = Command::new(&app.cmd);
+    command.args(app.args.iter().chain(args.iter()));
+    let terminal = if own_group { own_foreground_group() } else { None };
+    if own_group {
+        command.process_group(0);
+    }
+    if terminal.is_some() {
+        // Safe, since it only calls functions that are allowed after fork
+        unsafe { command.pre_exec(|| set_foreground_group(process::id())) };
+    }
+    tree::become_subreaper().unwrap_or_else(|e| eprintln!(
+        "Could not become subreaper, processes that outlive their parent won't be tracked: {:?}", e
+    ));
+    let mut app_cmd = command.spawn().expect("Failed to execute command");
+    if own_group {
+        forward_signals_to_group(app_cmd.id()).unwrap_or_else(|e| eprintln!(
+            "Could not forward signals to the app: {:?}", e
+        ));
+    }
+    let session = tree::session_of(process::id()).expect("Could not get session id");
+    let mut running_app = RunningApp::new(
+        &app.id,
+        ProcessTree::new(app_cmd.id(), session, app.count_daemons),
+        if own_group { Some(app_cmd.id()) } else { None }
+    );
+    // Time and shutdown hooks, which run in the background
+    let mut children = Children::new();
+
+    // Loop every second while any process of the app is running:
     let delay = time::Duration::from_secs(1);
-    while app_cmd.try_wait().unwrap().is_none() {
-        thread::sleep(delay);
+    while running_app.update(&mut app_cmd) {
+        clock.sleep(delay);
+        running_app.kill_if_grace_period_over(clock.instant());
 
-        // Log usage in db
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .expect("Could not obtain timestamp");
-        db.record_usage(&app.id, timestamp, delay.as_secs())
+        // Log usage in db, unless the app was stopped by a hook
+        running_app.record_usage(&*db, clock.timestamp(), delay.as_secs())
             .unwrap_or_else(
-                |e| println!("Failed to record usage: {:?}", e)
+                |e| eprintln!("Failed to record usage, will retry: {:?}", e)
             );
 
         // Run time hooks that are due
-        let now = Instant::now();
-        while let Some(hook) = time_hook_schedule.peek()
-            .and_then(|(hook_by_addr, prio)| {
-                if prio.0 > now {
-                    None
-                } else {
-                    Some(hook_by_addr.0)
-                }
-        }) {
-            start_time_hook(hook);
-            if hook.interval.seconds > 0 {
-                time_hook_schedule.push_decrease(
-                    ByAddress(hook),
-                    Reverse(now + hook.interval.into())
-                );
-            } else {
-                time_hook_schedule.pop();
-            }
-        }
-    }
-
-    // Run shutdown hook once app terminates correctly
-    start_shutdown_hooks(&app.shutdown_hooks);
-}
-
-fn start_time_hook(hook: &TimeHook) {
-    Command::new(&hook.cmd)
-        .args(&hook.args)
-        .spawn()
-        .expect("Couldn't start time hook!");
-}
-
-fn start_shutdown_hooks(hooks: &[ShutdownHook]) {
-    for hook in hooks {
-        Command::new(&hook.cmd)
-            .args(&hook.args)
-            .spawn()
-            .expect("Failed to start shutdown hook");
+        start_due_time_hooks(
+            &mut time_hook_schedule,
+            &ctx,
+            &mut running_app,
+            &mut children
+        );
+        log_finished(children.reap());
     }
-}
 
-fn schedule_time_hooks<'a>(time_hooks: &'a [TimeHook]) -> PriorityQueue<ByAddress<&'a TimeHook>, Reverse<Instant>> {
-    let now = Instant::now();
-    let mut q: PriorityQueue<ByAddress<&'a TimeHook>, Reverse<Instant>> = PriorityQueue::new();
-    for time_hook in time_hooks {
-        q.push(ByAddress(time_hook), Reverse(now + time_hook.initial_delay.into()));
+    if let Some(pgid) = terminal {
+        set_foreground_group(pgid).unwrap_or_else(|e| eprintln!(
+            "Could not take back the terminal: {:?}", e
+        ));
     }
-    q
-}
+    time_hook_schedule.abandon_condition_cmds(&mut children);
+    running_app.finish_termination(clock);
+    running_app.finish_recording(&*db, clock);
 
-fn check_startup_hooks(app: &App) -> Result<(), &StartupHook> {
-    for hook in &app.startup_hooks {
-        let status = Command::new(&hook.cmd)
-            .args(&hook.args)
-            .status()
-            .unwrap_or_else(|_| panic!("Failed to run startup hook: {:?}", hook));
-        if !status.success() {
-            return Err(&hook)
-        }
-    }
-    Ok(())
+    // Run shutdown hook once app terminates correctly
+    start_shutdown_hooks(&app.shutdown_hooks, &ctx, &mut children);
+    log_finished(children.wait_all());
 }
-
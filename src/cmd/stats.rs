@@ -0,0 +1,333 @@
+use chrono::prelude::*;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::calendar;
+use crate::calendar::WeekStart;
+use crate::clock::Clock;
+use crate::cmd;
+use crate::config::Config;
+use crate::db;
+use crate::db::{UsageStats, UsageStore};
+use crate::expressions::parser::TimeUnit;
+
+/// Period of the calendar, up to now
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Today,
+    Week,
+    Month
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Period, String> {
+        match s {
+            "today" => Ok(Period::Today),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            other => Err(format!("Unknown period `{}`, expected today, week or month", other))
+        }
+    }
+}
+
+/// Time range to show usage for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Range {
+    Period(Period),
+    /// From the start of `from` to the end of `to`, or until now
+    Dates { from: NaiveDate, to: Option<NaiveDate> }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            other => Err(format!("Unknown format `{}`, expected table or json", other))
+        }
+    }
+}
+
+pub fn stats(
+    config_path: Option<PathBuf>,
+    db_path: Option<PathBuf>,
+    range: Range,
+    format: Format,
+    clock: &dyn Clock
+) {
+    let db = cmd::get_current_db(db_path);
+    let config = cmd::load_optional_config(config_path, "groups won't be shown");
+    let week_start = config.as_ref().map(|config| config.week_start).unwrap_or_default();
+    let (from, to) = bounds(range, clock.now(), week_start);
+    if to < from {
+        eprintln!("The range ends before it starts");
+        std::process::exit(2)
+    }
+    let report = report(&*db, config.as_ref(), &from, &to).unwrap_or_else(|e| {
+        eprintln!("Could not read usage: {:?}", e);
+        std::process::exit(1)
+    });
+    match format {
+        Format::Table => print!("{}", table(&report)),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("Could not serialize stats")
+        )
+    }
+}
+
+/// Usage of all apps and groups in a time range
+#[derive(Debug, PartialEq, Serialize)]
+struct Report {
+    /// Timestamps of the start and end of the range
+    from: u64,
+    to: u64,
+    /// Number of calendar days that the range touches
+    days: u64,
+    apps: Vec<Row>,
+    groups: Vec<Row>
+}
+
+/// Usage of one app or group. All durations are in seconds.
+#[derive(Debug, PartialEq, Serialize)]
+struct Row {
+    id: String,
+    total: u64,
+    sessions: u64,
+    longest_session: u64,
+    average_daily: u64
+}
+
+/// Start and end of the range, where `now` is the latest possible end
+fn bounds(range: Range, now: DateTime<Local>, week_start: WeekStart)
+    -> (DateTime<Local>, DateTime<Local>) {
+    let from = match range {
+        Range::Period(Period::Today) => calendar::start_of(&now, TimeUnit::Day, week_start),
+        Range::Period(Period::Week) => calendar::start_of(&now, TimeUnit::Week, week_start),
+        Range::Period(Period::Month) => calendar::start_of(&now, TimeUnit::Month, week_start),
+        Range::Dates { from, .. } => calendar::start_of_day(from, &Local)
+    };
+    let to = match range {
+        Range::Dates { to: Some(to), .. } => calendar::start_of_day(to.succ(), &Local).min(now),
+        _ => now
+    };
+    (from, to)
+}
+
+/// Number of calendar days between `from` and `to`, counting the ones that
+/// are only partly covered
+fn days_between(from: &DateTime<Local>, to: &DateTime<Local>) -> u64 {
+    let last = if to > from { *to - chrono::Duration::seconds(1) } else { *from };
+    (last.date().naive_local() - from.date().naive_local()).num_days() as u64 + 1
+}
+
+fn report<S: UsageStore + ?Sized>(
+    db: &S,
+    config: Option<&Config>,
+    from: &DateTime<Local>,
+    to: &DateTime<Local>
+) -> db::Result<Report> {
+    let (from_ts, to_ts) = (from.timestamp() as u64, to.timestamp() as u64);
+    let days = days_between(from, to);
+    let row = |id: &str, members: &[&str]| -> db::Result<Row> {
+        let UsageStats { total, sessions, longest_session } =
+            db.get_usage_stats(members, from_ts, to_ts)?;
+        Ok(Row {
+            id: id.to_string(),
+            total,
+            sessions,
+            longest_session,
+            average_daily: total / days
+        })
+    };
+
+    let apps = cmd::app_ids(config, db)?.iter()
+        .map(|id| row(id, &[id]))
+        .collect::<db::Result<_>>()?;
+    let groups = config.map(|config| &config.groups).into_iter().flatten()
+        .map(|(id, members)| {
+            let members: Vec<&str> = members.iter().map(String::as_str).collect();
+            row(id, &members)
+        })
+        .collect::<db::Result<_>>()?;
+    Ok(Report { from: from_ts, to: to_ts, days, apps, groups })
+}
+
+fn table(report: &Report) -> String {
+    let from = Local.timestamp(report.from as i64, 0);
+    let to = Local.timestamp(report.to as i64, 0);
+    let mut out = format!(
+        "Usage from {} to {} ({} day{})\n",
+        from.format("%Y-%m-%d %H:%M"),
+        to.format("%Y-%m-%d %H:%M"),
+        report.days,
+        if report.days == 1 { "" } else { "s" }
+    );
+    for (title, rows) in [("App", &report.apps), ("Group", &report.groups)] {
+        if !rows.is_empty() {
+            out.push('\n');
+            out.push_str(&rows_table(title, rows));
+        }
+    }
+    out
+}
+
+/// Aligns the rows under a header. Ids are aligned left, everything else right.
+fn rows_table(title: &str, rows: &[Row]) -> String {
+    let header = [title, "Total", "Sessions", "Longest", "Avg/day"];
+    let cells: Vec<[String; 5]> = rows.iter()
+        .map(|row| [
+            row.id.clone(),
+            format_duration(row.total),
+            row.sessions.to_string(),
+            format_duration(row.longest_session),
+            format_duration(row.average_daily)
+        ])
+        .collect();
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    let lines = std::iter::once(header.map(str::to_string)).chain(cells);
+    for line in lines {
+        let mut columns = vec![format!("{:<1$}", line[0], widths[0])];
+        for (cell, width) in line.iter().zip(&widths).skip(1) {
+            columns.push(format!("{:>1$}", cell, width));
+        }
+        out.push_str(columns.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Hours and minutes, or minutes and seconds for less than an hour
+pub(crate) fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::cmd::stats::*;
+    use crate::db::memory::MemoryStore;
+
+    #[test]
+    fn ranges_end_now() {
+        // A Wednesday
+        let now = Local.ymd(2021, 12, 8).and_hms(14, 23, 10);
+        let day = |d| Local.ymd(2021, 12, d).and_hms(0, 0, 0);
+        let bounds = |range| bounds(range, now, WeekStart::Monday);
+        assert_eq!((day(8), now), bounds(Range::Period(Period::Today)));
+        assert_eq!((day(6), now), bounds(Range::Period(Period::Week)));
+        assert_eq!((day(1), now), bounds(Range::Period(Period::Month)));
+        let from = NaiveDate::from_ymd(2021, 12, 2);
+        assert_eq!((day(2), now), bounds(Range::Dates { from, to: None }));
+        let to = Some(NaiveDate::from_ymd(2021, 12, 4));
+        assert_eq!((day(2), day(5)), bounds(Range::Dates { from, to }));
+        let to = Some(NaiveDate::from_ymd(2021, 12, 24));
+        assert_eq!((day(2), now), bounds(Range::Dates { from, to }));
+        assert_eq!(
+            (day(5), now),
+            super::bounds(Range::Period(Period::Week), now, WeekStart::Sunday)
+        );
+    }
+
+    #[test]
+    fn partial_days_count() {
+        let at = |d, h| Local.ymd(2021, 12, d).and_hms(h, 0, 0);
+        assert_eq!(1, days_between(&at(8, 0), &at(8, 0)));
+        assert_eq!(1, days_between(&at(8, 0), &at(8, 14)));
+        assert_eq!(1, days_between(&at(8, 0), &at(9, 0)));
+        assert_eq!(3, days_between(&at(6, 0), &at(8, 14)));
+        assert_eq!(2, days_between(&at(6, 23), &at(7, 1)));
+    }
+
+    #[test]
+    fn durations_are_formatted_by_size() {
+        assert_eq!("0s", format_duration(0));
+        assert_eq!("59s", format_duration(59));
+        assert_eq!("1m 05s", format_duration(65));
+        assert_eq!("1h 00m", format_duration(3600));
+        assert_eq!("27h 46m", format_duration(100_000));
+    }
+
+    #[test]
+    fn reports_apps_and_groups() {
+        let config = Config::parse("
+apps:
+  - id: firefox
+    cmd: firefox
+  - id: discord
+    cmd: discord
+groups:
+  social: [discord, element]").unwrap();
+        let from = Local.ymd(2021, 12, 6).and_hms(0, 0, 0);
+        let to = Local.ymd(2021, 12, 8).and_hms(0, 0, 0);
+        let ts = |hours: u64| from.timestamp() as u64 + hours * 3600;
+        let db = MemoryStore::new();
+        db.record_usage("discord", ts(2), 3600).unwrap();
+        db.record_usage("element", ts(26), 7200).unwrap();
+        db.record_usage("element", ts(30), 1800).unwrap();
+        // Before the range
+        db.record_usage("element", ts(0) - 1, 600).unwrap();
+
+        let report = report(&db, Some(&config), &from, &to).unwrap();
+        let row = |id: &str, total, sessions, longest_session| Row {
+            id: id.to_string(),
+            total,
+            sessions,
+            longest_session,
+            average_daily: total / 2
+        };
+        assert_eq!(
+            Report {
+                from: ts(0),
+                to: ts(48),
+                days: 2,
+                apps: vec![
+                    row("firefox", 0, 0, 0),
+                    row("discord", 3600, 1, 3600),
+                    row("element", 9000, 2, 7200)
+                ],
+                groups: vec![row("social", 12600, 3, 7200)]
+            },
+            report
+        );
+        assert_eq!(
+            "Usage from 2021-12-06 00:00 to 2021-12-08 00:00 (2 days)
+
+App       Total  Sessions  Longest  Avg/day
+firefox      0s         0       0s       0s
+discord  1h 00m         1   1h 00m  30m 00s
+element  2h 30m         2   2h 00m   1h 15m
+
+Group    Total  Sessions  Longest  Avg/day
+social  3h 30m         3   2h 00m   1h 45m
+",
+            table(&report)
+        );
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(12600, json["groups"][0]["total"]);
+        assert_eq!("element", json["apps"][2]["id"]);
+    }
+}
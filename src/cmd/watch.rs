@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time;
+
+use crate::clock::Clock;
+use crate::cmd;
+use crate::cmd::hooks::{
+    log_finished, schedule_time_hooks, start_due_time_hooks, start_shutdown_hooks, HookContext,
+    RunningApp, TimeHookSchedule
+};
+use crate::config::{App, Config};
+use crate::db::UsageStore;
+use crate::process::Children;
+use crate::process::scanner::{ProcScanner, ProcessInfo, Scanner};
+use crate::process::tree::ProcessTree;
+
+/// Linux truncates process names to this many bytes
+const COMM_LEN: usize = 15;
+
+pub fn watch(config_path: Option<PathBuf>, db_path: Option<PathBuf>, clock: &dyn Clock) {
+    let config = cmd::get_config(config_path);
+    let db = cmd::get_db(db_path);
+    let mut watcher = Watcher::new(&config, ProcScanner);
+
+    let delay = time::Duration::from_secs(1);
+    loop {
+        watcher.tick(&*db, clock, delay.as_secs());
+        clock.sleep(delay);
+    }
+}
+
+/// Follows the apps in the config that are running, without having been
+/// started by leakbuster.
+struct Watcher<'a, S: Scanner> {
+    config: &'a Config,
+    scanner: S,
+    /// Executable of leakbuster. Apps started by `leakbuster run` are
+    /// already tracked by it, so they are left alone.
+    leakbuster_exe: Option<PathBuf>,
+    /// Running apps, by index in the config
+    running: BTreeMap<usize, WatchedApp<'a>>,
+    /// Time and shutdown hooks, which run in the background
+    children: Children
+}
+
+struct WatchedApp<'a> {
+    app: RunningApp,
+    time_hook_schedule: TimeHookSchedule<'a>
+}
+
+impl<'a, S: Scanner> Watcher<'a, S> {
+    fn new(config: &'a Config, scanner: S) -> Watcher<'a, S> {
+        Watcher {
+            config,
+            scanner,
+            leakbuster_exe: env::current_exe().ok(),
+            running: BTreeMap::new(),
+            children: Children::new()
+        }
+    }
+
+    /// Scans the processes once. Apps that are running are charged
+    /// `duration` seconds of usage up to the current time, and their time
+    /// hooks are run if they are due.
+    fn tick(&mut self, db: &dyn UsageStore, clock: &dyn Clock, duration: u64) {
+        let now = clock.instant();
+        let timestamp = clock.timestamp();
+        let processes = match self.scanner.scan() {
+            Ok(processes) => processes,
+            Err(e) => {
+                eprintln!("Could not scan processes: {:?}", e);
+                return;
+            }
+        };
+        let by_pid: BTreeMap<u32, &ProcessInfo> = processes.iter()
+            .map(|p| (p.pid, p))
+            .collect();
+        for (index, app) in self.config.apps.iter().enumerate() {
+            let ctx = HookContext {
+                db,
+                app_id: &app.id,
+                groups: &self.config.groups,
+                calendars: &self.config.calendar_dates,
+                week_start: self.config.week_start,
+                clock
+            };
+            let matches: Vec<&ProcessInfo> = processes.iter()
+                .filter(|p| !p.zombie && matches(app, p))
+                .filter(|p| !self.started_by_leakbuster(p, &by_pid))
+                .collect();
+            let pids: Vec<u32> = matches.iter().map(|p| p.pid).collect();
+            let still_running = match self.running.get_mut(&index) {
+                Some(watched) => watched.app.update_watched(&processes, &pids),
+                None => false
+            };
+            if !still_running {
+                if let Some(mut watched) = self.running.remove(&index) {
+                    eprintln!("App '{}' exited", app.id);
+                    watched.time_hook_schedule.abandon_condition_cmds(&mut self.children);
+                    watched.app.finish_recording(db, clock);
+                    start_shutdown_hooks(&app.shutdown_hooks, &ctx, &mut self.children);
+                }
+                if let Some(first) = matches.first() {
+                    eprintln!("App '{}' is running", app.id);
+                    let mut running_app = RunningApp::new(
+                        &app.id,
+                        ProcessTree::new(first.pid, first.session, app.count_daemons),
+                        None
+                    );
+                    running_app.update_watched(&processes, &pids);
+                    self.running.insert(index, WatchedApp {
+                        app: running_app,
+                        time_hook_schedule: schedule_time_hooks(&app.time_hooks, now)
+                    });
+                }
+            }
+
+            if let Some(watched) = self.running.get_mut(&index) {
+                watched.app.kill_if_grace_period_over(now);
+                // Log usage in db, unless the app was stopped by a hook
+                watched.app.record_usage(db, timestamp, duration)
+                    .unwrap_or_else(
+                        |e| eprintln!("Failed to record usage, will retry: {:?}", e)
+                    );
+                start_due_time_hooks(
+                    &mut watched.time_hook_schedule,
+                    &ctx,
+                    &mut watched.app,
+                    &mut self.children
+                );
+            }
+        }
+        log_finished(self.children.reap());
+    }
+
+    /// Whether the process descends from leakbuster, e.g. because it was
+    /// started by `leakbuster run`, or is one of leakbuster's hooks
+    fn started_by_leakbuster(&self, process: &ProcessInfo, by_pid: &BTreeMap<u32, &ProcessInfo>)
+        -> bool {
+        let leakbuster_exe = match &self.leakbuster_exe {
+            Some(exe) => exe,
+            None => return false
+        };
+        let mut ancestor = by_pid.get(&process.ppid);
+        // Bounded, in case the snapshot contains a cycle because pids were
+        // reused while it was taken
+        for _ in 0..by_pid.len() {
+            match ancestor {
+                Some(p) if p.exe.as_deref() == Some(leakbuster_exe) => return true,
+                Some(p) => ancestor = by_pid.get(&p.ppid),
+                None => return false
+            }
+        }
+        false
+    }
+}
+
+/// Whether the process is an instance of the app, see `config::Watch`
+fn matches(app: &App, process: &ProcessInfo) -> bool {
+    let watch = &app.watch;
+    if watch.is_empty() {
+        return Path::new(&app.cmd).file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| truncate(name, COMM_LEN) == process.comm)
+            .unwrap_or(false);
+    }
+    let exe_matches = watch.exe.as_ref()
+        .map(|exe| process.exe.as_ref() == Some(exe))
+        .unwrap_or(true);
+    let comm_matches = watch.comm.as_ref()
+        .map(|comm| truncate(comm, COMM_LEN) == process.comm)
+        .unwrap_or(true);
+    let cmdline_matches = watch.cmdline.as_ref()
+        .map(|pattern| pattern.is_match(&process.cmdline.join(" ")))
+        .unwrap_or(true);
+    exe_matches && comm_matches && cmdline_matches
+}
+
+/// The longest prefix of `s` with at most `len` bytes that doesn't split a
+/// character
+fn truncate(s: &str, len: usize) -> &str {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::clock::SimulatedClock;
+    use crate::cmd::watch::*;
+    use crate::db;
+    use crate::db::memory::MemoryStore;
+    use std::cell::RefCell;
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+    use std::rc::Rc;
+    use tempfile::TempDir;
+
+    /// A process table that the test can change between scans
+    #[derive(Clone, Default)]
+    struct FakeScanner(Rc<RefCell<Vec<ProcessInfo>>>);
+
+    impl FakeScanner {
+        fn set(&self, processes: Vec<ProcessInfo>) {
+            *self.0.borrow_mut() = processes;
+        }
+    }
+
+    impl Scanner for FakeScanner {
+        fn scan(&mut self) -> io::Result<Vec<ProcessInfo>> {
+            Ok(self.0.borrow().clone())
+        }
+    }
+
+    const LEAKBUSTER: &str = "/usr/bin/leakbuster";
+
+    #[test]
+    fn matches_by_comm_exe_and_cmdline() {
+        let config = Config::parse("
+apps:
+  - id: firefox
+    cmd: /usr/bin/firefox
+  - id: work-browser
+    cmd: firefox
+    watch:
+      exe: /usr/lib/firefox/firefox
+      cmdline: -P work( |$)
+  - id: long-name
+    cmd: signal-desktop-beta").unwrap();
+        let firefox = process(1, 0, "firefox", "/usr/lib/firefox/firefox", &["firefox"]);
+        let work = process(2, 0, "firefox", "/usr/lib/firefox/firefox", &["firefox", "-P", "work"]);
+        let workshop = process(3, 0, "firefox", "/usr/lib/firefox/firefox", &["firefox", "-P", "workshop"]);
+        let signal = process(4, 0, "signal-desktop-", "/opt/Signal/signal-desktop-beta", &[]);
+        let matching = |app: &App| -> Vec<u32> {
+            [&firefox, &work, &workshop, &signal].iter()
+                .filter(|p| matches(app, p))
+                .map(|p| p.pid)
+                .collect()
+        };
+        assert_eq!(vec![1, 2, 3], matching(&config.apps[0]));
+        assert_eq!(vec![2], matching(&config.apps[1]));
+        assert_eq!(vec![4], matching(&config.apps[2]));
+    }
+
+    #[test]
+    fn records_usage_while_app_runs() {
+        let dir = TempDir::new().unwrap();
+        let marker = dir.path().join("shut-down");
+        let config = Config::parse(&format!("
+apps:
+  - id: firefox
+    cmd: firefox
+    shutdown_hooks:
+      - cmd: touch
+        args: [{:?}]", marker)).unwrap();
+        let db = MemoryStore::new();
+        let scanner = FakeScanner::default();
+        let mut watcher = watcher(&config, &scanner);
+        let clock = SimulatedClock::at_timestamp(100);
+        let second = time::Duration::from_secs(1);
+
+        watcher.tick(&db, &clock, 1);
+        assert!(watcher.running.is_empty());
+
+        scanner.set(vec![process(10, 1, "firefox", "/usr/bin/firefox", &[])]);
+        clock.advance(second);
+        watcher.tick(&db, &clock, 1);
+        scanner.set(vec![
+            process(10, 1, "firefox", "/usr/bin/firefox", &[]),
+            process(11, 10, "Web Content", "/usr/bin/firefox", &[]),
+        ]);
+        clock.advance(second);
+        watcher.tick(&db, &clock, 1);
+        // The launcher exits, but its child lives on
+        scanner.set(vec![process(11, 1, "Web Content", "/usr/bin/firefox", &[])]);
+        clock.advance(second);
+        watcher.tick(&db, &clock, 1);
+        assert!(!marker.exists());
+
+        scanner.set(vec![]);
+        clock.advance(second);
+        watcher.tick(&db, &clock, 1);
+        assert!(watcher.running.is_empty());
+        assert_eq!(3, db.get_usage("firefox", 0, 200).unwrap());
+        watcher.children.wait_all();
+        assert!(marker.exists(), "Shutdown hook should have run");
+    }
+
+    #[test]
+    fn ignores_apps_started_by_leakbuster() {
+        let config = Config::parse("
+apps:
+  - id: firefox
+    cmd: firefox").unwrap();
+        let db = MemoryStore::new();
+        let scanner = FakeScanner::default();
+        let mut watcher = watcher(&config, &scanner);
+        scanner.set(vec![
+            process(10, 1, "leakbuster", LEAKBUSTER, &["leakbuster", "run", "firefox"]),
+            process(11, 10, "sh", "/bin/sh", &[]),
+            process(12, 11, "firefox", "/usr/bin/firefox", &[]),
+        ]);
+        watcher.tick(&db, &SimulatedClock::at_timestamp(100), 1);
+        assert!(watcher.running.is_empty());
+        assert_eq!(0, db.get_usage("firefox", 0, 200).unwrap());
+    }
+
+    #[test]
+    fn runs_time_hooks_of_running_apps() {
+        let config = Config::parse("
+apps:
+  - id: sleep
+    cmd: sleep
+    time_hooks:
+      - action: kill
+        initial_delay: 10 s").unwrap();
+        let db = MemoryStore::new();
+        let scanner = FakeScanner::default();
+        let mut watcher = watcher(&config, &scanner);
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        scanner.set(vec![process(child.id(), 1, "sleep", "/bin/sleep", &[])]);
+
+        let clock = SimulatedClock::at_timestamp(100);
+        watcher.tick(&db, &clock, 1);
+        assert!(child.try_wait().unwrap().is_none(), "Hook isn't due yet");
+        clock.advance(time::Duration::from_secs(10));
+        watcher.tick(&db, &clock, 1);
+        assert_eq!(Some(libc::SIGKILL), child.wait().unwrap().signal());
+    }
+
+    fn watcher<'a>(config: &'a Config, scanner: &FakeScanner) -> Watcher<'a, FakeScanner> {
+        let mut watcher = Watcher::new(config, scanner.clone());
+        watcher.leakbuster_exe = Some(PathBuf::from(LEAKBUSTER));
+        watcher
+    }
+
+    fn process(pid: u32, ppid: u32, comm: &str, exe: &str, cmdline: &[&str]) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            ppid,
+            comm: comm.to_string(),
+            exe: Some(PathBuf::from(exe)),
+            cmdline: cmdline.iter().map(|arg| arg.to_string()).collect(),
+            ..ProcessInfo::default()
+        }
+    }
+}
@@ -1,33 +1,226 @@
-use crate::expressions::parser::{Condition, Duration};
+mod locate;
 
+use crate::calendar;
+use crate::calendar::WeekStart;
+use crate::expressions::{Calendars, Groups};
+use crate::expressions::parser::{
+    caret_snippet, parse_condition, parse_duration, Condition, Duration, ParseError
+};
+
+use regex::Regex;
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::result;
 
-#[derive(Deserialize, Debug)]
+/// Fields that hold expressions in the condition language, and must be
+/// parsed as a Condition
+const CONDITION_FIELDS: &[&str] = &["condition"];
+/// Fields that hold expressions of type Duration
+const DURATION_FIELDS: &[&str] = &[
+    "interval", "initial_delay", "grace_period", "timeout", "condition_timeout"
+];
+/// Names of the objects in the config and their fields, keyed by the path
+/// of the object in the file, as reported by `locate::find_keys`. Only used
+/// to describe unknown fields and suggest the field that was meant. Which
+/// fields are unknown is decided by serde, so a field that is missing here
+/// is still accepted.
+const KNOWN_FIELDS: &[(&[&str], &str, &[&str])] = &[
+    (&[], "Root", &["apps", "groups", "calendars", "week_start"]),
+    (
+        &["apps"],
+        "App",
+        &[
+            "id", "cmd", "args", "count_daemons", "watch", "startup_hooks", "time_hooks",
+            "shutdown_hooks"
+        ]
+    ),
+    (&["apps", "watch"], "Watch", &["exe", "comm", "cmdline"]),
+    (&["apps", "startup_hooks"], "StartupHook", &["cmd", "args", "timeout", "condition"]),
+    (
+        &["apps", "time_hooks"],
+        "TimeHook",
+        &[
+            "cmd", "args", "timeout", "action", "target", "grace_period", "condition_cmd",
+            "condition_args", "condition_timeout", "condition", "interval", "initial_delay"
+        ]
+    ),
+    (&["apps", "shutdown_hooks"], "ShutdownHook", &["cmd", "args", "timeout", "condition"]),
+];
+
+#[derive(Deserialize, PartialEq, Debug)]
 pub struct Config {
-    pub apps: Vec<App>
+    pub apps: Vec<App>,
+    #[serde(default)]
+    pub groups: Groups,
+    /// Files with the days of each calendar, for `on calendar`. Relative
+    /// paths are relative to the directory of the config file.
+    #[serde(default)]
+    pub calendars: BTreeMap<String, PathBuf>,
+    /// Day that `this week` starts on
+    #[serde(default)]
+    pub week_start: WeekStart,
+    /// Days of each calendar, read from `calendars` by `Config::load`
+    #[serde(skip)]
+    pub calendar_dates: Calendars,
+    /// Why calendars that `Config::load` couldn't read were left out.
+    /// Conditions that refer to them fail to evaluate.
+    #[serde(skip)]
+    pub calendar_errors: Vec<String>
 }
 
 impl Config {
     pub fn load<P: AsRef<Path>>(config_path: P) -> Result<Config> {
+        let config_path = config_path.as_ref();
         let config_str = fs::read_to_string(config_path)?;
-        let config = serde_yaml::from_str(&config_str)?;
+        let mut config = Config::parse(&config_str)?;
+        let dir = config_path.parent().unwrap_or_else(|| Path::new(""));
+        let (calendar_dates, errors) = config.load_calendars(dir);
+        config.calendar_dates = calendar_dates;
+        config.calendar_errors = errors.iter().map(|e| e.to_string()).collect();
         Ok(config)
     }
 
+    /// Parses the contents of a config file
+    pub fn parse(config_str: &str) -> Result<Config> {
+        match serde_yaml::from_str::<Config>(config_str) {
+            Ok(config) => match config.errors().into_iter().next() {
+                Some(error) => Err(Error::InvalidConfig(error)),
+                None => Ok(config)
+            },
+            Err(e) => {
+                // serde only knows that some field is wrong, so find the
+                // broken expression again to report its exact position
+                match expression_errors(config_str).into_iter().next() {
+                    Some(expression_error) => Err(Error::ExpressionError(Box::new(expression_error))),
+                    None => Err(Error::DeserializationError(e))
+                }
+            }
+        }
+    }
+
+    /// Reads the files of all calendars, with relative paths resolved
+    /// against `dir`. Calendars that can't be read are left out, and
+    /// reported in the errors instead.
+    pub fn load_calendars(&self, dir: &Path) -> (Calendars, Vec<CalendarError>) {
+        let mut calendars = Calendars::new();
+        let mut errors = vec![];
+        for (name, path) in &self.calendars {
+            match calendar::file::load(&dir.join(path)) {
+                Ok(dates) => {
+                    calendars.insert(name.clone(), dates);
+                },
+                Err(error) => errors.push(CalendarError {
+                    name: name.clone(),
+                    path: path.clone(),
+                    error
+                })
+            }
+        }
+        (calendars, errors)
+    }
+
     pub fn get_app<'a>(&'a self, app_id: &str) -> Option<&'a App> {
         self.apps.iter().find(|app| app.id == app_id)
     }
+
+    /// Problems that serde can't detect, and that make the config unusable
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for app in &self.apps {
+            for hook in &app.time_hooks {
+                match (&hook.cmd, &hook.action) {
+                    (None, None) => errors.push(format!(
+                        "Time hook of app '{}' needs either `cmd` or `action`", app.id
+                    )),
+                    (Some(_), Some(_)) => errors.push(format!(
+                        "Time hook of app '{}' can't have both `cmd` and `action`", app.id
+                    )),
+                    _ => {}
+                }
+            }
+        }
+        errors
+    }
+
+    /// Problems with the config that don't prevent it from being used, but
+    /// are likely mistakes. Meant to be shown to the user.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = self.calendar_warnings();
+        for (group_id, members) in &self.groups {
+            for app_id in members {
+                if self.get_app(app_id).is_none() {
+                    warnings.push(format!(
+                        "Group '{}' contains app '{}', which is not defined in config",
+                        group_id, app_id
+                    ));
+                }
+            }
+        }
+        for app in &self.apps {
+            for condition in app.conditions() {
+                warnings.extend(self.unknown_app_warnings(
+                    condition,
+                    &format!("Condition of app '{}'", app.id)
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Warnings about the calendars that `Config::load` couldn't read
+    pub fn calendar_warnings(&self) -> Vec<String> {
+        self.calendar_errors.iter()
+            .map(|error| format!("{}, conditions that refer to it can't be evaluated", error))
+            .collect()
+    }
+
+    /// Warnings about apps, groups and calendars that are referenced in the condition,
+    /// but not defined in this config. `context` describes the condition to
+    /// the user.
+    pub fn unknown_app_warnings(&self, condition: &Condition, context: &str)
+        -> Vec<String> {
+        let unknown_apps = condition.referenced_apps()
+            .into_iter()
+            .filter(|app_id| self.get_app(app_id).is_none())
+            .map(|app_id| format!(
+                "{} refers to app '{}', which is not defined in config",
+                context, app_id
+            ));
+        let unknown_groups = condition.referenced_groups()
+            .into_iter()
+            .filter(|group_id| !self.groups.contains_key(*group_id))
+            .map(|group_id| format!(
+                "{} refers to group '{}', which is not defined in config",
+                context, group_id
+            ));
+        let unknown_calendars = condition.referenced_calendars()
+            .into_iter()
+            .filter(|name| !self.calendars.contains_key(*name))
+            .map(|name| format!(
+                "{} refers to calendar '{}', which is not defined in config",
+                context, name
+            ));
+        unknown_apps.chain(unknown_groups).chain(unknown_calendars).collect()
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, PartialEq, Debug)]
 pub struct App {
     pub id: String,
     pub cmd: String,
     #[serde(default)]
     pub args: Vec<String>,
+    /// Whether descendants of the app that start a session of their own, as
+    /// daemons do, keep the app running
+    #[serde(default)]
+    pub count_daemons: bool,
+    /// How `leakbuster watch` recognizes the app
+    #[serde(default)]
+    pub watch: Watch,
     #[serde(default)]
     pub startup_hooks: Vec<StartupHook>,
     #[serde(default)]
@@ -36,33 +229,113 @@ pub struct App {
     pub shutdown_hooks: Vec<ShutdownHook>,
 }
 
+impl App {
+    /// Conditions of all hooks of this app
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.startup_hooks.iter().filter_map(|h| h.condition.as_ref())
+            .chain(self.time_hooks.iter().filter_map(|h| h.condition.as_ref()))
+            .chain(self.shutdown_hooks.iter().filter_map(|h| h.condition.as_ref()))
+    }
+}
+
+/// Describes the processes of an app that was started without leakbuster.
+/// A process has to match all criteria that are set. If none are set, the
+/// process name has to be the file name of the app's `cmd`.
+#[derive(Deserialize, PartialEq, Debug, Default)]
+pub struct Watch {
+    /// Path of the executable
+    #[serde(default)]
+    pub exe: Option<PathBuf>,
+    /// Process name, as shown by `ps -o comm`
+    #[serde(default)]
+    pub comm: Option<String>,
+    /// Has to match somewhere in the command line, with the arguments
+    /// separated by spaces
+    #[serde(default)]
+    pub cmdline: Option<Pattern>
+}
+
+impl Watch {
+    pub fn is_empty(&self) -> bool {
+        self.exe.is_none() && self.comm.is_none() && self.cmdline.is_none()
+    }
+}
+
+/// A regular expression, which is checked when the config is loaded
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> result::Result<Pattern, regex::Error> {
+        Regex::new(&pattern).map(Pattern)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
 #[derive(Deserialize, PartialEq, Debug)]
 pub struct StartupHook {
     pub cmd: String,
     #[serde(default)]
     pub args: Vec<String>,
+    /// Kill the hook if it runs for longer than this
+    #[serde(default)]
+    pub timeout: Option<Duration>,
     #[serde(default)]
     pub condition: Option<Condition>
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, PartialEq, Debug)]
 pub struct ShutdownHook {
     pub cmd: String,
     #[serde(default)]
     pub args: Vec<String>,
+    /// Kill the hook if it runs for longer than this
+    #[serde(default)]
+    pub timeout: Option<Duration>,
     #[serde(default)]
     pub condition: Option<Condition>
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, PartialEq, Debug)]
 pub struct TimeHook {
-    pub cmd: String,
+    /// Command to run. Exactly one of `cmd` and `action` is set.
+    #[serde(default)]
+    pub cmd: Option<String>,
     #[serde(default)]
     pub args: Vec<String>,
+    /// Kill the hook command if it runs for longer than this
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// Signal to send to the app instead of running a command
+    #[serde(default)]
+    pub action: Option<Action>,
+    #[serde(default)]
+    pub target: SignalTarget,
+    /// Time that the app gets to exit after `terminate`, before it is killed
+    #[serde(default="TimeHook::default_grace_period")]
+    pub grace_period: Duration,
     #[serde(default)]
     pub condition_cmd: Option<String>,
     #[serde(default)]
     pub condition_args: Vec<String>,
+    /// Kill the condition command if it runs for longer than this, which
+    /// counts as failed
+    #[serde(default)]
+    pub condition_timeout: Option<Duration>,
     #[serde(default)]
     pub condition: Option<Condition>,
     #[serde(default="TimeHook::default_interval")]
@@ -79,12 +352,216 @@ impl TimeHook {
     fn default_initial_delay() -> Duration {
         Duration { seconds: 0 }
     }
+
+    fn default_grace_period() -> Duration {
+        Duration { seconds: 10 }
+    }
+}
+
+/// Built-in hook actions, which signal the app that leakbuster started
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// SIGTERM, followed by SIGKILL if the app is still running after the
+    /// grace period
+    Terminate,
+    /// SIGKILL
+    Kill,
+    /// SIGSTOP, pauses the app
+    Stop,
+    /// SIGCONT, resumes a stopped app
+    Cont
+}
+
+/// Which processes an action is sent to
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignalTarget {
+    /// The process that leakbuster started, and its descendants
+    #[default]
+    Process,
+    /// The process that leakbuster started, and all processes in its process
+    /// group. The app is started in a process group of its own for this.
+    Group
+}
+
+/// Parses all condition and duration fields in the raw config, and returns
+/// the errors, with their positions in the config file.
+pub fn expression_errors(config_str: &str) -> Vec<ExpressionError> {
+    let keys: Vec<&str> = CONDITION_FIELDS.iter().chain(DURATION_FIELDS).copied().collect();
+    let fields = match locate::find_fields(config_str, &keys) {
+        Ok(fields) => fields,
+        // Not valid YAML, which serde will report on its own
+        Err(_) => return vec![]
+    };
+    fields.into_iter().filter_map(|field| {
+        let error = if CONDITION_FIELDS.contains(&field.key.as_str()) {
+            parse_condition(&field.value).err()
+        } else {
+            parse_duration(&field.value).err()
+        }?;
+        let (line, column) = field.position(config_str, error.offset);
+        let line_str = config_str.lines().nth(line - 1).unwrap_or("");
+        Some(ExpressionError {
+            line,
+            column,
+            field: field.key,
+            snippet: caret_snippet(line_str, column, error.len),
+            error
+        })
+    }).collect()
+}
+
+/// Finds all fields in the raw config that leakbuster doesn't know about.
+/// These are ignored when loading the config, so they are most likely typos.
+/// If the config can't be loaded, only the fields before the first error
+/// are found.
+pub fn unknown_fields(config_str: &str) -> Vec<UnknownField> {
+    // Keys of the enclosing objects, and name of each field that serde skipped
+    let mut ignored: Vec<(Vec<String>, String)> = vec![];
+    let _ = serde_ignored::deserialize::<_, _, Config>(
+        serde_yaml::Deserializer::from_str(config_str),
+        |path| {
+            let mut keys = map_keys(&path);
+            if let Some(name) = keys.pop() {
+                ignored.push((keys, name));
+            }
+        }
+    );
+    let mut keys = match locate::find_keys(config_str) {
+        Ok(keys) => keys.into_iter(),
+        Err(_) => return vec![]
+    };
+    // Serde reads the file in order, so the keys of the ignored fields come
+    // up in the same order
+    ignored.into_iter().filter_map(|(path, name)| {
+        let key = keys.find(|key| key.path == path && key.name == name)?;
+        let (object, fields) = match KNOWN_FIELDS.iter()
+            .find(|(known_path, _, _)| path.iter().eq(known_path.iter())) {
+            Some((_, object, fields)) => (object.to_string(), *fields),
+            None => (path.last().cloned().unwrap_or_else(|| "Root".to_string()), &[][..])
+        };
+        let suggestion = fields.iter()
+            .find(|field| edit_distance(field, &name) <= 2)
+            .map(|field| field.to_string());
+        Some(UnknownField { line: key.line, column: key.column, object, name, suggestion })
+    }).collect()
+}
+
+/// Keys of the mappings on the path, outermost first, without the positions
+/// in sequences
+fn map_keys(path: &serde_ignored::Path) -> Vec<String> {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => vec![],
+        Path::Map { parent, key } => {
+            let mut keys = map_keys(parent);
+            keys.push(key.clone());
+            keys
+        },
+        Path::Seq { parent, .. }
+        | Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => map_keys(parent)
+    }
+}
+
+/// Levenshtein distance, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// A field in the config file that isn't part of the config format
+#[derive(Debug, PartialEq)]
+pub struct UnknownField {
+    /// Line in the config file, starting at 1
+    pub line: usize,
+    /// Column in the config file, in characters, starting at 1
+    pub column: usize,
+    /// Kind of object that contains the field, e.g. "TimeHook"
+    pub object: String,
+    pub name: String,
+    /// A known field with a similar name
+    pub suggestion: Option<String>
+}
+
+impl fmt::Display for UnknownField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unknown field `{}` in {} at line {}, column {}",
+            self.name, self.object, self.line, self.column
+        )?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{}`?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// A condition or duration in the config file that couldn't be parsed
+#[derive(Debug)]
+pub struct ExpressionError {
+    /// Line in the config file, starting at 1
+    pub line: usize,
+    /// Column in the config file, in characters, starting at 1
+    pub column: usize,
+    /// Name of the field that contains the expression
+    pub field: String,
+    pub error: ParseError,
+    /// The line of the config file that contains the error, with a caret
+    /// below the error position
+    pub snippet: String
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Couldn't parse {} in `{}` at line {}, column {}: {}\n{}",
+            self.error.what, self.field, self.line, self.column,
+            self.error.reason(), self.snippet
+        )
+    }
+}
+
+/// A calendar file that couldn't be read
+#[derive(Debug)]
+pub struct CalendarError {
+    pub name: String,
+    /// Path as written in the config
+    pub path: PathBuf,
+    pub error: calendar::file::Error
+}
+
+impl fmt::Display for CalendarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Couldn't load calendar '{}' from {}: {}",
+            self.name, self.path.display(), self.error
+        )
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
     IoError(std::io::Error),
-    DeserializationError(serde_yaml::Error)
+    DeserializationError(serde_yaml::Error),
+    /// The config is well-formed, but doesn't make sense
+    InvalidConfig(String),
+    ExpressionError(Box<ExpressionError>)
 }
 
 impl From<std::io::Error> for Error {
@@ -105,13 +582,30 @@ pub type Result<T, E = Error> = result::Result<T, E>;
 mod test {
     use tempfile::NamedTempFile;
     use std::fs;
-    use crate::config::{App, Config, Result, StartupHook, TimeHook};
+    use crate::config::{
+        locate, unknown_fields, Action, App, Config, Error, Result, SignalTarget, StartupHook,
+        TimeHook, UnknownField, Watch, KNOWN_FIELDS
+    };
+    use crate::calendar::{DateRange, WeekStart};
+    use crate::expressions::{Calendars, Groups};
+    use chrono::NaiveDate;
+    use std::collections::BTreeMap;
 
     #[test]
     fn load_minimal_config() {
         let cfg = config_from("apps: []")
             .expect("Error while loading config");
-        assert_eq!(Config { apps: vec![] }, cfg);
+        assert_eq!(
+            Config {
+                apps: vec![],
+                groups: Groups::new(),
+                calendars: BTreeMap::new(),
+                week_start: WeekStart::Monday,
+                calendar_dates: Calendars::new(),
+                calendar_errors: vec![]
+            },
+            cfg
+        );
     }
 
     #[test]
@@ -136,25 +630,43 @@ apps:
                     id: "app1".to_string(),
                     cmd: "echo".to_string(),
                     args: vec!["-n".to_string(), "Hello world".to_string()],
+                    count_daemons: false,
+                    watch: Watch::default(),
                     startup_hooks: vec![
                         StartupHook {
                             cmd: "touch".to_string(),
                             args: vec!["startup_marker".to_string()],
+                            timeout: None,
+                            condition: None
                         }
                     ],
                     time_hooks: vec![
                         TimeHook {
-                            cmd: "killall".to_string(),
+                            cmd: Some("killall".to_string()),
                             args: vec!["firefox".to_string()],
-                            condition_cmd: "sh".to_string(),
+                            timeout: None,
+                            action: None,
+                            target: SignalTarget::Process,
+                            grace_period: TimeHook::default_grace_period(),
+                            condition_cmd: Some("sh".to_string()),
                             condition_args: vec![
                                 "-c".to_string(),
                                 "exit 1".to_string()
-                            ]
+                            ],
+                            condition_timeout: None,
+                            condition: None,
+                            interval: TimeHook::default_interval(),
+                            initial_delay: TimeHook::default_initial_delay()
                         }
-                    ]
+                    ],
+                    shutdown_hooks: vec![]
                 }
-            ]
+            ],
+            groups: Groups::new(),
+            calendars: BTreeMap::new(),
+            week_start: WeekStart::Monday,
+            calendar_dates: Calendars::new(),
+            calendar_errors: vec![]
         };
         assert_eq!(expected, cfg);
     }
@@ -172,12 +684,328 @@ apps:
         cfg.get_app("app_id").expect("could not find app by id");
     }
 
+    #[test]
+    fn warns_about_references_to_unknown_apps() {
+        let cfg = config_from("
+apps:
+  - id: firefox
+    cmd: firefox
+    startup_hooks:
+      - cmd: true
+        condition: atmost 1 h of discord in this day
+    time_hooks:
+      - cmd: true
+        condition: usage(firefox, this day) + usage(slack, this day) > 1 h
+  - id: discord
+    cmd: discord
+    shutdown_hooks:
+      - cmd: true
+        condition: atmost 1 h of steam in this day")
+            .expect("Could not load config");
+        assert_eq!(
+            vec![
+                "Condition of app 'firefox' refers to app 'slack', which is not defined in config",
+                "Condition of app 'discord' refers to app 'steam', which is not defined in config",
+            ],
+            cfg.warnings()
+        );
+    }
+
+    #[test]
+    fn load_groups() {
+        let cfg = config_from("
+apps:
+  - id: slack
+    cmd: slack
+  - id: discord
+    cmd: discord
+groups:
+  chat: [slack, discord]
+  empty: []")
+            .expect("Could not load config");
+        assert_eq!(
+            Some(&vec!["slack".to_string(), "discord".to_string()]),
+            cfg.groups.get("chat")
+        );
+        assert_eq!(Some(&vec![]), cfg.groups.get("empty"));
+        assert!(cfg.warnings().is_empty());
+    }
+
+    #[test]
+    fn load_week_start() {
+        let cfg = config_from("
+apps: []
+week_start: sunday")
+            .expect("Could not load config");
+        assert_eq!(WeekStart::Sunday, cfg.week_start);
+        assert!(config_from("apps: []\nweek_start: tuesday").is_err());
+    }
+
+    #[test]
+    fn load_calendars_relative_to_config() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("vacation.yaml"), "- 2026-12-20..2027-01-06").unwrap();
+        let config_path = dir.path().join("config.yaml");
+        fs::write(&config_path, "
+apps:
+  - id: game
+    cmd: game
+    startup_hooks:
+      - cmd: true
+        condition: on calendar vacation or on calendar holidays
+calendars:
+  vacation: vacation.yaml").unwrap();
+        let cfg = Config::load(&config_path).expect("Could not load config");
+        let vacation = DateRange {
+            first: NaiveDate::from_ymd(2026, 12, 20),
+            last: NaiveDate::from_ymd(2027, 1, 6)
+        };
+        assert_eq!(Some(&vec![vacation]), cfg.calendar_dates.get("vacation"));
+        assert_eq!(
+            vec!["Condition of app 'game' refers to calendar 'holidays', which is not defined in config"],
+            cfg.warnings()
+        );
+
+        fs::write(&config_path, "apps: []
+calendars:
+  holidays: holidays.ics").unwrap();
+        let cfg = Config::load(&config_path).expect("Could not load config");
+        assert!(cfg.calendar_dates.is_empty());
+        assert_eq!(
+            vec!["Couldn't load calendar 'holidays' from holidays.ics: No such file or directory \
+                (os error 2), conditions that refer to it can't be evaluated"],
+            cfg.warnings()
+        );
+    }
+
+    #[test]
+    fn warns_about_unknown_groups_and_members() {
+        let cfg = config_from("
+apps:
+  - id: slack
+    cmd: slack
+    startup_hooks:
+      - cmd: true
+        condition: atmost 2 h of group:chat in this day and atmost 2 h of group:games in this day
+groups:
+  chat: [slack, element]")
+            .expect("Could not load config");
+        assert_eq!(
+            vec![
+                "Group 'chat' contains app 'element', which is not defined in config",
+                "Condition of app 'slack' refers to group 'games', which is not defined in config",
+            ],
+            cfg.warnings()
+        );
+    }
+
+    #[test]
+    fn condition_errors_point_into_config_file() {
+        let err = config_from("
+apps:
+  - id: firefox
+    cmd: firefox
+    time_hooks:
+      - cmd: true
+        condition: atmost 1 h in this dya")
+            .expect_err("Config should be rejected");
+        match err {
+            Error::ExpressionError(e) => {
+                assert_eq!((7, 39), (e.line, e.column));
+                assert_eq!("condition", e.field);
+                assert_eq!(
+                    "Couldn't parse condition in `condition` at line 7, column 39: expected time unit (second, minute, hour, day, week, month or year)
+        condition: atmost 1 h in this dya
+                                      ^",
+                    e.to_string()
+                );
+            },
+            other => panic!("Expected expression error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn duration_errors_point_into_config_file() {
+        let err = config_from("
+apps:
+  - id: firefox
+    cmd: firefox
+    time_hooks:
+      - cmd: true
+        interval: '10 min'")
+            .expect_err("Config should be rejected");
+        match err {
+            Error::ExpressionError(e) => {
+                assert_eq!((7, 23), (e.line, e.column));
+                assert_eq!("interval", e.field);
+            },
+            other => panic!("Expected expression error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn load_hook_actions() {
+        let cfg = config_from("
+apps:
+  - id: firefox
+    cmd: firefox
+    time_hooks:
+      - action: stop
+      - action: terminate
+        target: group
+        grace_period: 30 s")
+            .expect("Could not load config");
+        let hooks = &cfg.apps[0].time_hooks;
+        assert_eq!(
+            (None, Some(Action::Stop), SignalTarget::Process, TimeHook::default_grace_period()),
+            (hooks[0].cmd.clone(), hooks[0].action, hooks[0].target, hooks[0].grace_period)
+        );
+        assert_eq!(
+            (None, Some(Action::Terminate), SignalTarget::Group, 30),
+            (hooks[1].cmd.clone(), hooks[1].action, hooks[1].target, hooks[1].grace_period.seconds)
+        );
+    }
+
+    #[test]
+    fn time_hooks_need_either_cmd_or_action() {
+        let test_cases = vec![
+            ("cmd: true\n        action: kill", "can't have both `cmd` and `action`"),
+            ("args: []", "needs either `cmd` or `action`"),
+        ];
+        for (hook, expected) in test_cases {
+            let err = config_from(&format!("
+apps:
+  - id: firefox
+    cmd: firefox
+    time_hooks:
+      - {}", hook))
+                .expect_err("Config should be rejected");
+            match err {
+                Error::InvalidConfig(e) => assert!(e.ends_with(expected), "{}", e),
+                other => panic!("Expected invalid config, got {:?}", other)
+            }
+        }
+    }
+
+    #[test]
+    fn load_watch_patterns() {
+        let cfg = config_from("
+apps:
+  - id: work-browser
+    cmd: firefox
+    watch:
+      cmdline: firefox .*-P work").expect("Could not load config");
+        let pattern = cfg.apps[0].watch.cmdline.as_ref().unwrap();
+        assert!(pattern.is_match("/usr/lib/firefox/firefox -P work"));
+        assert!(!pattern.is_match("/usr/lib/firefox/firefox -P home"));
+        assert!(Watch::default().is_empty());
+
+        let err = config_from("
+apps:
+  - id: work-browser
+    cmd: firefox
+    watch:
+      cmdline: firefox (-P").expect_err("Config should be rejected");
+        assert!(matches!(err, Error::DeserializationError(_)), "{:?}", err);
+    }
+
     #[test]
     fn get_nonexistent_app() {
         let cfg = config_from("apps: []").expect("Could not load config");
         assert!(cfg.get_app("app_id").is_none());
     }
 
+    #[test]
+    fn finds_unknown_fields() {
+        let config_str = "
+apps:
+  - id: firefox
+    cmd: firefox
+    startup_hook: []
+    time_hooks:
+      - cmd: true
+        condition_cmd: sh
+        comdition_args: [-c, 'exit 0']
+groups:
+  anything: [firefox]
+colour: blue";
+        let found: Vec<String> = unknown_fields(config_str).iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "Unknown field `startup_hook` in App at line 5, column 5, did you mean `startup_hooks`?",
+                "Unknown field `comdition_args` in TimeHook at line 9, column 9, did you mean `condition_args`?",
+                "Unknown field `colour` in Root at line 12, column 1",
+            ],
+            found
+        );
+        // Unknown fields are ignored when loading
+        config_from(config_str).expect("Could not load config");
+        // Found even if the config can't be loaded, e.g. because the field
+        // that was meant is missing
+        let found: Vec<String> = unknown_fields("apps:\n  - id: a\n    cmdd: a").iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            vec!["Unknown field `cmdd` in App at line 3, column 5, did you mean `cmd`?"],
+            found
+        );
+    }
+
+    #[test]
+    fn all_known_fields_are_accepted() {
+        let config_str = "
+apps:
+  - id: firefox
+    cmd: firefox
+    args: []
+    count_daemons: true
+    watch:
+      exe: /usr/lib/firefox/firefox
+      comm: firefox
+      cmdline: -P work
+    startup_hooks:
+      - cmd: true
+        args: []
+        timeout: 1 m
+        condition: Mon
+    time_hooks:
+      - cmd: true
+        args: []
+        timeout: 1 m
+        condition_cmd: true
+        condition_args: []
+        condition_timeout: 5 s
+        condition: Mon
+        interval: 1 m
+        initial_delay: 1 m
+      - action: terminate
+        target: group
+        grace_period: 5 s
+    shutdown_hooks:
+      - cmd: true
+        args: []
+        timeout: 1 m
+        condition: Mon
+groups:
+  browsers: [firefox]
+calendars: {}
+week_start: monday";
+        assert_eq!(Vec::<UnknownField>::new(), unknown_fields(config_str));
+        config_from(config_str).expect("Could not load config");
+        // Every field that suggestions are made from is a real one
+        let keys = locate::find_keys(config_str).unwrap();
+        for (path, _, fields) in KNOWN_FIELDS {
+            for field in *fields {
+                assert!(
+                    keys.iter().any(|key| key.path.iter().eq(path.iter()) && key.name == *field),
+                    "{}.{} isn't used in the config", path.join("."), field
+                );
+            }
+        }
+    }
+
     fn config_from(config_str: &str) -> Result<Config> {
         let f = NamedTempFile::new().unwrap();
         fs::write(f.path(), config_str).unwrap();
@@ -0,0 +1,237 @@
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, ScanError, TScalarStyle};
+
+/// A scalar value in the config file, together with where it is in the file.
+/// Used to report errors in condition and duration fields at their actual
+/// position, which serde doesn't keep track of.
+#[derive(Debug)]
+pub struct Field {
+    pub key: String,
+    pub value: String,
+    /// Char index of the first character of the scalar, after quotes
+    content_start: usize
+}
+
+/// A key of a mapping in the config file
+#[derive(Debug)]
+pub struct Key {
+    /// Keys of the enclosing mappings, outermost first. Sequences don't
+    /// appear in the path, so every hook of every app has the path
+    /// `["apps", "time_hooks"]`.
+    pub path: Vec<String>,
+    pub name: String,
+    /// Line in the file, starting at 1
+    pub line: usize,
+    /// Column in the file, in characters, starting at 1
+    pub column: usize
+}
+
+/// Finds all scalar values in the YAML document that belong to one of the
+/// given keys, in order of appearance.
+pub fn find_fields(yaml: &str, keys: &[&str]) -> Result<Vec<Field>, ScanError> {
+    Ok(scan(yaml, keys)?.fields)
+}
+
+/// Finds all keys of all mappings in the YAML document, in order of
+/// appearance.
+pub fn find_keys(yaml: &str) -> Result<Vec<Key>, ScanError> {
+    Ok(scan(yaml, &[])?.keys)
+}
+
+fn scan<'a>(yaml: &str, field_keys: &'a [&'a str]) -> Result<FieldFinder<'a>, ScanError> {
+    let mut finder = FieldFinder { field_keys, stack: vec![], fields: vec![], keys: vec![] };
+    Parser::new(yaml.chars()).load(&mut finder, false)?;
+    Ok(finder)
+}
+
+impl Field {
+    /// Maps a byte offset into the value to a 1-based line and column in the
+    /// file. Exact for plain and quoted scalars without escape sequences, and
+    /// for literal blocks. Folded whitespace is matched up greedily.
+    pub fn position(&self, yaml: &str, value_offset: usize) -> (usize, usize) {
+        let chars: Vec<char> = yaml.chars().collect();
+        let mut r = self.content_start;
+        for c in self.value[..value_offset].chars() {
+            if r >= chars.len() {
+                break;
+            }
+            if c.is_whitespace() {
+                // Line breaks and indentation may have been folded into a
+                // single space or stripped
+                while r < chars.len() && chars[r].is_whitespace() {
+                    r += 1;
+                }
+            } else {
+                while r < chars.len() && chars[r] != c && chars[r].is_whitespace() {
+                    r += 1;
+                }
+                r += 1;
+            }
+        }
+        while r < chars.len() && chars[r].is_whitespace() {
+            r += 1;
+        }
+        line_and_column(&chars, r)
+    }
+}
+
+/// 1-based line and column of a char index
+fn line_and_column(chars: &[char], index: usize) -> (usize, usize) {
+    let index = index.min(chars.len());
+    let before = &chars[..index];
+    let line = before.iter().filter(|c| **c == '\n').count() + 1;
+    let column = index - before.iter().rposition(|c| *c == '\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line, column)
+}
+
+enum Node {
+    Mapping { key: Option<String>, expect_key: bool, path: Vec<String> },
+    Sequence { path: Vec<String> }
+}
+
+struct FieldFinder<'a> {
+    field_keys: &'a [&'a str],
+    stack: Vec<Node>,
+    fields: Vec<Field>,
+    keys: Vec<Key>
+}
+
+impl<'a> FieldFinder<'a> {
+    /// Called for every value node. Returns the key of the value, if the
+    /// value is part of a mapping.
+    fn value_key(&mut self) -> Option<String> {
+        match self.stack.last_mut() {
+            Some(Node::Mapping { key, expect_key, .. }) => {
+                *expect_key = true;
+                key.take()
+            },
+            _ => None
+        }
+    }
+
+    /// Path of a new node that is the next value in the current node
+    fn child_path(&mut self) -> Vec<String> {
+        let key = self.value_key();
+        let mut path = match self.stack.last() {
+            Some(Node::Mapping { path, .. }) | Some(Node::Sequence { path }) => path.clone(),
+            None => vec![]
+        };
+        path.extend(key);
+        path
+    }
+
+    /// The marker of block scalars already points at the content, but the
+    /// marker of quoted scalars points at the opening quote.
+    fn content_start(style: TScalarStyle, mark: &Marker) -> usize {
+        match style {
+            TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted => mark.index() + 1,
+            _ => mark.index()
+        }
+    }
+}
+
+impl<'a> MarkedEventReceiver for FieldFinder<'a> {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, style, _, _) => {
+                if let Some(Node::Mapping { key, expect_key: expect_key @ true, path }) =
+                    self.stack.last_mut() {
+                    let (line, column) = (mark.line(), mark.col() + 1);
+                    self.keys.push(Key { path: path.clone(), name: value.clone(), line, column });
+                    *key = Some(value);
+                    *expect_key = false;
+                    return;
+                }
+                if let Some(key) = self.value_key() {
+                    if self.field_keys.contains(&key.as_str()) {
+                        let content_start = FieldFinder::content_start(style, &mark);
+                        self.fields.push(Field { key, value, content_start });
+                    }
+                }
+            },
+            Event::MappingStart(_) => {
+                let path = self.child_path();
+                self.stack.push(Node::Mapping { key: None, expect_key: true, path });
+            },
+            Event::SequenceStart(_) => {
+                let path = self.child_path();
+                self.stack.push(Node::Sequence { path });
+            },
+            Event::Alias(_) => {
+                self.value_key();
+            },
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            },
+            _ => {}
+        }
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::config::locate::*;
+
+    #[test]
+    fn finds_fields_by_key() {
+        let yaml = "
+apps:
+  - id: a
+    condition: Mon
+    hooks:
+      - condition: 'Tue'
+        other: Wed
+  - condition:
+      nested: Thu
+";
+        let fields = find_fields(yaml, &["condition", "nested"]).unwrap();
+        let found: Vec<(&str, &str)> = fields.iter()
+            .map(|f| (f.key.as_str(), f.value.as_str()))
+            .collect();
+        assert_eq!(vec![("condition", "Mon"), ("condition", "Tue"), ("nested", "Thu")], found);
+    }
+
+    #[test]
+    fn maps_value_offsets_to_file_positions() {
+        let test_cases = vec![
+            ("condition: Mon adn Tue\n", 4, (1, 16)),
+            ("a: b\ncondition: 'Mon adn Tue'\n", 4, (2, 17)),
+            ("condition: \"Mon adn Tue\"\n", 0, (1, 13)),
+            ("x:\n  condition: |\n    Mon and\n      Tue adn Wed\n", 14, (4, 11)),
+            ("condition: Mon and\n  Tue adn Wed\n", 12, (2, 7)),
+        ];
+        for (yaml, offset, expected) in test_cases {
+            let fields = find_fields(yaml, &["condition"]).unwrap();
+            assert_eq!(expected, fields[0].position(yaml, offset), "In {:?}", yaml);
+        }
+    }
+
+    #[test]
+    fn finds_keys_with_paths() {
+        let yaml = "
+apps:
+  - id: a
+    time_hooks:
+      - cmd: true
+        comdition_args: []
+groups:
+  chat: [a]
+";
+        let keys = find_keys(yaml).unwrap();
+        let keys: Vec<(String, &str, usize, usize)> = keys.iter()
+            .map(|k| (k.path.join("."), k.name.as_str(), k.line, k.column))
+            .collect();
+        assert_eq!(
+            vec![
+                ("".to_string(), "apps", 2, 1),
+                ("apps".to_string(), "id", 3, 5),
+                ("apps".to_string(), "time_hooks", 4, 5),
+                ("apps.time_hooks".to_string(), "cmd", 5, 9),
+                ("apps.time_hooks".to_string(), "comdition_args", 6, 9),
+                ("".to_string(), "groups", 7, 1),
+                ("groups".to_string(), "chat", 8, 3),
+            ],
+            keys
+        );
+    }
+}
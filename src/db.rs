@@ -1,70 +1,565 @@
-use rusqlite::{params, Connection};
-use std::convert::TryInto;
+pub mod jsonl;
+pub mod memory;
+pub mod migrations;
+pub mod recorder;
+
+use rusqlite::{params, Connection, OpenFlags, ToSql, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
 use std::path::Path;
 use std::result;
+use std::time::Duration;
+
+/// Where usage is recorded, and read from by conditions
+pub trait UsageStore {
+    /// Starts recording a period of use of the app, which is extended with
+    /// `extend_session` for as long as the app keeps running. `pid` is the
+    /// process that the app was recognized by.
+    fn start_session(&self, app_key: &str, start: u64, end: u64, pid: Option<u32>)
+        -> Result<SessionId>;
+
+    /// Moves the end of the session to `end`.
+    fn extend_session(&self, session: SessionId, end: u64) -> Result<()>;
+
+    /// Records that the app was used for the `duration` seconds up to
+    /// `timestamp`, which count whole in any window that `timestamp` lies
+    /// in. If a session of the app that was recorded in steps of the same
+    /// duration ends right where this one starts, it is extended instead of
+    /// adding another one.
+    fn record_usage(&self, app_key: &str, timestamp: u64, duration: u64) -> Result<()>;
+
+    /// Combined usage of all given apps between `from` and `to`, both
+    /// included, as one sum. Sessions that reach beyond either end only
+    /// count with the part inside, see `usage_within`.
+    fn get_total_usage(&self, app_keys: &[&str], from: u64, to: u64) -> Result<u64>;
+
+    /// Usage of the app between `from` and `to`
+    fn get_usage(&self, app_key: &str, from: u64, to: u64) -> Result<u64> {
+        self.get_total_usage(&[app_key], from, to)
+    }
+
+    /// Aggregates over the sessions of all given apps between `from` and
+    /// `to`, counted like in `get_total_usage`
+    fn get_usage_stats(&self, app_keys: &[&str], from: u64, to: u64) -> Result<UsageStats>;
+
+    /// Keys of all apps that usage was recorded for, sorted
+    fn app_keys(&self) -> Result<Vec<String>>;
+
+    /// The sessions that match the filter, ordered by their start. They are
+    /// read as they are iterated over, where the backend allows it.
+    fn sessions<'a>(&'a self, filter: &SessionFilter)
+        -> Result<Box<dyn Iterator<Item = Result<Session>> + 'a>>;
+
+    /// Adds the sessions all at once, or none of them if any is invalid.
+    /// Sessions that lie within a stored session of the same app are
+    /// skipped as duplicates. Sessions that partly overlap stored sessions
+    /// of the same app are merged with them, so a session that has grown
+    /// since it was last imported isn't counted twice.
+    fn import_sessions(&self, sessions: &[Session]) -> Result<ImportSummary>;
+}
+
+/// A period of use of an app, as it is exported and imported
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub app: String,
+    pub start: u64,
+    pub end: u64,
+    /// Process that the app was recognized by, if known
+    pub pid: Option<u32>
+}
+
+impl Session {
+    fn validate(&self) -> result::Result<(), String> {
+        if self.app.is_empty() {
+            Err("App is empty".to_string())
+        } else if self.end < self.start {
+            Err(format!("Session ends at {} before it starts at {}", self.end, self.start))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the session lies within `other`, of the same app
+    fn is_covered_by(&self, other: &Session) -> bool {
+        self.app == other.app && other.start <= self.start && self.end <= other.end
+    }
+
+    /// Whether the sessions are of the same app, and share some time
+    fn overlaps(&self, other: &Session) -> bool {
+        self.app == other.app && other.start < self.end && self.start < other.end
+    }
+}
+
+/// Checks all sessions before any is imported
+fn validate_all(sessions: &[Session]) -> Result<()> {
+    for (index, session) in sessions.iter().enumerate() {
+        session.validate().map_err(|e| Error::InvalidDataError(
+            format!("Session {}: {}", index + 1, e)
+        ))?;
+    }
+    Ok(())
+}
+
+/// Which sessions to read, see `UsageStore::sessions`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionFilter {
+    /// Only sessions of this app
+    pub app_key: Option<String>,
+    /// Only the parts of sessions after `from`, and before `to`
+    pub from: Option<u64>,
+    pub to: Option<u64>
+}
+
+impl SessionFilter {
+    /// The part of the session that the filter lets through, if any
+    fn apply(&self, session: Session) -> Option<Session> {
+        let from = self.from.unwrap_or(0);
+        let to = self.to.unwrap_or(u64::MAX);
+        let matches = self.app_key.iter().all(|key| *key == session.app)
+            && (self.from.is_none() || session.end > from)
+            && (self.to.is_none() || session.start < to);
+        if matches {
+            Some(Session { start: session.start.max(from), end: session.end.min(to), ..session })
+        } else {
+            None
+        }
+    }
+
+    /// Applies the filter to all sessions, and sorts the rest by start
+    fn apply_all<I: IntoIterator<Item = Session>>(&self, sessions: I) -> Vec<Session> {
+        let mut sessions: Vec<Session> = sessions.into_iter()
+            .filter_map(|session| self.apply(session))
+            .collect();
+        sessions.sort_by_key(|session| session.start);
+        sessions
+    }
+}
+
+/// Outcome of `UsageStore::import_sessions`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Sessions that partly overlapped stored sessions, which were extended
+    /// to cover them
+    pub merged: usize,
+    /// Sessions that were skipped, because they were stored already
+    pub duplicates: usize
+}
+
+/// Aggregates over the sessions of some apps, see `UsageStore::get_usage_stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UsageStats {
+    /// Combined usage, in seconds
+    pub total: u64,
+    /// Number of sessions that count within the window
+    pub sessions: u64,
+    /// Longest part of a session within the window, in seconds
+    pub longest_session: u64
+}
+
+impl UsageStats {
+    /// Stats of the sessions, given by their start, end and step, between
+    /// `from` and `to`
+    fn of_sessions<I: IntoIterator<Item = (u64, u64, u64)>>(sessions: I, from: u64, to: u64)
+        -> UsageStats {
+        let mut stats = UsageStats::default();
+        for (start, end, step) in sessions {
+            let length = usage_within(start, end, step, from, to);
+            if length > 0 {
+                stats.total += length;
+                stats.sessions += 1;
+                stats.longest_session = stats.longest_session.max(length);
+            }
+        }
+        stats
+    }
+}
+
+/// Opens the usage store at the path. Files ending in `.jsonl` are kept as
+/// JSON lines, everything else is an SQLite db.
+pub fn open_store<P: AsRef<Path>>(path: P) -> Result<Box<dyn UsageStore + Send>> {
+    let path = path.as_ref();
+    if path.extension() == Some("jsonl".as_ref()) {
+        Ok(Box::new(jsonl::JsonLinesStore::open(path)?))
+    } else {
+        Ok(Box::new(connect_sqlite(path)?))
+    }
+}
+
+/// Opens the usage store at the path like `open_store`, but without
+/// upgrading the schema of an existing SQLite db, see `connect_sqlite_current`
+pub fn open_current_store<P: AsRef<Path>>(path: P) -> Result<Box<dyn UsageStore + Send>> {
+    let path = path.as_ref();
+    if path.extension() == Some("jsonl".as_ref()) {
+        Ok(Box::new(jsonl::JsonLinesStore::open(path)?))
+    } else {
+        Ok(Box::new(connect_sqlite_current(path)?))
+    }
+}
+
+/// Start of the usage interval of `duration` seconds up to `timestamp`
+fn usage_start(timestamp: u64, duration: u64) -> Result<u64> {
+    timestamp.checked_sub(duration).ok_or_else(|| Error::InvalidDataError(
+        format!("Usage of {} s can't end at {}", duration, timestamp)
+    ))
+}
+
+/// Usage of the session from `start` to `end` between `from` and `to`, both
+/// included. A session is made of usages of `step` seconds each, which count
+/// whole in the windows that they end in, like the rows of the table that
+/// sessions replaced. Sessions that are recorded while the app runs have
+/// steps of 1 s, so they are clipped to the second.
+fn usage_within(start: u64, end: u64, step: u64, from: u64, to: u64) -> u64 {
+    if step == 0 || to < start {
+        return 0;
+    }
+    // The n-th usage of the session ends at `start + n * step`
+    let before = from.saturating_sub(start);
+    let first = (before.saturating_add(step - 1) / step).max(1);
+    let last = ((to - start) / step).min(end.saturating_sub(start) / step);
+    (last + 1).saturating_sub(first) * step
+}
 
+/// How long a write waits for other processes to release their lock on the
+/// db, before it fails
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens the db, creating it if necessary, and upgrades its schema.
 pub fn connect_sqlite<P: AsRef<Path>>(db_path: P) -> Result<Db> {
     let connection = Connection::open(db_path)?;
+    configure(&connection)?;
+    Db::open(connection)
+}
+
+/// Opens the db, creating it if necessary, but fails with
+/// `Error::OutdatedSchemaError` instead of upgrading the schema of a db that
+/// has data already. Commands that only look at the usage, or add to it once,
+/// use this, so a db is only ever upgraded by the commands that record usage
+/// anyway, or when the user asks for it.
+pub fn connect_sqlite_current<P: AsRef<Path>>(db_path: P) -> Result<Db> {
+    let connection = Connection::open(db_path)?;
+    configure(&connection)?;
     let db = Db { connection };
-    db.init()?;
+    let version = db.schema_version()?;
+    if version < migrations::LATEST_VERSION {
+        let tables: i64 = db.connection.query_row(
+            "SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0)
+        )?;
+        if tables > 0 {
+            return Err(Error::OutdatedSchemaError {
+                version,
+                latest: migrations::LATEST_VERSION
+            });
+        }
+    }
+    // Creates the tables of a new db, and refuses one of a newer version
+    db.migrate(false)?;
     Ok(db)
 }
 
+/// Opens an existing db as it is, without upgrading its schema.
+pub fn connect_sqlite_unmigrated<P: AsRef<Path>>(db_path: P) -> Result<Db> {
+    let connection = Connection::open_with_flags(
+        db_path,
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX
+    )?;
+    configure(&connection)?;
+    Ok(Db { connection })
+}
+
+/// Sets the connection up to share the db with other leakbuster processes,
+/// e.g. a `watch` and a few `run`s. Writers wait for each other instead of
+/// failing right away. Only affects this connection, not the db file.
+fn configure(connection: &Connection) -> Result<()> {
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.execute_batch("PRAGMA synchronous = NORMAL")?;
+    Ok(())
+}
+
+/// Switches the db to WAL mode, in which readers don't block the writer.
+/// The mode is persisted in the db file, so this is only done to a db that
+/// has the latest schema, and not to one that is only looked at.
+fn enable_wal(connection: &Connection) -> Result<()> {
+    // Reported back as the mode in effect, which stays `memory` for
+    // in-memory dbs
+    let _mode: String = connection.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+    Ok(())
+}
+
 pub fn open_in_memory() -> Result<Db> {
-    let connection = Connection::open_in_memory()?;
-    let db = Db { connection };
-    db.init()?;
-    Ok(db)
+    Db::open(Connection::open_in_memory()?)
 }
 
 pub struct Db {
     connection: Connection
 }
 
+/// A period of use of an app that is still being recorded, see
+/// `UsageStore::start_session`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionId(i64);
+
 impl Db {
-    fn init(&self) -> Result<()> {
+    fn open(connection: Connection) -> Result<Db> {
+        let db = Db { connection };
+        db.migrate(false)?;
+        Ok(db)
+    }
+
+    /// Start, end and step of the sessions of the apps that may count between
+    /// `from` and `to`, see `usage_within`
+    fn sessions_within(&self, app_keys: &[&str], from: u64, to: u64)
+        -> Result<Vec<(u64, u64, u64)>> {
+        if app_keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let placeholders = vec!["?"; app_keys.len()].join(", ");
+        let sql = format!(
+            "SELECT start, end, step FROM sessions
+                WHERE app_key IN ({})
+                  AND start < ?
+                  AND end >= ?",
+            placeholders
+        );
+        let mut params: Vec<&dyn ToSql> = app_keys.iter().map(|key| key as &dyn ToSql).collect();
+        params.push(&to);
+        params.push(&from);
+        let sessions = self.connection.prepare(&sql)?
+            .query_map(params.as_slice(), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(sessions)
+    }
+
+    /// Version of the schema, see `migrations::MIGRATIONS`
+    pub fn schema_version(&self) -> Result<u32> {
+        migrations::version(&self.connection)
+    }
+
+    /// Upgrades the schema to the latest version, and switches the db to WAL
+    /// mode. With `dry_run`, the db is left unchanged. Returns the versions
+    /// that were reached, and descriptions of the changes that reached them.
+    pub fn migrate(&self, dry_run: bool) -> Result<Vec<(u32, &'static str)>> {
+        let applied = migrations::migrate(&self.connection, dry_run)?;
+        if !dry_run {
+            enable_wal(&self.connection)?;
+        }
+        Ok(applied.into_iter().map(|(version, m)| (version, m.description)).collect())
+    }
+}
+
+impl UsageStore for Db {
+    fn start_session(&self, app_key: &str, start: u64, end: u64, pid: Option<u32>)
+        -> Result<SessionId> {
         self.connection.execute(
-            "CREATE TABLE IF NOT EXISTS usage (
-                app_key     TEXT NOT NULL,
-                timestamp   INT,
-                duration    INT
-            )", [])?;
-        Ok(())
+            "INSERT INTO sessions (app_key, start, end, pid) VALUES (?1, ?2, ?3, ?4)",
+            params![app_key, start, end, pid]
+        )?;
+        Ok(SessionId(self.connection.last_insert_rowid()))
     }
 
-    pub fn record_usage(&self, app_key: &str, timestamp: u64, duration: u64)
-        -> Result<()> {
+    fn extend_session(&self, session: SessionId, end: u64) -> Result<()> {
         self.connection.execute(
-            "INSERT INTO usage
-                (app_key, timestamp, duration) VALUES (?1, ?2, ?3)",
-            params![app_key, timestamp, duration]
+            "UPDATE sessions SET end = ?1 WHERE rowid = ?2",
+            params![end, session.0]
         )?;
         Ok(())
     }
 
-    pub fn get_usage(&self, app_key: &str, from: u64, to: u64) -> Result<u64> {
-        let usage: i64 = self.connection.query_row(
-            "SELECT SUM(duration) FROM USAGE
-                WHERE app_key = ?1
-                  AND timestamp >= ?2
-                  AND timestamp <= ?3",
-            params![app_key, from, to],
-            |row| Ok(row.get_ref(0)?.as_i64_or_null())
-        )??.unwrap_or(0);
-        let usage: Result<u64> = usage.try_into().map_err(|err| {
-            Error::InvalidDataError(format!(
-                    "Total usage must be non-negative ({:?})",
-                    err
-            ))
+    fn record_usage(&self, app_key: &str, timestamp: u64, duration: u64) -> Result<()> {
+        let start = usage_start(timestamp, duration)?;
+        let extended = self.connection.execute(
+            "UPDATE sessions SET end = ?1
+                WHERE rowid = (
+                    SELECT rowid FROM sessions
+                        WHERE app_key = ?2 AND end = ?3 AND step = ?4
+                        LIMIT 1
+                )",
+            params![timestamp, app_key, start, duration]
+        )?;
+        if extended == 0 {
+            self.connection.execute(
+                "INSERT INTO sessions (app_key, start, end, step) VALUES (?1, ?2, ?3, ?4)",
+                params![app_key, start, timestamp, duration]
+            )?;
+        }
+        Ok(())
+    }
+
+    fn get_total_usage(&self, app_keys: &[&str], from: u64, to: u64) -> Result<u64> {
+        Ok(self.sessions_within(app_keys, from, to)?.into_iter()
+            .map(|(start, end, step)| usage_within(start, end, step, from, to))
+            .sum())
+    }
+
+    fn get_usage_stats(&self, app_keys: &[&str], from: u64, to: u64) -> Result<UsageStats> {
+        Ok(UsageStats::of_sessions(self.sessions_within(app_keys, from, to)?, from, to))
+    }
+
+    fn app_keys(&self) -> Result<Vec<String>> {
+        let keys = self.connection
+            .prepare("SELECT DISTINCT app_key FROM sessions ORDER BY app_key")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(keys)
+    }
+
+    fn sessions<'a>(&'a self, filter: &SessionFilter)
+        -> Result<Box<dyn Iterator<Item = Result<Session>> + 'a>> {
+        Ok(Box::new(Sessions {
+            db: self,
+            filter: filter.clone(),
+            after: None,
+            batch: VecDeque::new(),
+            done: false
+        }))
+    }
+
+    fn import_sessions(&self, sessions: &[Session]) -> Result<ImportSummary> {
+        validate_all(sessions)?;
+        let tx = Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        let mut summary = ImportSummary::default();
+        {
+            let mut covering = tx.prepare(
+                "SELECT COUNT(*) FROM sessions WHERE app_key = ?1 AND start <= ?2 AND end >= ?3"
+            )?;
+            let mut overlapping = tx.prepare(
+                "SELECT rowid, start, end, pid FROM sessions
+                WHERE app_key = ?1 AND start < ?3 AND end > ?2
+                ORDER BY start, rowid"
+            )?;
+            let mut insert = tx.prepare(
+                "INSERT INTO sessions (app_key, start, end, pid) VALUES (?1, ?2, ?3, ?4)"
+            )?;
+            // Imported sessions are clipped to the second, and so is the
+            // session that they are merged into
+            let mut update = tx.prepare(
+                "UPDATE sessions SET start = ?2, end = ?3, pid = ?4, step = 1 WHERE rowid = ?1"
+            )?;
+            let mut delete = tx.prepare("DELETE FROM sessions WHERE rowid = ?1")?;
+            for session in sessions {
+                let covered: i64 = covering.query_row(
+                    params![session.app, session.start, session.end],
+                    |row| row.get(0)
+                )?;
+                if covered > 0 {
+                    summary.duplicates += 1;
+                    continue;
+                }
+                let stored = overlapping
+                    .query_map(params![session.app, session.start, session.end], |row| Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, Option<u32>>(3)?
+                    )))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                match stored.split_first() {
+                    None => {
+                        insert.execute(
+                            params![session.app, session.start, session.end, session.pid]
+                        )?;
+                        summary.imported += 1;
+                    },
+                    // The first stored session grows to cover the others
+                    Some(((rowid, start, _, pid), others)) => {
+                        let end = stored.iter().map(|(_, _, end, _)| *end).max().unwrap_or(0);
+                        update.execute(params![
+                            rowid,
+                            (*start).min(session.start as i64),
+                            end.max(session.end as i64),
+                            pid.or(session.pid)
+                        ])?;
+                        for (rowid, _, _, _) in others {
+                            delete.execute(params![rowid])?;
+                        }
+                        summary.merged += 1;
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
+}
+
+/// Reads the sessions of the SQLite db in batches, so exporting a large db
+/// doesn't hold all of it in memory
+struct Sessions<'a> {
+    db: &'a Db,
+    filter: SessionFilter,
+    /// Start and rowid of the last session that was read
+    after: Option<(u64, i64)>,
+    batch: VecDeque<Session>,
+    done: bool
+}
+
+/// Number of sessions that `Sessions` reads at once
+const SESSION_BATCH_SIZE: u32 = 1000;
+
+impl<'a> Sessions<'a> {
+    fn read_batch(&mut self) -> Result<()> {
+        let (after_start, after_rowid) = self.after.map_or((-1, -1), |(start, rowid)| {
+            (start as i64, rowid)
         });
-        usage
+        let mut statement = self.db.connection.prepare(
+            "SELECT rowid, app_key, start, end, pid FROM sessions
+                WHERE (?1 IS NULL OR app_key = ?1)
+                  AND (?2 IS NULL OR end > ?2)
+                  AND (?3 IS NULL OR start < ?3)
+                  AND (start > ?4 OR (start = ?4 AND rowid > ?5))
+                ORDER BY start, rowid
+                LIMIT ?6"
+        )?;
+        let rows = statement.query_map(
+            params![
+                self.filter.app_key, self.filter.from, self.filter.to,
+                after_start, after_rowid, SESSION_BATCH_SIZE
+            ],
+            |row| Ok((row.get(0)?, Session {
+                app: row.get(1)?,
+                start: row.get(2)?,
+                end: row.get(3)?,
+                pid: row.get(4)?
+            }))
+        )?;
+        let mut read = 0;
+        for row in rows {
+            let (rowid, session): (i64, Session) = row?;
+            self.after = Some((session.start, rowid));
+            self.batch.extend(self.filter.apply(session));
+            read += 1;
+        }
+        self.done = read < SESSION_BATCH_SIZE;
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Sessions<'a> {
+    type Item = Result<Session>;
+
+    fn next(&mut self) -> Option<Result<Session>> {
+        while self.batch.is_empty() && !self.done {
+            if let Err(e) = self.read_batch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.batch.pop_front().map(Ok)
     }
 }
 
 #[derive(Debug)]
 pub enum Error {
     RusqliteError(rusqlite::Error),
-    InvalidDataError(String)
+    IoError(io::Error),
+    InvalidDataError(String),
+    /// The db was written by a newer version of leakbuster, with a schema
+    /// version that is higher than the supported one
+    NewerSchemaError { version: u32, supported: u32 },
+    /// The db has an older schema, and needs to be upgraded before it can be
+    /// used, see `connect_sqlite_current`
+    OutdatedSchemaError { version: u32, latest: u32 }
 }
 
 impl From<rusqlite::Error> for Error {
@@ -73,6 +568,12 @@ impl From<rusqlite::Error> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
 impl From<rusqlite::types::FromSqlError> for Error {
     fn from(err: rusqlite::types::FromSqlError) -> Error {
         Error::InvalidDataError(
@@ -86,9 +587,23 @@ pub type Result<T, E = Error> = result::Result<T, E>;
 #[allow(unused_imports, dead_code)]
 mod test {
     use crate::db;
-    use crate::db::Db;
+    use crate::db::{
+        Db, Error, ImportSummary, Result, Session, SessionFilter, UsageStats, UsageStore,
+        SESSION_BATCH_SIZE
+    };
+    use crate::db::jsonl::JsonLinesStore;
+    use crate::db::memory::MemoryStore;
+    use crate::db::migrations;
+    use crate::db::recorder::UsageRecorder;
 
-    use tempfile::NamedTempFile;
+    use rusqlite::{params, Connection};
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::{NamedTempFile, TempDir};
 
     #[test]
     fn can_connect_to_and_initialize_new_db() {
@@ -134,38 +649,417 @@ mod test {
         db.record_usage("some-app", 1638437768, 60).unwrap();
     }
 
+    /// Usage of 60 s each, ending at the given timestamps
+    const USAGES: &[(&str, u64)] = &[("a1", 100), ("a2", 150), ("a1", 200), ("a1", 300)];
+    /// App, from, to, expected usage. Each usage counts whole in the windows
+    /// that it ends in.
+    const EXPECTED_USAGE: &[(&str, u64, u64, u64)] = &[
+        ("a1", 100, 300, 180),
+        ("a1", 150, 300, 120),
+        ("a1", 100, 250, 120),
+        ("a1", 150, 250, 60),
+        ("a1", 150, 170, 0),
+        ("a2", 100, 300, 60),
+    ];
+
     #[test]
     fn usage_is_calculated_correctly() {
         let f = tmpf();
         let db = db::connect_sqlite(f.path()).unwrap();
-        let usages = vec![
-            ("a1", 100, 60),
-            ("a2", 150, 60),
-            ("a1", 200, 60),
-            ("a1", 300, 60),
-        ];
-        for (app_key, timestamp, usage) in usages {
-            db.record_usage(app_key, timestamp, usage).unwrap();
-        }
-        let tests = vec![
-            ("a1", 100, 300, 180),
-            ("a1", 150, 300, 120),
-            ("a1", 100, 250, 120),
-            ("a1", 150, 250, 60),
-            ("a1", 150, 170, 0),
-            ("a2", 100, 300, 60),
-        ];
-        for (app_key, from, to, expected_usage) in tests {
+        for (app_key, timestamp) in USAGES {
+            db.record_usage(app_key, *timestamp, 60).unwrap();
+        }
+        check_expected_usage(&db);
+    }
+
+    #[test]
+    fn migration_keeps_usage_of_per_second_rows() {
+        // As written by older versions, which recorded usage every second
+        let rows: Vec<(&str, u64, u64)> = USAGES.iter()
+            .flat_map(|(app_key, timestamp)| {
+                (timestamp - 59..=*timestamp).map(move |second| (*app_key, second, 1))
+            })
+            .collect();
+        let db = check_migrated_usage(&rows);
+        assert_eq!(4, session_count(&db));
+    }
+
+    #[test]
+    fn migration_keeps_usage_of_longer_rows() {
+        let mut rows: Vec<(&str, u64, u64)> = USAGES.iter()
+            .map(|(app_key, timestamp)| (*app_key, *timestamp, 60))
+            .collect();
+        // Recorded twice at once, and in steps of different lengths in a row
+        rows.extend([("a3", 100, 60), ("a3", 100, 60), ("a3", 160, 60), ("a3", 161, 1)]);
+        let db = check_migrated_usage(&rows);
+        check_expected_usage(&db);
+    }
+
+    /// Writes the rows of app, timestamp and duration to the usage table of a
+    /// db at version 1, and checks that the usage in many windows is the
+    /// same after the db is migrated as in the table before
+    fn check_migrated_usage(rows: &[(&str, u64, u64)]) -> Db {
+        let f = tmpf();
+        let windows: Vec<(&str, u64, u64)> = ["a1", "a2", "a3"].iter()
+            .flat_map(|app_key| (30..=310).step_by(9).map(move |from| (*app_key, from)))
+            .flat_map(|(app_key, from)| (from..=320).step_by(13).map(move |to| (app_key, from, to)))
+            .collect();
+        let old_usage: Vec<u64> = {
+            let connection = Connection::open(f.path()).unwrap();
+            connection.execute_batch(
+                "CREATE TABLE usage (app_key TEXT NOT NULL, timestamp INT, duration INT);
+                PRAGMA user_version = 1;"
+            ).unwrap();
+            for (app_key, timestamp, duration) in rows {
+                connection.execute(
+                    "INSERT INTO usage (app_key, timestamp, duration) VALUES (?1, ?2, ?3)",
+                    params![app_key, timestamp, duration]
+                ).unwrap();
+            }
+            connection.execute(
+                "INSERT INTO usage (app_key, timestamp, duration) VALUES ('a1', NULL, 1)",
+                []
+            ).unwrap();
+            // The query of version 1
+            windows.iter()
+                .map(|(app_key, from, to)| connection.query_row(
+                    "SELECT COALESCE(SUM(duration), 0) FROM usage
+                        WHERE app_key = ?1 AND timestamp >= ?2 AND timestamp <= ?3",
+                    params![app_key, from, to],
+                    |row| row.get(0)
+                ).unwrap())
+                .collect()
+        };
+        let db = db::connect_sqlite(f.path()).unwrap();
+        assert_eq!(migrations::LATEST_VERSION, db.schema_version().unwrap());
+        for ((app_key, from, to), old_usage) in windows.iter().zip(old_usage) {
+            assert_eq!(
+                old_usage,
+                db.get_usage(app_key, *from, *to).unwrap(),
+                "app_key={}, from={}, to={}", app_key, from, to
+            );
+        }
+        db
+    }
+
+    #[test]
+    fn adjacent_usage_extends_session() {
+        let db = db::open_in_memory().unwrap();
+        for timestamp in 101..=160 {
+            db.record_usage("a1", timestamp, 1).unwrap();
+        }
+        db.record_usage("a1", 200, 10).unwrap();
+        db.record_usage("a2", 201, 1).unwrap();
+        assert_eq!(3, session_count(&db));
+        assert_eq!(70, db.get_usage("a1", 0, 1000).unwrap());
+        assert_eq!(31, db.get_usage("a1", 130, 195).unwrap());
+    }
+
+    #[test]
+    fn sessions_are_extended_in_place() {
+        let db = db::open_in_memory().unwrap();
+        let session = db.start_session("a1", 100, 100, Some(42)).unwrap();
+        assert_eq!(0, db.get_usage("a1", 0, 1000).unwrap());
+        db.extend_session(session, 130).unwrap();
+        db.extend_session(session, 160).unwrap();
+        assert_eq!(1, session_count(&db));
+        assert_eq!(60, db.get_usage("a1", 0, 1000).unwrap());
+        assert_eq!(11, db.get_usage("a1", 150, 1000).unwrap());
+        assert_eq!(1, db.get_usage("a1", 160, 1000).unwrap());
+        assert_eq!(0, db.get_usage("a1", 161, 1000).unwrap());
+    }
+
+    #[test]
+    fn all_stores_behave_the_same() {
+        check_store(&db::open_in_memory().unwrap());
+        check_store(&MemoryStore::new());
+        let f = NamedTempFile::new().unwrap();
+        check_store(&JsonLinesStore::open(f.path()).unwrap());
+    }
+
+    #[test]
+    fn store_is_chosen_by_extension() {
+        let dir = TempDir::new().unwrap();
+        let jsonl = dir.path().join("usage.jsonl");
+        db::open_store(&jsonl).unwrap().record_usage("app", 100, 60).unwrap();
+        assert!(fs::read_to_string(&jsonl).unwrap().starts_with("{\"app\":\"app\""));
+        let sqlite = dir.path().join("usage.db");
+        db::open_store(&sqlite).unwrap().record_usage("app", 100, 60).unwrap();
+        assert_eq!(60, db::connect_sqlite(&sqlite).unwrap().get_usage("app", 0, 1000).unwrap());
+    }
+
+    fn check_store(store: &dyn UsageStore) {
+        for (app_key, timestamp) in USAGES {
+            store.record_usage(app_key, *timestamp, 60).unwrap();
+        }
+        check_expected_usage(store);
+        let session = store.start_session("s1", 100, 100, Some(42)).unwrap();
+        store.extend_session(session, 130).unwrap();
+        store.extend_session(session, 160).unwrap();
+        assert_eq!(60, store.get_usage("s1", 0, 1000).unwrap());
+        assert_eq!(11, store.get_usage("s1", 150, 1000).unwrap());
+        store.record_usage("s1", 200, 40).unwrap();
+        assert_eq!(40, store.get_usage("s1", 200, 1000).unwrap());
+        assert_eq!(100, store.get_usage("s1", 0, 1000).unwrap());
+        assert_eq!(340, store.get_total_usage(&["a1", "a2", "s1"], 0, 1000).unwrap());
+        assert_eq!(0, store.get_total_usage(&[], 0, 1000).unwrap());
+        assert!(store.record_usage("s1", 10, 20).is_err());
+
+        // a1 has sessions 40..100, 140..200 and 240..300
+        let stats = |app_keys: &[&str], from, to| store.get_usage_stats(app_keys, from, to)
+            .unwrap();
+        let expected = UsageStats { total: 180, sessions: 3, longest_session: 60 };
+        assert_eq!(expected, stats(&["a1"], 0, 1000));
+        let expected = UsageStats { total: 60, sessions: 1, longest_session: 60 };
+        assert_eq!(expected, stats(&["a1"], 90, 190));
+        let expected = UsageStats { total: 340, sessions: 6, longest_session: 60 };
+        assert_eq!(expected, stats(&["a1", "a2", "s1"], 0, 1000));
+        assert_eq!(UsageStats::default(), stats(&["a1"], 101, 199));
+        assert_eq!(UsageStats::default(), stats(&[], 0, 1000));
+        assert_eq!(vec!["a1", "a2", "s1"], store.app_keys().unwrap());
+
+        let session = |app: &str, start, end, pid| Session { app: app.to_string(), start, end, pid };
+        let sessions = |filter: SessionFilter| store.sessions(&filter).unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let filter = SessionFilter { app_key: Some("a1".to_string()), from: Some(90), to: Some(250) };
+        assert_eq!(
+            vec![session("a1", 90, 100, None), session("a1", 140, 200, None), session("a1", 240, 250, None)],
+            sessions(filter)
+        );
+        let all = sessions(SessionFilter::default());
+        // Usage of another step than the session's doesn't extend it
+        assert_eq!(6, all.len());
+        assert!(all.contains(&session("s1", 100, 160, Some(42))), "{:?}", all);
+        assert!(all.contains(&session("s1", 160, 200, None)), "{:?}", all);
+        assert!(all.windows(2).all(|pair| pair[0].start <= pair[1].start), "{:?}", all);
+
+        let imported = store.import_sessions(&[
+            session("a1", 150, 180, None),
+            session("a1", 300, 360, Some(7)),
+            session("a3", 0, 10, None),
+            session("a3", 2, 8, None)
+        ]).unwrap();
+        assert_eq!(ImportSummary { imported: 2, merged: 0, duplicates: 2 }, imported);
+        assert_eq!(240, store.get_usage("a1", 0, 1000).unwrap());
+        assert_eq!(10, store.get_usage("a3", 0, 1000).unwrap());
+        assert!(sessions(SessionFilter::default()).contains(&session("a1", 300, 360, Some(7))));
+        // Importing again only finds duplicates
+        let imported = store.import_sessions(&[session("a3", 0, 10, None)]).unwrap();
+        assert_eq!(ImportSummary { imported: 0, merged: 0, duplicates: 1 }, imported);
+        // Nothing is imported if any session is invalid
+        match store.import_sessions(&[session("a4", 0, 10, None), session("a4", 20, 10, None)]) {
+            Err(Error::InvalidDataError(message)) =>
+                assert!(message.starts_with("Session 2:"), "{}", message),
+            other => panic!("Expected invalid data error, got {:?}", other)
+        }
+        assert!(store.import_sessions(&[session("", 0, 10, None)]).is_err());
+        assert_eq!(vec!["a1", "a2", "a3", "s1"], store.app_keys().unwrap());
+    }
+
+    #[test]
+    fn overlapping_imports_are_merged() {
+        check_merged_imports(&db::open_in_memory().unwrap());
+        check_merged_imports(&MemoryStore::new());
+        let f = NamedTempFile::new().unwrap();
+        check_merged_imports(&JsonLinesStore::open(f.path()).unwrap());
+    }
+
+    fn check_merged_imports(store: &dyn UsageStore) {
+        let session = |app: &str, start, end, pid| Session { app: app.to_string(), start, end, pid };
+        let summary = |imported, merged, duplicates| ImportSummary { imported, merged, duplicates };
+        let stats = |app_key| store.get_usage_stats(&[app_key], 0, 10000).unwrap();
+        store.import_sessions(&[session("firefox", 1000, 2000, Some(42))]).unwrap();
+        // The same session, exported again after it went on
+        let imported = store.import_sessions(&[session("firefox", 1000, 3000, Some(42))]).unwrap();
+        assert_eq!(summary(0, 1, 0), imported);
+        assert_eq!(UsageStats { total: 2000, sessions: 1, longest_session: 2000 }, stats("firefox"));
+        let imported = store.import_sessions(&[
+            session("firefox", 500, 1200, Some(42)),
+            session("firefox", 2500, 4000, Some(42)),
+            session("firefox", 5000, 6000, None),
+            session("discord", 1000, 2000, None)
+        ]).unwrap();
+        assert_eq!(summary(2, 2, 0), imported);
+        assert_eq!(4500, stats("firefox").total);
+        // Fills the gap between two stored sessions
+        let imported = store.import_sessions(&[session("firefox", 3500, 5500, None)]).unwrap();
+        assert_eq!(summary(0, 1, 0), imported);
+        assert_eq!(UsageStats { total: 5500, sessions: 1, longest_session: 5500 }, stats("firefox"));
+        assert_eq!(UsageStats { total: 1000, sessions: 1, longest_session: 1000 }, stats("discord"));
+        let imported = store.import_sessions(&[session("firefox", 500, 6000, Some(42))]).unwrap();
+        assert_eq!(summary(0, 0, 1), imported);
+    }
+
+    #[allow(clippy::expect_fun_call)]
+    fn check_expected_usage(db: &dyn UsageStore) {
+        for (app_key, from, to, expected_usage) in EXPECTED_USAGE {
             let param_str = format!(
                 "app_key={}, from={}, to={}, expected_usage={}",
                 app_key, from, to, expected_usage
             );
-            let u = db.get_usage(app_key, from, to)
+            let u = db.get_usage(app_key, *from, *to)
                 .expect(&format!("Could not get usage for params: {}",
                                 param_str));
-            assert_eq!(expected_usage, u, "{}", param_str);
+            assert_eq!(*expected_usage, u, "{}", param_str);
         }
+    }
+
+    fn session_count(db: &Db) -> i64 {
+        db.connection.query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn total_usage_sums_over_all_apps() {
+        let db = db::open_in_memory().unwrap();
+        db.record_usage("a1", 100, 60).unwrap();
+        db.record_usage("a2", 150, 60).unwrap();
+        db.record_usage("a3", 200, 60).unwrap();
+        db.record_usage("a1", 300, 60).unwrap();
+        let no_apps: &[&str] = &[];
+        assert_eq!(180, db.get_total_usage(&["a1", "a2"], 0, 1000).unwrap());
+        assert_eq!(120, db.get_total_usage(&["a1", "a2"], 120, 1000).unwrap());
+        assert_eq!(60, db.get_total_usage(&["a3", "unknown"], 0, 1000).unwrap());
+        assert_eq!(0, db.get_total_usage(no_apps, 0, 1000).unwrap());
+    }
+
+    #[test]
+    fn sessions_are_read_in_batches() {
+        let db = db::open_in_memory().unwrap();
+        let count = SESSION_BATCH_SIZE as u64 * 2 + 10;
+        // Two apps with sessions at the same time, so that batches end
+        // between sessions with the same start
+        let sessions: Vec<Session> = (0..count)
+            .map(|i| Session {
+                app: format!("a{}", i % 2),
+                start: i / 2 * 10,
+                end: i / 2 * 10 + 5,
+                pid: None
+            })
+            .collect();
+        db.import_sessions(&sessions).unwrap();
+        let read = db.sessions(&SessionFilter::default()).unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(sessions, read);
+        let filter = SessionFilter { app_key: Some("a1".to_string()), ..Default::default() };
+        assert_eq!(count as usize / 2, db.sessions(&filter).unwrap().count());
+    }
+
+    #[test]
+    fn file_dbs_use_wal_mode() {
+        let f = tmpf();
+        let db = db::connect_sqlite(f.path()).unwrap();
+        let mode: String = db.connection.query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!("wal", mode);
+    }
 
+    #[test]
+    fn only_migrated_dbs_switch_to_wal_mode() {
+        let f = tmpf();
+        let journal_mode = || -> String {
+            Connection::open(f.path()).unwrap()
+                .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!("delete", journal_mode());
+        let db = db::connect_sqlite_unmigrated(f.path()).unwrap();
+        assert_eq!(migrations::LATEST_VERSION as usize, db.migrate(true).unwrap().len());
+        assert_eq!(0, db.schema_version().unwrap());
+        assert_eq!("delete", journal_mode());
+        // A db of a newer release is refused as it is
+        db.connection.execute_batch("PRAGMA user_version = 100").unwrap();
+        assert!(matches!(db.migrate(false), Err(Error::NewerSchemaError { .. })));
+        assert_eq!("delete", journal_mode());
+        db.connection.execute_batch("PRAGMA user_version = 0").unwrap();
+        db.migrate(false).unwrap();
+        assert_eq!("wal", journal_mode());
+    }
+
+    #[test]
+    fn only_new_dbs_are_migrated_by_connect_sqlite_current() {
+        let f = tmpf();
+        let db = db::connect_sqlite_current(f.path()).unwrap();
+        assert_eq!(migrations::LATEST_VERSION, db.schema_version().unwrap());
+        let f = tmpf();
+        Connection::open(f.path()).unwrap().execute_batch(
+            "CREATE TABLE usage (app_key TEXT NOT NULL, timestamp INT, duration INT);
+            PRAGMA user_version = 1;"
+        ).unwrap();
+        match db::connect_sqlite_current(f.path()) {
+            Err(Error::OutdatedSchemaError { version, latest }) => {
+                assert_eq!(1, version);
+                assert_eq!(migrations::LATEST_VERSION, latest);
+            },
+            other => panic!("Expected outdated schema error, got {:?}", other.map(|_| ()))
+        }
+        assert_eq!(1, db::connect_sqlite_unmigrated(f.path()).unwrap().schema_version().unwrap());
+    }
+
+    /// Set for the processes that `concurrent_writers_lose_no_usage` starts
+    const STRESS_DB_VAR: &str = "LEAKBUSTER_STRESS_DB";
+    /// All writers record usage of the same app, like several `run`s of it
+    const STRESS_APP: &str = "app";
+    const STRESS_WRITERS: usize = 4;
+    /// Seconds of usage that each writer records
+    const STRESS_TICKS: u64 = 300;
+
+    #[test]
+    fn concurrent_writers_lose_no_usage() {
+        let f = tmpf();
+        db::connect_sqlite(f.path()).unwrap();
+        let exe = env::current_exe().unwrap();
+        let writers: Vec<_> = (0..STRESS_WRITERS).map(|_| {
+            Command::new(&exe)
+                .args(["--exact", "db::test::stress_test_writer"])
+                .env(STRESS_DB_VAR, f.path())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap()
+        }).collect();
+        for writer in writers {
+            let output = writer.wait_with_output().unwrap();
+            assert!(
+                output.status.success(),
+                "Writer failed: {}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+        let db = db::connect_sqlite(f.path()).unwrap();
+        assert_eq!(
+            STRESS_WRITERS as u64 * STRESS_TICKS,
+            db.get_usage(STRESS_APP, 0, 10000).unwrap()
+        );
+        assert_eq!(STRESS_WRITERS as i64 * 6, session_count(&db));
+    }
+
+    /// One of the processes of `concurrent_writers_lose_no_usage`. Does
+    /// nothing when run as part of the test suite.
+    #[test]
+    fn stress_test_writer() {
+        let path = match env::var(STRESS_DB_VAR) {
+            Ok(path) => path,
+            _ => return
+        };
+        let db = db::connect_sqlite(path).unwrap();
+        let mut recorder = UsageRecorder::new(STRESS_APP, Some(process::id()));
+        for tick in 1..=STRESS_TICKS {
+            // Makes 6 sessions, to have writers insert as well as update
+            if tick % 50 == 1 {
+                recorder.pause();
+            }
+            // Failed writes are retried along with the next one
+            let _ = recorder.record(&db, 1000 + tick, 1);
+            let _ = db.get_usage(STRESS_APP, 0, 10000);
+        }
+        for _ in 0..100 {
+            if recorder.flush(&db).is_ok() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Could not write {} s of usage", recorder.unwritten_seconds());
     }
 
     fn tmpf() -> NamedTempFile {
@@ -0,0 +1,306 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::db::{
+    usage_start, usage_within, validate_all, Error, ImportSummary, Result, Session, SessionFilter,
+    SessionId, UsageStats, UsageStore
+};
+
+/// Keeps usage in a plain-text file, with one JSON object per line for each
+/// interval that an app was used, e.g.
+///
+/// ```text
+/// {"app":"firefox","start":1638622611,"end":1638622612,"pid":4242}
+/// ```
+///
+/// Lines are only ever appended, so the file diffs well, and several
+/// leakbuster processes can write to it at the same time. Sessions aren't
+/// stored as such, extending one appends the interval since its last end.
+/// Usage that was recorded in steps of other than 1 s has a `step`, see
+/// `db::usage_within`.
+pub struct JsonLinesStore {
+    path: PathBuf,
+    file: File,
+    /// Sessions started by this store, indexed by their id
+    sessions: RefCell<Vec<OpenSession>>
+}
+
+struct OpenSession {
+    app_key: String,
+    end: u64,
+    pid: Option<u32>
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Line {
+    app: String,
+    start: u64,
+    end: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    step: Option<u64>
+}
+
+impl Line {
+    fn step(&self) -> u64 {
+        self.step.unwrap_or(1)
+    }
+}
+
+impl JsonLinesStore {
+    /// Opens the file, creating it if necessary
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JsonLinesStore> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(JsonLinesStore { path, file, sessions: RefCell::new(vec![]) })
+    }
+
+    fn append(&self, line: &Line) -> Result<()> {
+        self.append_all(std::slice::from_ref(line))
+    }
+
+    fn append_all(&self, lines: &[Line]) -> Result<()> {
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(&serde_json::to_string(line).map_err(|e| Error::InvalidDataError(
+                format!("Could not serialize usage: {}", e)
+            ))?);
+            text.push('\n');
+        }
+        // A single write, so that lines of other processes don't end up in
+        // the middle of it
+        (&self.file).write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    fn lines(&self) -> Result<Vec<Line>> {
+        let mut lines = vec![];
+        for (number, text) in BufReader::new(File::open(&self.path)?).lines().enumerate() {
+            let text = text?;
+            if text.trim().is_empty() {
+                continue;
+            }
+            lines.push(serde_json::from_str(&text).map_err(|e| Error::InvalidDataError(
+                format!("Line {} of {} is malformed: {}", number + 1, self.path.display(), e)
+            ))?);
+        }
+        Ok(lines)
+    }
+
+    /// Sessions are the runs of intervals of the same app and process that
+    /// touch or overlap each other. Intervals that were recorded without a
+    /// process join whichever session they touch, like in the SQLite db.
+    /// Intervals with steps of other than 1 s only join a session of the
+    /// same step that ends where they start. Returns the sessions along with
+    /// their steps.
+    fn sessions_of<F: Fn(&Line) -> bool>(&self, include: F) -> Result<Vec<(Session, u64)>> {
+        let mut lines: Vec<Line> = self.lines()?.into_iter().filter(include).collect();
+        lines.sort_by_key(|line| line.start);
+        let mut sessions: Vec<(Session, u64)> = vec![];
+        for line in lines {
+            let step = line.step();
+            let touching = sessions.iter_mut().rev().find(|(session, session_step)| {
+                session.app == line.app
+                    && *session_step == step
+                    && (line.start == session.end || (step == 1 && line.start < session.end))
+                    && (line.pid.is_none() || session.pid == line.pid)
+            });
+            match touching {
+                Some((session, _)) => session.end = session.end.max(line.end),
+                None => sessions.push((Session {
+                    app: line.app,
+                    start: line.start,
+                    end: line.end,
+                    pid: line.pid
+                }, step))
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+impl UsageStore for JsonLinesStore {
+    fn start_session(&self, app_key: &str, start: u64, end: u64, pid: Option<u32>)
+        -> Result<SessionId> {
+        self.append(&Line { app: app_key.to_string(), start, end, pid, step: None })?;
+        let mut sessions = self.sessions.borrow_mut();
+        sessions.push(OpenSession { app_key: app_key.to_string(), end, pid });
+        Ok(SessionId(sessions.len() as i64 - 1))
+    }
+
+    fn extend_session(&self, session: SessionId, end: u64) -> Result<()> {
+        let mut sessions = self.sessions.borrow_mut();
+        let open = sessions.get_mut(session.0 as usize).ok_or_else(|| Error::InvalidDataError(
+            format!("Unknown session: {:?}", session)
+        ))?;
+        if end < open.end {
+            return Err(Error::InvalidDataError(format!(
+                "Sessions can only be extended, not shortened from {} to {}", open.end, end
+            )));
+        }
+        if end > open.end {
+            self.append(&Line {
+                app: open.app_key.clone(),
+                start: open.end,
+                end,
+                pid: open.pid,
+                step: None
+            })?;
+            open.end = end;
+        }
+        Ok(())
+    }
+
+    fn record_usage(&self, app_key: &str, timestamp: u64, duration: u64) -> Result<()> {
+        let start = usage_start(timestamp, duration)?;
+        self.append(&Line {
+            app: app_key.to_string(),
+            start,
+            end: timestamp,
+            pid: None,
+            step: Some(duration).filter(|step| *step != 1)
+        })
+    }
+
+    fn get_total_usage(&self, app_keys: &[&str], from: u64, to: u64) -> Result<u64> {
+        Ok(self.lines()?.iter()
+            .filter(|line| app_keys.contains(&line.app.as_str()))
+            .map(|line| usage_within(line.start, line.end, line.step(), from, to))
+            .sum())
+    }
+
+    fn get_usage_stats(&self, app_keys: &[&str], from: u64, to: u64) -> Result<UsageStats> {
+        let sessions = self.sessions_of(|line| app_keys.contains(&line.app.as_str()))?;
+        let sessions = sessions.iter().map(|(session, step)| (session.start, session.end, *step));
+        Ok(UsageStats::of_sessions(sessions, from, to))
+    }
+
+    fn app_keys(&self) -> Result<Vec<String>> {
+        let keys: BTreeSet<String> = self.lines()?.into_iter().map(|line| line.app).collect();
+        Ok(keys.into_iter().collect())
+    }
+
+    fn sessions<'a>(&'a self, filter: &SessionFilter)
+        -> Result<Box<dyn Iterator<Item = Result<Session>> + 'a>> {
+        let sessions = self.sessions_of(|line| {
+            filter.app_key.iter().all(|key| *key == line.app)
+        })?;
+        let sessions = sessions.into_iter().map(|(session, _)| session);
+        Ok(Box::new(filter.apply_all(sessions).into_iter().map(Ok)))
+    }
+
+    /// Each imported session is appended as one line, all of them at once.
+    /// Of a session that overlaps stored ones, only the parts that they
+    /// don't cover are appended, with the process of the first of them, so
+    /// the lines join that session when they are read.
+    fn import_sessions(&self, sessions: &[Session]) -> Result<ImportSummary> {
+        validate_all(sessions)?;
+        let mut stored: Vec<Session> = self.sessions_of(|_| true)?.into_iter()
+            .map(|(session, _)| session)
+            .collect();
+        let mut lines = vec![];
+        let mut summary = ImportSummary::default();
+        for session in sessions {
+            if stored.iter().any(|other| session.is_covered_by(other)) {
+                summary.duplicates += 1;
+                continue;
+            }
+            let (mut overlapping, rest): (Vec<Session>, Vec<Session>) = stored.into_iter()
+                .partition(|other| session.overlaps(other));
+            stored = rest;
+            overlapping.sort_by_key(|other| other.start);
+            let pid = match overlapping.first() {
+                Some(first) => first.pid,
+                None => session.pid
+            };
+            let mut merged = Session { pid, ..session.clone() };
+            let mut uncovered_from = session.start;
+            for other in &overlapping {
+                if other.start > uncovered_from {
+                    lines.push(Line {
+                        app: session.app.clone(),
+                        start: uncovered_from,
+                        end: other.start,
+                        pid,
+                        step: None
+                    });
+                }
+                uncovered_from = uncovered_from.max(other.end);
+                merged.start = merged.start.min(other.start);
+                merged.end = merged.end.max(other.end);
+            }
+            if uncovered_from < session.end || overlapping.is_empty() {
+                lines.push(Line {
+                    app: session.app.clone(),
+                    start: uncovered_from,
+                    end: session.end,
+                    pid,
+                    step: None
+                });
+            }
+            if overlapping.is_empty() {
+                summary.imported += 1;
+            } else {
+                summary.merged += 1;
+            }
+            stored.push(merged);
+        }
+        self.append_all(&lines)?;
+        Ok(summary)
+    }
+}
+
+#[allow(unused_imports, dead_code)]
+mod test {
+    use crate::db;
+    use crate::db::jsonl::*;
+
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn sessions_are_appended_as_intervals() {
+        let f = NamedTempFile::new().unwrap();
+        let store = JsonLinesStore::open(f.path()).unwrap();
+        let session = store.start_session("firefox", 100, 101, Some(42)).unwrap();
+        store.extend_session(session, 102).unwrap();
+        store.extend_session(session, 102).unwrap();
+        store.record_usage("discord", 110, 10).unwrap();
+        assert_eq!(
+            "{\"app\":\"firefox\",\"start\":100,\"end\":101,\"pid\":42}\n\
+            {\"app\":\"firefox\",\"start\":101,\"end\":102,\"pid\":42}\n\
+            {\"app\":\"discord\",\"start\":100,\"end\":110,\"step\":10}\n",
+            fs::read_to_string(f.path()).unwrap()
+        );
+        assert!(store.extend_session(session, 101).is_err());
+    }
+
+    #[test]
+    fn usage_of_other_writers_is_read() {
+        let f = NamedTempFile::new().unwrap();
+        let store = JsonLinesStore::open(f.path()).unwrap();
+        let other = JsonLinesStore::open(f.path()).unwrap();
+        let session = store.start_session("app", 100, 110, None).unwrap();
+        other.record_usage("app", 130, 10).unwrap();
+        store.extend_session(session, 120).unwrap();
+        assert_eq!(30, store.get_usage("app", 0, 1000).unwrap());
+        assert_eq!(30, other.get_usage("app", 0, 1000).unwrap());
+    }
+
+    #[test]
+    fn malformed_lines_are_reported() {
+        let f = NamedTempFile::new().unwrap();
+        fs::write(f.path(), "{\"app\":\"a1\",\"start\":100,\"end\":160}\n\nnot json\n").unwrap();
+        let store = JsonLinesStore::open(f.path()).unwrap();
+        match store.get_usage("a1", 0, 1000) {
+            Err(Error::InvalidDataError(message)) =>
+                assert!(message.starts_with("Line 3 of"), "{}", message),
+            other => panic!("Expected invalid data error, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::db;
+use crate::db::{
+    usage_start, usage_within, validate_all, Error, ImportSummary, Result, SessionFilter, SessionId,
+    UsageStats, UsageStore
+};
+
+/// Keeps usage in memory only, e.g. for tests
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: RefCell<BTreeMap<i64, Session>>
+}
+
+struct Session {
+    app_key: String,
+    start: u64,
+    end: u64,
+    pid: Option<u32>,
+    /// See `db::usage_within`
+    step: u64
+}
+
+impl Session {
+    fn exported(&self) -> db::Session {
+        db::Session { app: self.app_key.clone(), start: self.start, end: self.end, pid: self.pid }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn insert(&self, session: Session) -> SessionId {
+        let mut sessions = self.sessions.borrow_mut();
+        let id = sessions.keys().next_back().map_or(1, |id| id + 1);
+        sessions.insert(id, session);
+        SessionId(id)
+    }
+}
+
+impl UsageStore for MemoryStore {
+    fn start_session(&self, app_key: &str, start: u64, end: u64, pid: Option<u32>)
+        -> Result<SessionId> {
+        Ok(self.insert(Session { app_key: app_key.to_string(), start, end, pid, step: 1 }))
+    }
+
+    fn extend_session(&self, session: SessionId, end: u64) -> Result<()> {
+        match self.sessions.borrow_mut().get_mut(&session.0) {
+            Some(session) => {
+                session.end = end;
+                Ok(())
+            },
+            None => Err(Error::InvalidDataError(format!("Unknown session: {:?}", session)))
+        }
+    }
+
+    fn record_usage(&self, app_key: &str, timestamp: u64, duration: u64) -> Result<()> {
+        let start = usage_start(timestamp, duration)?;
+        let mut sessions = self.sessions.borrow_mut();
+        let adjacent = sessions.values_mut().find(|session| {
+            session.app_key == app_key && session.end == start && session.step == duration
+        });
+        match adjacent {
+            Some(session) => session.end = timestamp,
+            None => {
+                drop(sessions);
+                self.insert(Session {
+                    app_key: app_key.to_string(),
+                    start,
+                    end: timestamp,
+                    pid: None,
+                    step: duration
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn get_total_usage(&self, app_keys: &[&str], from: u64, to: u64) -> Result<u64> {
+        Ok(self.sessions.borrow().values()
+            .filter(|session| app_keys.contains(&session.app_key.as_str()))
+            .map(|session| usage_within(session.start, session.end, session.step, from, to))
+            .sum())
+    }
+
+    fn get_usage_stats(&self, app_keys: &[&str], from: u64, to: u64) -> Result<UsageStats> {
+        let sessions = self.sessions.borrow();
+        let sessions = sessions.values()
+            .filter(|session| app_keys.contains(&session.app_key.as_str()))
+            .map(|session| (session.start, session.end, session.step));
+        Ok(UsageStats::of_sessions(sessions, from, to))
+    }
+
+    fn app_keys(&self) -> Result<Vec<String>> {
+        let keys: BTreeSet<String> = self.sessions.borrow().values()
+            .map(|session| session.app_key.clone())
+            .collect();
+        Ok(keys.into_iter().collect())
+    }
+
+    fn sessions<'a>(&'a self, filter: &SessionFilter)
+        -> Result<Box<dyn Iterator<Item = Result<db::Session>> + 'a>> {
+        let sessions = filter.apply_all(self.sessions.borrow().values().map(Session::exported));
+        Ok(Box::new(sessions.into_iter().map(Ok)))
+    }
+
+    fn import_sessions(&self, sessions: &[db::Session]) -> Result<ImportSummary> {
+        validate_all(sessions)?;
+        let mut summary = ImportSummary::default();
+        for session in sessions {
+            let mut stored = self.sessions.borrow_mut();
+            if stored.values().any(|stored| session.is_covered_by(&stored.exported())) {
+                summary.duplicates += 1;
+                continue;
+            }
+            let overlapping: Vec<i64> = stored.iter()
+                .filter(|(_, stored)| session.overlaps(&stored.exported()))
+                .map(|(id, _)| *id)
+                .collect();
+            // The first overlapping session grows to cover the others
+            let mut merged = db::Session { pid: None, ..session.clone() };
+            for id in &overlapping {
+                if let Some(other) = stored.remove(id) {
+                    merged.start = merged.start.min(other.start);
+                    merged.end = merged.end.max(other.end);
+                    merged.pid = merged.pid.or(other.pid);
+                }
+            }
+            match overlapping.first() {
+                Some(id) => {
+                    stored.insert(*id, Session {
+                        app_key: merged.app,
+                        start: merged.start,
+                        end: merged.end,
+                        pid: merged.pid.or(session.pid),
+                        step: 1
+                    });
+                    summary.merged += 1;
+                },
+                None => {
+                    drop(stored);
+                    self.start_session(&session.app, session.start, session.end, session.pid)?;
+                    summary.imported += 1;
+                }
+            }
+        }
+        Ok(summary)
+    }
+}
@@ -0,0 +1,257 @@
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use std::collections::HashMap;
+
+use crate::db::{Error, Result};
+
+/// A change to the schema of the usage db
+pub struct Migration {
+    /// Shown to the user by `leakbuster db migrate`
+    pub description: &'static str,
+    apply: fn(&Transaction) -> rusqlite::Result<()>
+}
+
+/// All migrations, oldest first. After the first n migrations, the db is at
+/// schema version n, which is stored in SQLite's `user_version`. Migrations
+/// that were released must never change. Schema changes are made by
+/// appending a new one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Create the usage table",
+        apply: create_usage_table
+    },
+    Migration {
+        description: "Fold per-second usage rows into sessions",
+        apply: fold_usage_into_sessions
+    },
+];
+
+/// Schema version that this version of leakbuster reads and writes
+pub const LATEST_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Schema version of the db
+pub fn version(connection: &Connection) -> Result<u32> {
+    Ok(connection.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Applies the migrations that the db is missing, each in a transaction of
+/// its own, so a failing migration leaves the db at the previous version.
+/// With `dry_run`, they are applied in a single transaction, which is rolled
+/// back. Fails without touching the db if it was written by a newer version
+/// of leakbuster. Returns the versions that were reached, along with the
+/// migrations that reached them.
+pub fn migrate(connection: &Connection, dry_run: bool)
+    -> Result<Vec<(u32, &'static Migration)>> {
+    migrate_with(connection, MIGRATIONS, dry_run)
+}
+
+fn migrate_with<'a>(connection: &Connection, migrations: &'a [Migration], dry_run: bool)
+    -> Result<Vec<(u32, &'a Migration)>> {
+    let mut applied = vec![];
+    // Other processes may open the db at the same time, so the version is
+    // only read once the transaction holds the write lock
+    let mut tx = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+    loop {
+        let version = version(&tx)?;
+        let migration = match migrations.get(version as usize) {
+            Some(migration) => migration,
+            None if version as usize == migrations.len() => break,
+            None => return Err(Error::NewerSchemaError {
+                version,
+                supported: migrations.len() as u32
+            })
+        };
+        (migration.apply)(&tx)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", version + 1))?;
+        applied.push((version + 1, migration));
+        if !dry_run {
+            tx.commit()?;
+            tx = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        }
+    }
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+    Ok(applied)
+}
+
+/// The schema of leakbuster versions that didn't keep track of the schema
+/// version, which may have created the table already
+fn create_usage_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS usage (
+            app_key     TEXT NOT NULL,
+            timestamp   INT,
+            duration    INT
+        )")
+}
+
+/// Converts the rows of the `usage` table, which were written once per
+/// second, into sessions. Each row covers the `duration` seconds up to its
+/// `timestamp`, and counted whole in any window that its timestamp was in.
+/// Rows of the same app and duration that follow each other without a gap
+/// are merged into one session with that duration as its step, so the usage
+/// that each window counts stays the same. Rows that overlap, e.g. because
+/// two leakbuster processes tracked the app at once, start sessions of their
+/// own, as does a row that follows a gap.
+fn fold_usage_into_sessions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE sessions (
+            app_key     TEXT NOT NULL,
+            start       INT NOT NULL,
+            end         INT NOT NULL,
+            pid         INT,
+            step        INT NOT NULL DEFAULT 1
+        );
+        CREATE INDEX sessions_by_app ON sessions (app_key, start);")?;
+    let rows: Vec<(String, i64, i64, i64)> = tx.prepare(
+        "SELECT app_key, timestamp - duration, timestamp, duration FROM usage
+            WHERE timestamp IS NOT NULL AND duration > 0
+            ORDER BY app_key, timestamp"
+    )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut sessions: Vec<(String, i64, i64, i64)> = vec![];
+    // Sessions that may be extended, by their app, step and end
+    let mut open: HashMap<(String, i64, i64), Vec<usize>> = HashMap::new();
+    for (app_key, start, end, step) in rows {
+        let extended = open.get_mut(&(app_key.clone(), step, start)).and_then(Vec::pop);
+        let index = match extended {
+            Some(index) => {
+                sessions[index].2 = end;
+                index
+            },
+            None => {
+                sessions.push((app_key.clone(), start, end, step));
+                sessions.len() - 1
+            }
+        };
+        open.entry((app_key, step, end)).or_default().push(index);
+    }
+    let mut insert = tx.prepare(
+        "INSERT INTO sessions (app_key, start, end, step) VALUES (?1, ?2, ?3, ?4)"
+    )?;
+    for (app_key, start, end, step) in &sessions {
+        insert.execute(params![app_key, start, end, step])?;
+    }
+    tx.execute_batch("DROP TABLE usage")
+}
+
+#[allow(unused_imports, dead_code)]
+mod test {
+    use crate::db::migrations::*;
+
+    /// Usage of 60 s each, ending at the given timestamps
+    const USAGES: &[(&str, i64)] = &[("a1", 100), ("a2", 150), ("a1", 200), ("a1", 300)];
+
+    #[test]
+    fn migrates_new_db_to_latest_version() {
+        let connection = Connection::open_in_memory().unwrap();
+        let applied = migrate(&connection, false).unwrap();
+        let versions: Vec<u32> = applied.iter().map(|(version, _)| *version).collect();
+        assert_eq!((1..=LATEST_VERSION).collect::<Vec<u32>>(), versions);
+        assert_eq!(LATEST_VERSION, version(&connection).unwrap());
+        assert!(migrate(&connection, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrates_unversioned_db() {
+        // Written by a version that only ran CREATE TABLE IF NOT EXISTS
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS usage (
+                app_key     TEXT NOT NULL,
+                timestamp   INT,
+                duration    INT
+            )").unwrap();
+        insert_usage(&connection);
+        assert_eq!(0, version(&connection).unwrap());
+        assert_eq!(LATEST_VERSION as usize, migrate(&connection, false).unwrap().len());
+        let expected: Vec<(String, i64, i64)> = vec![
+            ("a1".to_string(), 40, 100),
+            ("a1".to_string(), 140, 200),
+            ("a1".to_string(), 240, 300),
+            ("a2".to_string(), 90, 150),
+        ];
+        assert_eq!(expected, sessions(&connection));
+    }
+
+    #[test]
+    fn migrates_from_version_1() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate_with(&connection, &MIGRATIONS[..1], false).unwrap();
+        insert_usage(&connection);
+        assert_eq!(1, version(&connection).unwrap());
+        let applied = migrate(&connection, false).unwrap();
+        assert_eq!(vec![2], applied.iter().map(|(version, _)| *version).collect::<Vec<u32>>());
+        assert_eq!(4, sessions(&connection).len());
+        assert_eq!(LATEST_VERSION, version(&connection).unwrap());
+    }
+
+    #[test]
+    fn dry_run_leaves_db_unchanged() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate_with(&connection, &MIGRATIONS[..1], false).unwrap();
+        insert_usage(&connection);
+        let applied = migrate(&connection, true).unwrap();
+        assert_eq!(LATEST_VERSION as usize - 1, applied.len());
+        assert_eq!(1, version(&connection).unwrap());
+        let rows: i64 = connection.query_row("SELECT COUNT(*) FROM usage", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(4, rows);
+    }
+
+    #[test]
+    fn failed_migration_keeps_previous_version() {
+        let connection = Connection::open_in_memory().unwrap();
+        let migrations = [
+            Migration { description: "works", apply: |tx| tx.execute_batch("CREATE TABLE a (x INT)") },
+            Migration { description: "fails", apply: |tx| {
+                tx.execute_batch("CREATE TABLE b (x INT)")?;
+                tx.execute_batch("CREATE TABLE a (x INT)")
+            }},
+        ];
+        assert!(matches!(
+            migrate_with(&connection, &migrations, false),
+            Err(Error::RusqliteError(_))
+        ));
+        assert_eq!(1, version(&connection).unwrap());
+        let tables: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'b'", [], |row| row.get(0)
+        ).unwrap();
+        assert_eq!(0, tables, "Changes of the failed migration should be rolled back");
+    }
+
+    #[test]
+    fn refuses_db_of_newer_version() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(&format!("PRAGMA user_version = {}", LATEST_VERSION + 1)).unwrap();
+        match migrate(&connection, false) {
+            Err(Error::NewerSchemaError { version, supported }) => {
+                assert_eq!(LATEST_VERSION + 1, version);
+                assert_eq!(LATEST_VERSION, supported);
+            },
+            other => panic!("Expected newer schema error, got {:?}", other.map(|a| a.len()))
+        }
+        assert_eq!(LATEST_VERSION + 1, version(&connection).unwrap());
+    }
+
+    fn insert_usage(connection: &Connection) {
+        for (app_key, timestamp) in USAGES {
+            connection.execute(
+                "INSERT INTO usage (app_key, timestamp, duration) VALUES (?1, ?2, 60)",
+                params![app_key, timestamp]
+            ).unwrap();
+        }
+    }
+
+    fn sessions(connection: &Connection) -> Vec<(String, i64, i64)> {
+        connection.prepare("SELECT app_key, start, end FROM sessions ORDER BY app_key, start")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+}
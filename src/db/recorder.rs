@@ -0,0 +1,147 @@
+use crate::db::{Result, SessionId, UsageStore};
+
+/// Records the usage of an app while it runs, as sessions that are extended
+/// whenever more usage is recorded. Writes that fail, e.g. because another
+/// process held a lock on the db for too long, are kept in memory and made
+/// along with the next write, so no usage is lost.
+pub struct UsageRecorder {
+    app_key: String,
+    /// Process that the app was recognized by
+    pid: Option<u32>,
+    /// Sessions that ended, but weren't completely written yet
+    unwritten: Vec<RecordedSession>,
+    /// The session that is extended by the next recorded usage
+    current: Option<RecordedSession>
+}
+
+#[derive(Debug)]
+struct RecordedSession {
+    start: u64,
+    end: u64,
+    /// The session in the db, and where it ends there. None until the
+    /// session was written for the first time.
+    written: Option<(SessionId, u64)>
+}
+
+impl RecordedSession {
+    fn is_written(&self) -> bool {
+        matches!(self.written, Some((_, end)) if end == self.end)
+    }
+
+    fn write(&mut self, db: &dyn UsageStore, app_key: &str, pid: Option<u32>) -> Result<()> {
+        let id = match self.written {
+            Some((_, end)) if end == self.end => return Ok(()),
+            Some((id, _)) => {
+                db.extend_session(id, self.end)?;
+                id
+            },
+            None => db.start_session(app_key, self.start, self.end, pid)?
+        };
+        self.written = Some((id, self.end));
+        Ok(())
+    }
+
+    fn unwritten_seconds(&self) -> u64 {
+        let written_end = self.written.map(|(_, end)| end).unwrap_or(self.start);
+        self.end - written_end
+    }
+}
+
+impl UsageRecorder {
+    pub fn new(app_key: &str, pid: Option<u32>) -> UsageRecorder {
+        UsageRecorder { app_key: app_key.to_string(), pid, unwritten: vec![], current: None }
+    }
+
+    /// Records that the app was used for the `duration` seconds up to
+    /// `timestamp`. This extends the current session, unless there is a gap
+    /// since its end, e.g. because the computer was suspended.
+    pub fn record(&mut self, db: &dyn UsageStore, timestamp: u64, duration: u64) -> Result<()> {
+        match &mut self.current {
+            // Recording may be late by up to one interval
+            Some(session) if session.end <= timestamp && timestamp <= session.end + 2 * duration =>
+                session.end = timestamp,
+            _ => {
+                self.pause();
+                self.current = Some(RecordedSession {
+                    start: timestamp.saturating_sub(duration),
+                    end: timestamp,
+                    written: None
+                });
+            }
+        }
+        self.flush(db)
+    }
+
+    /// Ends the current session, so that the next recorded usage starts a
+    /// new one.
+    pub fn pause(&mut self) {
+        if let Some(session) = self.current.take() {
+            if !session.is_written() {
+                self.unwritten.push(session);
+            }
+        }
+    }
+
+    /// Writes all usage that wasn't written yet.
+    pub fn flush(&mut self, db: &dyn UsageStore) -> Result<()> {
+        for session in self.unwritten.iter_mut().chain(self.current.iter_mut()) {
+            session.write(db, &self.app_key, self.pid)?;
+        }
+        self.unwritten.clear();
+        Ok(())
+    }
+
+    /// Seconds of usage that haven't been written yet
+    pub fn unwritten_seconds(&self) -> u64 {
+        self.unwritten.iter().chain(self.current.iter())
+            .map(RecordedSession::unwritten_seconds)
+            .sum()
+    }
+}
+
+#[allow(unused_imports, dead_code)]
+mod test {
+    use crate::db;
+    use crate::db::recorder::*;
+
+    use std::time::Duration;
+    use rusqlite::Connection;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn sessions_are_extended_until_paused() {
+        let db = db::open_in_memory().unwrap();
+        let mut recorder = UsageRecorder::new("app", Some(42));
+        for timestamp in [101, 102, 104] {
+            recorder.record(&db, timestamp, 1).unwrap();
+        }
+        recorder.pause();
+        recorder.record(&db, 105, 1).unwrap();
+        assert_eq!(5, db.get_usage("app", 0, 1000).unwrap());
+        assert_eq!(2, db.get_usage("app", 104, 1000).unwrap());
+        assert_eq!(0, recorder.unwritten_seconds());
+    }
+
+    #[test]
+    fn failed_writes_are_retried() {
+        let f = NamedTempFile::new().unwrap();
+        let db = db::connect_sqlite(f.path()).unwrap();
+        db.connection.busy_timeout(Duration::from_millis(10)).unwrap();
+        let mut recorder = UsageRecorder::new("app", None);
+        recorder.record(&db, 101, 1).unwrap();
+
+        let other = Connection::open(f.path()).unwrap();
+        other.execute_batch("BEGIN IMMEDIATE").unwrap();
+        assert!(recorder.record(&db, 102, 1).is_err());
+        assert!(recorder.record(&db, 103, 1).is_err());
+        recorder.pause();
+        assert!(recorder.record(&db, 110, 1).is_err());
+        assert_eq!(3, recorder.unwritten_seconds());
+        other.execute_batch("COMMIT").unwrap();
+
+        recorder.record(&db, 111, 1).unwrap();
+        assert_eq!(0, recorder.unwritten_seconds());
+        assert_eq!(5, db.get_usage("app", 0, 1000).unwrap());
+        assert_eq!(2, db.get_usage("app", 104, 1000).unwrap());
+    }
+}
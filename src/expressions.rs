@@ -1,123 +1,213 @@
+pub mod lint;
 pub mod parser;
+pub mod typecheck;
 pub use crate::expressions::parser::{parse_duration, parse_condition, Duration};
 
+use crate::calendar;
+use crate::calendar::{DateRange, WeekStart};
+use crate::clock::Clock;
 use crate::db;
-use crate::db::Db;
-use crate::expressions::parser::{Condition, ConditionWeekday, TimeUnit};
+use crate::db::UsageStore;
+use crate::expressions::parser::{
+    CompareOp, Condition, DurationExpr, Span, Subject, Usage, Window
+};
 
 use chrono::prelude::*;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::result;
 
-struct EvalContext<'a, Z: TimeZone> {
-    pub db: &'a Db,
+/// Maps group ids to the ids of their member apps
+pub type Groups = BTreeMap<String, Vec<String>>;
+
+/// Maps calendar names to their days, as read from their files
+pub type Calendars = BTreeMap<String, Vec<DateRange>>;
+
+struct EvalContext<'a, S: UsageStore + ?Sized, Z: TimeZone> {
+    pub db: &'a S,
     pub time: &'a DateTime<Z>,
     pub app_id: &'a str,
+    pub groups: &'a Groups,
+    pub calendars: &'a Calendars,
+    pub week_start: WeekStart,
 }
 
-pub fn check_condition(
+pub fn check_condition<S: UsageStore + ?Sized>(
     condition: &Condition,
-    db: &Db,
+    db: &S,
     app_id: &str,
-) -> Result<bool, db::Error> {
-    let time = Local::now();
+    groups: &Groups,
+    calendars: &Calendars,
+    week_start: WeekStart,
+    clock: &dyn Clock,
+) -> Result<bool> {
+    let time = clock.now();
     let ctx = EvalContext {
         db,
         time: &time,
         app_id,
+        groups,
+        calendars,
+        week_start,
     };
     eval(&ctx, condition)
 }
 
-fn eval<Z: TimeZone>(
-    ctx: &EvalContext<Z>,
+fn eval<S: UsageStore + ?Sized, Z: TimeZone>(
+    ctx: &EvalContext<S, Z>,
     c: &Condition,
-) -> Result<bool, db::Error> {
+) -> Result<bool> {
     match c {
         Condition::And(c_and) => Ok(eval(ctx, &c_and.c1)? && eval(ctx, &c_and.c2)?),
         Condition::Or(c_or) => Ok(eval(ctx, &c_or.c1)? || eval(ctx, &c_or.c2)?),
         Condition::Not(c_not) => Ok(!eval(ctx, &c_not.c)?),
-        Condition::Weekday(cwd) => match ctx.time.weekday() {
-            Weekday::Mon => Ok(*cwd == ConditionWeekday::Mon),
-            Weekday::Tue => Ok(*cwd == ConditionWeekday::Tue),
-            Weekday::Wed => Ok(*cwd == ConditionWeekday::Wed),
-            Weekday::Thu => Ok(*cwd == ConditionWeekday::Thu),
-            Weekday::Fri => Ok(*cwd == ConditionWeekday::Fri),
-            Weekday::Sat => Ok(*cwd == ConditionWeekday::Sat),
-            Weekday::Sun => Ok(*cwd == ConditionWeekday::Sun) 
+        Condition::Weekday(days) => Ok(days.contains(ctx.time.weekday())),
+        Condition::Between(c_between) => {
+            let now = ctx.time.num_seconds_from_midnight();
+            let from = c_between.from.seconds_from_midnight();
+            let to = c_between.to.seconds_from_midnight();
+            if from <= to {
+                Ok(from <= now && now < to)
+            } else {
+                // The range wraps around midnight
+                Ok(from <= now || now < to)
+            }
+        }
+        Condition::Date(range) => Ok(range.contains(ctx.time.naive_local().date())),
+        Condition::Calendar(name) => {
+            let ranges = ctx.calendars.get(name)
+                .ok_or_else(|| Error::UnknownCalendar(name.to_string()))?;
+            let today = ctx.time.naive_local().date();
+            Ok(ranges.iter().any(|range| range.contains(today)))
         },
-        Condition::AtMostInSliding(c_in_window) => {
-            // We'll never have negative time stamps in a real use case
-            let ts: u64 = ctx.time.timestamp().try_into().unwrap();
-            let usage = ctx
-                .db
-                .get_usage(ctx.app_id, ts - c_in_window.window_size.seconds, ts)?;
-            Ok(usage < c_in_window.limit.seconds)
-        }
-        Condition::AtMostInThis(c_in_current) => {
-            let start_of_window = match c_in_current.time_unit {
-                TimeUnit::Second => ctx.time.clone(),
-                TimeUnit::Minute => ctx
-                    .time
-                    .with_nanosecond(0)
-                    .and_then(|t| t.with_second(0))
-                    .unwrap(),
-                TimeUnit::Hour => ctx
-                    .time
-                    .with_nanosecond(0)
-                    .and_then(|t| t.with_second(0))
-                    .and_then(|t| t.with_minute(0))
-                    .unwrap(),
-                TimeUnit::Day => ctx
-                    .time
-                    .with_nanosecond(0)
-                    .and_then(|t| t.with_second(0))
-                    .and_then(|t| t.with_minute(0))
-                    .and_then(|t| t.with_hour(0))
-                    .unwrap(),
-                TimeUnit::Week => ctx
-                    .time
-                    .with_nanosecond(0)
-                    .and_then(|t| t.with_second(0))
-                    .and_then(|t| t.with_minute(0))
-                    .and_then(|t| t.with_hour(0))
-                    .unwrap(),
-                TimeUnit::Month => ctx
-                    .time
-                    .with_nanosecond(0)
-                    .and_then(|t| t.with_second(0))
-                    .and_then(|t| t.with_minute(0))
-                    .and_then(|t| t.with_hour(0))
-                    .and_then(|t| t.with_day(0))
-                    .unwrap(),
-                TimeUnit::Year => ctx
-                    .time
-                    .with_nanosecond(0)
-                    .and_then(|t| t.with_second(0))
-                    .and_then(|t| t.with_minute(0))
-                    .and_then(|t| t.with_hour(0))
-                    .and_then(|t| t.with_day(0))
-                    .and_then(|t| t.with_month(0))
-                    .unwrap(),
-            };
-            let ts_now: u64 = ctx.time.timestamp().try_into().unwrap();
-            let ts_start: u64 = start_of_window.timestamp().try_into().unwrap();
-            let usage = ctx.db.get_usage(ctx.app_id, ts_start, ts_now)?;
-            Ok(usage < c_in_current.limit.seconds)
+        Condition::Compare(c_cmp) => {
+            let lhs = eval_duration(ctx, &c_cmp.lhs)?;
+            let rhs = eval_duration(ctx, &c_cmp.rhs)?;
+            Ok(match c_cmp.op {
+                CompareOp::Lt => lhs < rhs,
+                CompareOp::Le => lhs <= rhs,
+                CompareOp::Gt => lhs > rhs,
+                CompareOp::Ge => lhs >= rhs,
+                CompareOp::Eq => lhs == rhs,
+                CompareOp::Ne => lhs != rhs
+            })
         }
     }
 }
 
-#[allow(dead_code)]
+/// Evaluates a duration expression to a number of seconds. Subtraction can
+/// make the result negative.
+fn eval_duration<S: UsageStore + ?Sized, Z: TimeZone>(
+    ctx: &EvalContext<S, Z>,
+    d: &DurationExpr,
+) -> Result<i64> {
+    match d {
+        DurationExpr::Literal(span) => to_signed(span_seconds(ctx, span)?),
+        DurationExpr::Usage(usage) => to_signed(eval_usage(ctx, usage)?),
+        DurationExpr::Add(d_add) => eval_duration(ctx, &d_add.d1)?
+            .checked_add(eval_duration(ctx, &d_add.d2)?)
+            .ok_or_else(|| Error::Overflow(format!("Sum {} too large to evaluate", d))),
+        DurationExpr::Sub(d_sub) => eval_duration(ctx, &d_sub.d1)?
+            .checked_sub(eval_duration(ctx, &d_sub.d2)?)
+            .ok_or_else(|| Error::Overflow(format!("Difference {} too large to evaluate", d)))
+    }
+}
+
+fn to_signed(seconds: u64) -> Result<i64> {
+    seconds.try_into().map_err(|err| Error::Overflow(format!(
+        "Duration too large to evaluate ({:?})",
+        err
+    )))
+}
+
+/// Length of the span in seconds, counted back from the current time
+fn span_seconds<S: UsageStore + ?Sized, Z: TimeZone>(
+    ctx: &EvalContext<S, Z>,
+    span: &Span,
+) -> Result<u64> {
+    if let Some(duration) = span.fixed() {
+        return Ok(duration.seconds);
+    }
+    let too_long = || Error::Overflow(format!("Span {} too long to evaluate", span));
+    let start = calendar::months_before(ctx.time, span.months).ok_or_else(too_long)?;
+    let months: u64 = (ctx.time.clone() - start).num_seconds().try_into()
+        .map_err(|_| too_long())?;
+    months.checked_add(span.seconds).ok_or_else(too_long)
+}
+
+fn eval_usage<S: UsageStore + ?Sized, Z: TimeZone>(
+    ctx: &EvalContext<S, Z>,
+    usage: &Usage,
+) -> Result<u64> {
+    let (from, to) = window_bounds(ctx, &usage.window);
+    let usage = match &usage.subject {
+        Subject::Current => ctx.db.get_usage(ctx.app_id, from, to)?,
+        Subject::App(app_id) => ctx.db.get_usage(app_id, from, to)?,
+        Subject::Group(group_id) => {
+            let members = ctx.groups.get(group_id)
+                .ok_or_else(|| Error::UnknownGroup(group_id.to_string()))?;
+            let members: Vec<&str> = members.iter().map(String::as_str).collect();
+            ctx.db.get_total_usage(&members, from, to)?
+        }
+    };
+    Ok(usage)
+}
+
+/// Start and end of the window, as time stamps
+fn window_bounds<S: UsageStore + ?Sized, Z: TimeZone>(
+    ctx: &EvalContext<S, Z>,
+    window: &Window,
+) -> (u64, u64) {
+    // We'll never have negative time stamps in a real use case, only windows
+    // that reach back further than that
+    let timestamp = |time: DateTime<Z>| -> u64 { time.timestamp().try_into().unwrap_or(0) };
+    let now = timestamp(ctx.time.clone());
+    let current = |unit| timestamp(calendar::start_of(ctx.time, unit, ctx.week_start));
+    let earlier = |unit, count| calendar::start_of_earlier(ctx.time, unit, count, ctx.week_start)
+        .map_or(0, timestamp);
+    match window {
+        Window::Sliding(span) => match calendar::months_before(ctx.time, span.months) {
+            Some(start) => (timestamp(start).saturating_sub(span.seconds), now),
+            None => (0, now)
+        },
+        Window::This(unit) => (current(*unit), now),
+        Window::Last { count, unit } => (earlier(*unit, *count), now),
+        Window::Previous(unit) => (earlier(*unit, 1), current(*unit))
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    DbError(db::Error),
+    UnknownGroup(String),
+    UnknownCalendar(String),
+    Overflow(String)
+}
+
+impl From<db::Error> for Error {
+    fn from(err: db::Error) -> Error {
+        Error::DbError(err)
+    }
+}
+
+pub type Result<T, E = Error> = result::Result<T, E>;
+
+#[allow(dead_code, unused_imports)]
 mod test {
     use chrono::prelude::*;
+    use std::time::Duration;
 
+    use crate::calendar::{DateRange, WeekStart};
+    use crate::clock::{Clock, SimulatedClock};
     use crate::db;
-    use crate::db::Db;
+    use crate::db::UsageStore;
+    use crate::db::memory::MemoryStore;
     use crate::expressions;
+    use crate::expressions::{Calendars, Groups};
 
     #[test]
     fn no_expression_panics_on_empty_db() {
-        let db = db::open_in_memory().unwrap();
+        let db = MemoryStore::new();
         let time = Utc.ymd(2000, 3, 20).and_hms(12, 0, 0);
         let exprs = vec![
             "Mon",
@@ -130,22 +220,396 @@ mod test {
             "Mon and Tue",
             "Wed or Thu",
             "not Fri",
+            "between 09:00 and 17:30",
+            "between 22:00 and 06:00",
             "atmost 5 m in sliding 1 h",
-            "atmost 1 h in this week"
+            "atmost 1 h in this week",
+            "usage(this day) + usage(sliding 2 h) > 3 h",
+            "usage(this week) <= 10 h - usage(this day)",
+            "usage(this day) >= 30 m"
         ];
         for e in exprs {
             check_str_condition(&db, &time, "app", e).unwrap();
         }
     }
 
-    fn check_str_condition<Z: TimeZone>(
-        db: &Db,
+    #[test]
+    fn weekday_sets_contain_the_current_day() {
+        let db = MemoryStore::new();
+        // Monday to Sunday
+        let days: Vec<_> = (6..=12).map(|d| Local.ymd(2021, 12, d).and_hms(12, 0, 0)).collect();
+        let test_cases = vec![
+            ("Mon", "X      "),
+            ("Mon..Fri", "XXXXX  "),
+            ("weekday", "XXXXX  "),
+            ("Sat..Mon", "X    XX"),
+            ("{Sat, Sun}", "     XX"),
+            ("today is weekend", "     XX"),
+            ("{Tue, Thu..Fri}", " X XX  "),
+            ("not {Tue, Thu..Fri}", "X X  XX"),
+        ];
+        for (expr, expected) in test_cases {
+            let actual: String = days.iter()
+                .map(|day| match check_str_condition(&db, day, "app", expr).unwrap() {
+                    true => 'X',
+                    false => ' '
+                })
+                .collect();
+            assert_eq!(expected, actual, "Evaluating '{}'", expr);
+        }
+    }
+
+    #[test]
+    fn between_is_evaluated_against_time_of_day() {
+        let db = MemoryStore::new();
+        let test_cases = vec![
+            ("between 09:00 and 17:30", (12, 0, 0), true),
+            ("between 09:00 and 17:30", (9, 0, 0), true),
+            ("between 09:00 and 17:30", (8, 59, 59), false),
+            ("between 09:00 and 17:30", (17, 29, 59), true),
+            ("between 09:00 and 17:30", (17, 30, 0), false),
+            ("between 22:00 and 06:00", (23, 0, 0), true),
+            ("between 22:00 and 06:00", (0, 0, 0), true),
+            ("between 22:00 and 06:00", (5, 59, 59), true),
+            ("between 22:00 and 06:00", (6, 0, 0), false),
+            ("between 22:00 and 06:00", (12, 0, 0), false),
+            ("between 10:00 and 10:00", (10, 0, 0), false),
+        ];
+        for (expr, (h, m, s), expected) in test_cases {
+            let time = Utc.ymd(2000, 3, 20).and_hms(h, m, s);
+            assert_eq!(
+                expected,
+                check_str_condition(&db, &time, "app", expr).unwrap(),
+                "Evaluating '{}' at {}", expr, time
+            );
+        }
+    }
+
+    #[test]
+    fn usage_arithmetic_and_comparisons() {
+        let db = MemoryStore::new();
+        let time = Utc.ymd(2000, 3, 20).and_hms(12, 0, 0);
+        let ts = time.timestamp() as u64;
+        // 1 h yesterday, 2 h earlier today
+        db.record_usage("app", ts - 86400, 3600).unwrap();
+        db.record_usage("app", ts - 3 * 3600, 7200).unwrap();
+        let test_cases = vec![
+            ("usage(this day) == 2 h", true),
+            ("usage(this day) >= 30 m", true),
+            ("usage(this day) > 2 h", false),
+            ("usage(sliding 2 D) == 3 h", true),
+            ("usage(sliding 2 h) == 0 s", true),
+            ("usage(this day) + usage(sliding 2 D) > 4 h", true),
+            ("usage(this day) + usage(sliding 2 D) > 5 h", false),
+            ("usage(sliding 2 D) <= 4 h - usage(this day)", false),
+            ("usage(sliding 2 D) - usage(this day) == 1 h", true),
+            ("1 h - usage(this day) < 0 s", true),
+            ("1 h - 30 m - 30 m == 0 s", true),
+            ("usage(this day) != 2 h", false),
+            ("atmost 2 h in this day", false),
+            ("atmost 121 m in this day", true),
+            ("(usage(this day) < 3 h) and Mon", true),
+        ];
+        for (expr, expected) in test_cases {
+            assert_eq!(
+                expected,
+                check_str_condition(&db, &time, "app", expr).unwrap(),
+                "Evaluating '{}'", expr
+            );
+        }
+    }
+
+    #[test]
+    fn usage_of_other_apps() {
+        let db = MemoryStore::new();
+        let time = Utc.ymd(2000, 3, 20).and_hms(12, 0, 0);
+        let ts = time.timestamp() as u64;
+        db.record_usage("firefox", ts - 3600, 600).unwrap();
+        db.record_usage("discord", ts - 3600, 4000).unwrap();
+        let test_cases = vec![
+            ("atmost 1 h in this day", true),
+            ("atmost 1 h of discord in this day", false),
+            ("atmost 1 h of firefox in this day", true),
+            ("atmost 1 h of unknown in this day", true),
+            ("usage(discord, this day) - usage(this day) == 3400 s", true),
+        ];
+        for (expr, expected) in test_cases {
+            assert_eq!(
+                expected,
+                check_str_condition(&db, &time, "firefox", expr).unwrap(),
+                "Evaluating '{}'", expr
+            );
+        }
+    }
+
+    #[test]
+    fn usage_of_groups() {
+        let db = MemoryStore::new();
+        let time = Utc.ymd(2000, 3, 20).and_hms(12, 0, 0);
+        let ts = time.timestamp() as u64;
+        db.record_usage("slack", ts - 3600, 1800).unwrap();
+        db.record_usage("discord", ts - 3600, 3600).unwrap();
+        db.record_usage("element", ts - 7200, 1800).unwrap();
+        db.record_usage("firefox", ts - 7200, 9000).unwrap();
+        let mut groups = Groups::new();
+        groups.insert(
+            "chat".to_string(),
+            vec!["slack".to_string(), "discord".to_string(), "element".to_string()]
+        );
+        groups.insert("empty".to_string(), vec![]);
+        let test_cases = vec![
+            ("usage(group:chat, this day) == 2 h", true),
+            ("atmost 2 h of group:chat in this day", false),
+            ("atmost 2 h of group:chat in sliding 90 m", true),
+            ("usage(group:empty, this day) == 0 s", true),
+        ];
+        for (expr, expected) in test_cases {
+            assert_eq!(
+                expected,
+                check_str_condition_with_groups(&db, &time, "slack", &groups, expr)
+                    .unwrap(),
+                "Evaluating '{}'", expr
+            );
+        }
+    }
+
+    #[test]
+    fn conditions_are_checked_at_the_time_of_the_clock() {
+        let db = MemoryStore::new();
+        let clock = SimulatedClock::new(Local.ymd(2021, 12, 6).and_hms(22, 0, 0));
+        db.record_usage("app", clock.timestamp(), 3 * 3600).unwrap();
+        let condition = expressions::parse_condition("atmost 2 h in this day").unwrap();
+        let check = |clock: &SimulatedClock| {
+            expressions::check_condition(
+                &condition, &db, "app", &Groups::new(), &Calendars::new(), WeekStart::Monday,
+                clock
+            ).unwrap()
+        };
+        assert!(!check(&clock));
+        clock.advance(Duration::from_secs(3 * 3600));
+        assert!(check(&clock), "Usage of the previous day shouldn't count");
+    }
+
+    #[test]
+    fn calendar_windows_start_at_their_unit() {
+        let db = MemoryStore::new();
+        // Wednesday
+        let time = Utc.ymd(2000, 3, 22).and_hms(12, 0, 0);
+        let at = |month, day| Utc.ymd(2000, month, day).and_hms(12, 0, 0).timestamp() as u64;
+        // Wednesday, Monday, Sunday, the previous month and the previous year
+        db.record_usage("app", at(3, 22), 60).unwrap();
+        db.record_usage("app", at(3, 20), 120).unwrap();
+        db.record_usage("app", at(3, 19), 240).unwrap();
+        db.record_usage("app", at(2, 10), 480).unwrap();
+        db.record_usage("app", Utc.ymd(1999, 12, 31).and_hms(12, 0, 0).timestamp() as u64, 960)
+            .unwrap();
+        let test_cases = vec![
+            ("usage(this day) == 60 s", WeekStart::Monday),
+            ("usage(this week) == 180 s", WeekStart::Monday),
+            ("usage(this week) == 420 s", WeekStart::Sunday),
+            ("usage(this month) == 420 s", WeekStart::Monday),
+            ("usage(this year) == 900 s", WeekStart::Monday),
+        ];
+        for (expr, week_start) in test_cases {
+            assert!(
+                check_str_condition_with_week_start(&db, &time, week_start, expr).unwrap(),
+                "Evaluating '{}' with weeks starting on {:?}", expr, week_start
+            );
+        }
+    }
+
+    #[test]
+    fn relative_windows_end_now_or_at_the_current_unit() {
+        let db = MemoryStore::new();
+        // Wednesday
+        let time = Utc.ymd(2000, 3, 22).and_hms(12, 0, 0);
+        let at = |month, day, hour| {
+            Utc.ymd(2000, month, day).and_hms(hour, 0, 0).timestamp() as u64
+        };
+        // Usage is recorded when it ends
+        db.record_usage("app", at(3, 22, 12), 60).unwrap();
+        db.record_usage("app", at(3, 21, 12), 120).unwrap();
+        db.record_usage("app", at(3, 19, 12), 240).unwrap();
+        db.record_usage("app", at(3, 14, 12), 480).unwrap();
+        // Across midnight, and counts on Tuesday, where it ends
+        db.record_usage("app", at(3, 21, 1), 7200).unwrap();
+        let test_cases = vec![
+            ("usage(yesterday) == 7320 s", WeekStart::Monday),
+            ("usage(previous day) == usage(yesterday)", WeekStart::Monday),
+            ("usage(last 0 day) == usage(this day)", WeekStart::Monday),
+            ("usage(last 1 day) == 7380 s", WeekStart::Monday),
+            ("usage(last 3 day) == 7620 s", WeekStart::Monday),
+            ("usage(previous hour) == 60 s", WeekStart::Monday),
+            ("usage(previous week) == 720 s", WeekStart::Monday),
+            ("usage(previous week) == 480 s", WeekStart::Sunday),
+            ("usage(last 1 week) == 8100 s", WeekStart::Monday),
+            ("usage(previous month) == 0 s", WeekStart::Monday),
+            ("usage(last 100000 year) == 8100 s", WeekStart::Monday),
+            ("usage(last 18446744073709551615 second) == 8100 s", WeekStart::Monday),
+        ];
+        for (expr, week_start) in test_cases {
+            assert!(
+                check_str_condition_with_week_start(&db, &time, week_start, expr).unwrap(),
+                "Evaluating '{}' with weeks starting on {:?}", expr, week_start
+            );
+        }
+    }
+
+    #[test]
+    fn spans_with_months_are_counted_back_from_now() {
+        let db = MemoryStore::new();
+        let time = Utc.ymd(2000, 3, 31).and_hms(12, 0, 0);
+        // Usage is recorded when it ends, an hour after the start of the windows
+        let at = |month, day| Utc.ymd(2000, month, day).and_hms(13, 0, 0).timestamp() as u64;
+        db.record_usage("app", at(3, 1), 60).unwrap();
+        db.record_usage("app", at(2, 29), 120).unwrap();
+        db.record_usage("app", at(2, 28), 240).unwrap();
+        db.record_usage("app", at(1, 31), 480).unwrap();
+        let test_cases = vec![
+            // Since 29 February, the last day of the month before
+            ("usage(sliding 1 M) == 180 s", true),
+            ("usage(sliding 1 M 1 s) == 180 s", true),
+            ("usage(sliding 1 M 1 D) == 420 s", true),
+            ("usage(sliding 2 M) == 900 s", true),
+            ("usage(sliding 1 Y) == 900 s", true),
+            ("usage(sliding 1 h 30 m) == 0 s", true),
+            // March has 31 days, but one month back from its end is February
+            ("1 M == 31 D", true),
+            ("1 M == 1 M", true),
+            ("1 M 1 h == 31 D + 1 h", true),
+            ("1 Y == 366 D", true),
+            ("12 M == 1 Y", true),
+            ("usage(sliding 1 M) + 1 M > 1 M", true),
+        ];
+        for (expr, expected) in test_cases {
+            assert_eq!(
+                expected,
+                check_str_condition(&db, &time, "app", expr).unwrap(),
+                "Evaluating '{}'", expr
+            );
+        }
+        match check_str_condition(&db, &time, "app", "usage(this day) < 10000000000000 Y") {
+            Err(expressions::Error::Overflow(_)) => {},
+            other => panic!("Expected overflow error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn arithmetic_overflow_is_an_error() {
+        let db = MemoryStore::new();
+        let time = Utc.ymd(2000, 3, 20).and_hms(12, 0, 0);
+        let exprs = vec![
+            "9223372036854775807 s + 1 s > 1 s",
+            "0 s - 9223372036854775807 s - 2 s < 1 s",
+        ];
+        for expr in exprs {
+            match check_str_condition(&db, &time, "app", expr) {
+                Err(expressions::Error::Overflow(_)) => {},
+                other => panic!("Expected overflow error for '{}', got {:?}", expr, other)
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_groups_are_an_error() {
+        let db = MemoryStore::new();
+        let time = Utc.ymd(2000, 3, 20).and_hms(12, 0, 0);
+        match check_str_condition(&db, &time, "app", "atmost 1 h of group:x in this day") {
+            Err(expressions::Error::UnknownGroup(group_id)) => assert_eq!("x", group_id),
+            other => panic!("Expected unknown group error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn dates_are_checked_in_local_time() {
+        let mut calendars = Calendars::new();
+        let day = |month, day| NaiveDate::from_ymd(2026, month, day);
+        calendars.insert("vacation".to_string(), vec![
+            DateRange { first: day(7, 20), last: day(8, 28) },
+            DateRange { first: day(12, 21), last: day(12, 31) }
+        ]);
+        calendars.insert("none".to_string(), vec![]);
+        let test_cases = vec![
+            ("on 2026-12-24", (12, 24, 0), true),
+            ("on 2026-12-24", (12, 24, 23), true),
+            ("on 2026-12-24", (12, 25, 0), false),
+            ("between 2026-12-20 and 2027-01-06", (12, 20, 0), true),
+            ("between 2026-12-20 and 2027-01-06", (12, 19, 23), false),
+            ("between 2026-12-20 and 2026-12-20", (12, 20, 12), true),
+            ("on calendar vacation", (8, 1, 12), true),
+            ("on calendar vacation", (8, 28, 23), true),
+            ("on calendar vacation", (8, 29, 0), false),
+            ("on calendar vacation", (12, 31, 12), true),
+            ("on calendar none", (12, 31, 12), false),
+            ("not on calendar vacation and weekday", (9, 1, 12), true),
+        ];
+        for (expr, (month, day, hour), expected) in test_cases {
+            // In a time zone where it's still the day before in UTC
+            let time = FixedOffset::east(5 * 3600).ymd(2026, month, day).and_hms(hour, 0, 0);
+            assert_eq!(
+                expected,
+                check_str_condition_with_calendars(&time, &calendars, expr).unwrap(),
+                "Evaluating '{}' at {}", expr, time
+            );
+        }
+        let time = Utc.ymd(2026, 12, 24).and_hms(12, 0, 0);
+        match check_str_condition_with_calendars(&time, &calendars, "on calendar exams") {
+            Err(expressions::Error::UnknownCalendar(name)) => assert_eq!("exams", name),
+            other => panic!("Expected unknown calendar error, got {:?}", other)
+        }
+    }
+
+        fn check_str_condition<Z: TimeZone>(
+        db: &dyn UsageStore,
+        time: &DateTime<Z>,
+        app_id: &str,
+        condition_str: &str,
+    ) -> expressions::Result<bool> {
+        check_str_condition_with_groups(db, time, app_id, &Groups::new(), condition_str)
+    }
+
+    fn check_str_condition_with_week_start<Z: TimeZone>(
+        db: &dyn UsageStore,
+        time: &DateTime<Z>,
+        week_start: WeekStart,
+        condition_str: &str,
+    ) -> expressions::Result<bool> {
+        let condition = expressions::parse_condition(condition_str).unwrap();
+        let (groups, calendars) = (Groups::new(), Calendars::new());
+        let ctx = expressions::EvalContext {
+            db, time, app_id: "app", groups: &groups, calendars: &calendars, week_start
+        };
+        expressions::eval(&ctx, &condition)
+    }
+
+    fn check_str_condition_with_groups<Z: TimeZone>(
+        db: &dyn UsageStore,
         time: &DateTime<Z>,
         app_id: &str,
+        groups: &Groups,
+        condition_str: &str,
+    ) -> expressions::Result<bool> {
+        let condition = expressions::parse_condition(condition_str).unwrap();
+        let week_start = WeekStart::Monday;
+        let calendars = &Calendars::new();
+        let ctx = expressions::EvalContext { db, time, app_id, groups, calendars, week_start };
+        expressions::eval(&ctx, &condition)
+    }
+
+    fn check_str_condition_with_calendars<Z: TimeZone>(
+        time: &DateTime<Z>,
+        calendars: &Calendars,
         condition_str: &str,
-    ) -> Result<bool, db::Error> {
+    ) -> expressions::Result<bool> {
         let condition = expressions::parse_condition(condition_str).unwrap();
-        let ctx = expressions::EvalContext { db, time, app_id };
+        let ctx = expressions::EvalContext {
+            db: &MemoryStore::new(),
+            time,
+            app_id: "app",
+            groups: &Groups::new(),
+            calendars,
+            week_start: WeekStart::Monday
+        };
         expressions::eval(&ctx, &condition)
     }
 }
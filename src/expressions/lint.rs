@@ -0,0 +1,192 @@
+use crate::expressions::Groups;
+use crate::expressions::parser::{
+    CompareOp, Condition, ConditionCompare, DurationExpr, Span, Subject, TimeUnit, Usage, Window
+};
+
+/// Finds comparisons whose outcome doesn't depend on the usage data, such as
+/// `atmost 2 h in sliding 1 h`, which is always true because an app can't be
+/// used for more than an hour within an hour. These are almost certainly
+/// mistakes, unless the app is tracked more than once at the same time, in
+/// which case its sessions overlap. Returns one message per such comparison.
+pub fn constant_comparisons(condition: &Condition, groups: &Groups) -> Vec<String> {
+    let mut messages = vec![];
+    collect_constant_comparisons(condition, groups, &mut messages);
+    messages
+}
+
+fn collect_constant_comparisons(condition: &Condition, groups: &Groups, messages: &mut Vec<String>) {
+    match condition {
+        Condition::And(c_and) => {
+            collect_constant_comparisons(&c_and.c1, groups, messages);
+            collect_constant_comparisons(&c_and.c2, groups, messages);
+        },
+        Condition::Or(c_or) => {
+            collect_constant_comparisons(&c_or.c1, groups, messages);
+            collect_constant_comparisons(&c_or.c2, groups, messages);
+        },
+        Condition::Not(c_not) => collect_constant_comparisons(&c_not.c, groups, messages),
+        Condition::Compare(c_cmp) => {
+            if let Some(value) = constant_value(c_cmp, groups) {
+                messages.push(format!("Comparison `{}` is always {}", c_cmp, value));
+            }
+        },
+        Condition::Weekday(_) | Condition::Between(_) | Condition::Date(_)
+        | Condition::Calendar(_) => {}
+    }
+}
+
+/// The value of the comparison, if it is the same for all possible usage.
+fn constant_value(c_cmp: &ConditionCompare, groups: &Groups) -> Option<bool> {
+    let lhs = bounds(&c_cmp.lhs, groups)?;
+    let rhs = bounds(&c_cmp.rhs, groups)?;
+    match c_cmp.op {
+        CompareOp::Lt => less(lhs, rhs),
+        CompareOp::Le => less(rhs, lhs).map(|b| !b),
+        CompareOp::Gt => less(rhs, lhs),
+        CompareOp::Ge => less(lhs, rhs).map(|b| !b),
+        CompareOp::Eq => equal(lhs, rhs),
+        CompareOp::Ne => equal(lhs, rhs).map(|b| !b)
+    }
+}
+
+/// Smallest and largest possible value of a duration expression, in seconds
+type Bounds = (i128, i128);
+
+/// Whether a value in `a` is always, or never, smaller than a value in `b`
+fn less(a: Bounds, b: Bounds) -> Option<bool> {
+    if a.1 < b.0 {
+        Some(true)
+    } else if a.0 >= b.1 {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn equal(a: Bounds, b: Bounds) -> Option<bool> {
+    if a.0 == a.1 && b.0 == b.1 && a.0 == b.0 {
+        Some(true)
+    } else if a.1 < b.0 || b.1 < a.0 {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// None if the expression isn't bounded, e.g. because it refers to a group
+/// that isn't defined.
+fn bounds(d: &DurationExpr, groups: &Groups) -> Option<Bounds> {
+    match d {
+        DurationExpr::Literal(span) => Some(span_bounds(span)),
+        DurationExpr::Usage(usage) => usage_bounds(usage, groups),
+        DurationExpr::Add(d_add) => {
+            let (lo1, hi1) = bounds(&d_add.d1, groups)?;
+            let (lo2, hi2) = bounds(&d_add.d2, groups)?;
+            Some((lo1 + lo2, hi1 + hi2))
+        },
+        DurationExpr::Sub(d_sub) => {
+            let (lo1, hi1) = bounds(&d_sub.d1, groups)?;
+            let (lo2, hi2) = bounds(&d_sub.d2, groups)?;
+            Some((lo1 - hi2, hi1 - lo2))
+        }
+    }
+}
+
+/// Each app can be used for at most the length of the window. The usage of a
+/// group is the sum of its members.
+fn usage_bounds(usage: &Usage, groups: &Groups) -> Option<Bounds> {
+    let apps = match &usage.subject {
+        Subject::Current | Subject::App(_) => 1,
+        Subject::Group(group_id) => groups.get(group_id)?.len() as i128
+    };
+    Some((0, apps * max_window_length(&usage.window)))
+}
+
+/// Shortest and longest that a span can be, depending on when it's counted
+/// back from. Months have 28 to 31 days, years 365 or 366, and either may
+/// include a change to or from daylight saving time.
+fn span_bounds(span: &Span) -> Bounds {
+    const HOUR: i128 = 3600;
+    const DAY: i128 = 24 * HOUR;
+    let seconds = i128::from(span.seconds);
+    if span.months == 0 {
+        return (seconds, seconds);
+    }
+    let (years, months) = (i128::from(span.months / 12), i128::from(span.months % 12));
+    (
+        seconds + years * 365 * DAY + months * 28 * DAY - HOUR,
+        seconds + years * 366 * DAY + months * 31 * DAY + HOUR
+    )
+}
+
+/// Longest that a window can be, in seconds. Calendar units get an extra hour,
+/// since a day that ends daylight saving time has 25 hours.
+fn max_window_length(window: &Window) -> i128 {
+    match window {
+        Window::Sliding(span) => span_bounds(span).1,
+        Window::This(unit) | Window::Previous(unit) => max_unit_length(*unit),
+        // The current unit and the complete ones before it
+        Window::Last { count, unit } => (i128::from(*count) + 1) * max_unit_length(*unit)
+    }
+}
+
+fn max_unit_length(unit: TimeUnit) -> i128 {
+    const HOUR: i128 = 3600;
+    const DAY: i128 = 24 * HOUR;
+    match unit {
+        TimeUnit::Second => 1,
+        TimeUnit::Minute => 60,
+        // Where DST shifts by half an hour, the hour it ends in is longer
+        TimeUnit::Hour => HOUR + HOUR / 2,
+        TimeUnit::Day => DAY + HOUR,
+        TimeUnit::Week => 7 * DAY + HOUR,
+        TimeUnit::Month => 31 * DAY + HOUR,
+        TimeUnit::Year => 366 * DAY + HOUR
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::expressions::Groups;
+    use crate::expressions::lint::*;
+    use crate::expressions::parse_condition;
+
+    #[test]
+    fn finds_constant_comparisons() {
+        let mut groups = Groups::new();
+        groups.insert("chat".to_string(), vec!["a".to_string(), "b".to_string()]);
+        let test_cases = vec![
+            ("atmost 2 h in sliding 1 h", vec!["Comparison `usage(sliding 1 h) < 2 h` is always true"]),
+            ("atmost 1 h in sliding 1 h", vec![]),
+            ("atmost 0 s in this day", vec!["Comparison `usage(this day) < 0 s` is always false"]),
+            ("usage(this hour) > 2 h", vec!["Comparison `usage(this hour) > 2 h` is always false"]),
+            ("usage(this day) <= 24 h", vec![]),
+            ("usage(yesterday) > 25 h", vec!["Comparison `usage(previous day) > 25 h` is always false"]),
+            ("atmost 2 D in last 1 day", vec![]),
+            ("atmost 3 D in last 1 day", vec!["Comparison `usage(last 1 day) < 3 D` is always true"]),
+            ("usage(this day) <= 25 h", vec!["Comparison `usage(this day) <= 25 h` is always true"]),
+            ("atmost 3 h of group:chat in sliding 1 h", vec![
+                "Comparison `usage(group:chat, sliding 1 h) < 3 h` is always true"
+            ]),
+            ("atmost 90 m of group:chat in sliding 1 h", vec![]),
+            ("atmost 1 W of group:unknown in sliding 1 h", vec![]),
+            ("usage(this day) - usage(a, this day) >= 30 h", vec![
+                "Comparison `usage(this day) - usage(a, this day) >= 30 h` is always false"
+            ]),
+            ("1 h == 60 m", vec!["Comparison `1 h == 1 h` is always true"]),
+            ("atmost 30 D in sliding 1 M", vec![]),
+            ("atmost 40 D in sliding 1 M", vec!["Comparison `usage(sliding 1 M) < 40 D` is always true"]),
+            ("usage(sliding 1 Y) > 1 Y", vec![]),
+            ("usage(this week) > 1 M", vec!["Comparison `usage(this week) > 1 M` is always false"]),
+            ("1 M > 2 W", vec!["Comparison `1 M > 2 W` is always true"]),
+            ("1 M == 30 D", vec![]),
+            ("Mon or not (atmost 2 h in sliding 1 h)", vec![
+                "Comparison `usage(sliding 1 h) < 2 h` is always true"
+            ]),
+        ];
+        for (input, expected) in test_cases {
+            let condition = parse_condition(input).unwrap();
+            assert_eq!(expected, constant_comparisons(&condition, &groups), "In '{}'", input);
+        }
+    }
+}
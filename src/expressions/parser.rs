@@ -1,25 +1,37 @@
 use nom::{
     Finish,
     IResult,
+    InputLength,
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{char, multispace0, multispace1, one_of},
-    combinator::{map_res, recognize},
-    error::{context, convert_error, VerboseError},
-    multi::many1,
+    bytes::complete::{tag, tag_no_case, take_while1},
+    character::complete::{char, multispace0, multispace1, one_of, satisfy},
+    combinator::{all_consuming, eof, map, map_opt, map_res, not, opt, recognize, verify},
+    error::{context, ContextError, ErrorKind, FromExternalError},
+    multi::{count, many0, many1, separated_list1},
     sequence::{preceded, terminated, delimited, tuple},
 };
+use chrono::{NaiveDate, Weekday};
 use serde::{Deserialize, Deserializer};
+use std::cmp::Ordering;
+use std::fmt;
 use std::str::FromStr;
 
+use crate::calendar::DateRange;
+use crate::expressions::typecheck;
+use crate::expressions::typecheck::TypeError;
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Condition {
     And(ConditionAnd),
     Or(ConditionOr),
     Not(ConditionNot),
     Weekday(ConditionWeekday),
-    AtMostInSliding(ConditionAtMostInSliding),
-    AtMostInThis(ConditionAtMostInThis)
+    Between(ConditionBetween),
+    /// True on the days of the range, e.g. `on 2026-12-24`
+    Date(DateRange),
+    /// True on the days of a calendar from the config, by name
+    Calendar(String),
+    Compare(ConditionCompare)
 }
 
 impl<'de> Deserialize<'de> for Condition {
@@ -33,6 +45,98 @@ impl<'de> Deserialize<'de> for Condition {
     }
 }
 
+impl Condition {
+    /// Ids of all apps that are referenced by name in this condition, in
+    /// order of appearance. May contain duplicates.
+    pub fn referenced_apps(&self) -> Vec<&str> {
+        self.subjects().into_iter().filter_map(|subject| match subject {
+            Subject::App(app_id) => Some(app_id.as_str()),
+            _ => None
+        }).collect()
+    }
+
+    /// Ids of all groups that are referenced in this condition, in order of
+    /// appearance. May contain duplicates.
+    pub fn referenced_groups(&self) -> Vec<&str> {
+        self.subjects().into_iter().filter_map(|subject| match subject {
+            Subject::Group(group_id) => Some(group_id.as_str()),
+            _ => None
+        }).collect()
+    }
+
+    /// Names of all calendars that are referenced in this condition, in order
+    /// of appearance. May contain duplicates.
+    pub fn referenced_calendars(&self) -> Vec<&str> {
+        match self {
+            Condition::And(c_and) =>
+                [c_and.c1.referenced_calendars(), c_and.c2.referenced_calendars()].concat(),
+            Condition::Or(c_or) =>
+                [c_or.c1.referenced_calendars(), c_or.c2.referenced_calendars()].concat(),
+            Condition::Not(c_not) => c_not.c.referenced_calendars(),
+            Condition::Calendar(name) => vec![name.as_str()],
+            Condition::Weekday(_) | Condition::Between(_) | Condition::Date(_)
+            | Condition::Compare(_) => vec![]
+        }
+    }
+
+    /// Limits on the daily usage of a subject, as written with `atmost
+    /// {Duration} in this day`, i.e. `usage({Subject}, this day) <
+    /// {Duration}`. Also found under `not`, where the limit is what the
+    /// condition checks for being exceeded.
+    pub fn daily_limits(&self) -> Vec<(&Subject, Duration)> {
+        let mut limits = vec![];
+        self.collect_daily_limits(&mut limits);
+        limits
+    }
+
+    fn collect_daily_limits<'a>(&'a self, limits: &mut Vec<(&'a Subject, Duration)>) {
+        match self {
+            Condition::And(c_and) => {
+                c_and.c1.collect_daily_limits(limits);
+                c_and.c2.collect_daily_limits(limits);
+            },
+            Condition::Or(c_or) => {
+                c_or.c1.collect_daily_limits(limits);
+                c_or.c2.collect_daily_limits(limits);
+            },
+            Condition::Not(c_not) => c_not.c.collect_daily_limits(limits),
+            Condition::Compare(ConditionCompare {
+                lhs: DurationExpr::Usage(Usage { subject, window: Window::This(TimeUnit::Day) }),
+                op: CompareOp::Lt,
+                rhs: DurationExpr::Literal(limit)
+            }) => limits.extend(limit.fixed().map(|limit| (subject, limit))),
+            Condition::Compare(_) | Condition::Weekday(_) | Condition::Between(_)
+            | Condition::Date(_) | Condition::Calendar(_) => {}
+        }
+    }
+
+    fn subjects(&self) -> Vec<&Subject> {
+        let mut subjects = vec![];
+        self.collect_subjects(&mut subjects);
+        subjects
+    }
+
+    fn collect_subjects<'a>(&'a self, subjects: &mut Vec<&'a Subject>) {
+        match self {
+            Condition::And(c_and) => {
+                c_and.c1.collect_subjects(subjects);
+                c_and.c2.collect_subjects(subjects);
+            },
+            Condition::Or(c_or) => {
+                c_or.c1.collect_subjects(subjects);
+                c_or.c2.collect_subjects(subjects);
+            },
+            Condition::Not(c_not) => c_not.c.collect_subjects(subjects),
+            Condition::Compare(c_cmp) => {
+                c_cmp.lhs.collect_subjects(subjects);
+                c_cmp.rhs.collect_subjects(subjects);
+            },
+            Condition::Weekday(_) | Condition::Between(_) | Condition::Date(_)
+            | Condition::Calendar(_) => {}
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct ConditionAnd {
     pub c1: Box<Condition>,
@@ -50,27 +154,164 @@ pub struct ConditionNot {
     pub c: Box<Condition>
 }
 
+/// True on a set of weekdays, like `Mon`, `Mon..Fri` or `{Sat, Sun}`. Holds
+/// a bit for each day, from Monday in the lowest bit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConditionWeekday {
+    days: u8
+}
+
+impl ConditionWeekday {
+    pub fn of(days: &[Weekday]) -> ConditionWeekday {
+        let days = days.iter().fold(0, |bits, day| bits | 1 << day.num_days_from_monday());
+        ConditionWeekday { days }
+    }
+
+    /// The days from `first` to `last`, both included. Wraps around the end
+    /// of the week, so `Fri..Mon` is the long weekend.
+    pub fn range(first: Weekday, last: Weekday) -> ConditionWeekday {
+        let mut days = vec![first];
+        while days[days.len() - 1] != last {
+            days.push(days[days.len() - 1].succ());
+        }
+        ConditionWeekday::of(&days)
+    }
+
+    pub fn union(self, other: ConditionWeekday) -> ConditionWeekday {
+        ConditionWeekday { days: self.days | other.days }
+    }
+
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.days & 1 << day.num_days_from_monday() != 0
+    }
+}
+
+/// True if the time of day lies in the half-open range `[from, to)`. If `to`
+/// is earlier than `from`, the range wraps around midnight.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ConditionBetween {
+    pub from: Time,
+    pub to: Time
+}
+
+/// Compares two durations, e.g. `usage(this day) >= 30 m`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ConditionCompare {
+    pub lhs: DurationExpr,
+    pub op: CompareOp,
+    pub rhs: DurationExpr
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CompareOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne
+}
+
+/// Expression of type Duration. Evaluates to a (possibly negative) number of
+/// seconds.
+#[derive(Debug, Eq, PartialEq)]
+pub enum DurationExpr {
+    Literal(Span),
+    Usage(Usage),
+    Add(DurationAdd),
+    Sub(DurationSub)
+}
+
+impl DurationExpr {
+    fn collect_subjects<'a>(&'a self, subjects: &mut Vec<&'a Subject>) {
+        match self {
+            DurationExpr::Usage(usage) => subjects.push(&usage.subject),
+            DurationExpr::Add(d_add) => {
+                d_add.d1.collect_subjects(subjects);
+                d_add.d2.collect_subjects(subjects);
+            },
+            DurationExpr::Sub(d_sub) => {
+                d_sub.d1.collect_subjects(subjects);
+                d_sub.d2.collect_subjects(subjects);
+            },
+            DurationExpr::Literal(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct DurationAdd {
+    pub d1: Box<DurationExpr>,
+    pub d2: Box<DurationExpr>
+}
+
 #[derive(Debug, Eq, PartialEq)]
-pub enum ConditionWeekday {
-    Mon,
-    Tue,
-    Wed,
-    Thu,
-    Fri,
-    Sat,
-    Sun
+pub struct DurationSub {
+    pub d1: Box<DurationExpr>,
+    pub d2: Box<DurationExpr>
 }
 
+/// Total usage of an app in a time window, e.g. `usage(this day)` or
+/// `usage(discord, this day)`.
 #[derive(Debug, Eq, PartialEq)]
-pub struct ConditionAtMostInSliding {
-    pub limit: Duration,
-    pub window_size: Duration
+pub struct Usage {
+    pub subject: Subject,
+    pub window: Window
 }
 
+/// Whose usage is measured
 #[derive(Debug, Eq, PartialEq)]
-pub struct ConditionAtMostInThis {
-    pub limit: Duration,
-    pub time_unit: TimeUnit
+pub enum Subject {
+    /// The app that the condition is evaluated for
+    Current,
+    /// Some other app, by id
+    App(String),
+    /// All apps in a group, by group id
+    Group(String)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Window {
+    /// From the start of the current time unit until now
+    This(TimeUnit),
+    /// The given number of complete time units before the current one, and
+    /// the current one until now
+    Last { count: u64, unit: TimeUnit },
+    /// The complete time unit before the current one, e.g. yesterday
+    Previous(TimeUnit),
+    /// The given span back from now
+    Sliding(Span)
+}
+
+/// Untyped expression, as produced by the parser. Turned into a `Condition`
+/// or `DurationExpr` by the type checker.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Weekday(ConditionWeekday),
+    Between(ConditionBetween),
+    Date(DateRange),
+    Calendar(String),
+    Duration(Span),
+    Usage(Usage)
+}
+
+/// A time of day, with minute precision.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Time {
+    pub hour: u32,
+    pub minute: u32
+}
+
+impl Time {
+    pub fn seconds_from_midnight(&self) -> u32 {
+        self.hour * 3600 + self.minute * 60
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -95,7 +336,33 @@ impl From<Duration> for std::time::Duration {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Length of time in the condition language, which may include calendar
+/// months. Since months differ in length, it's only a number of seconds
+/// relative to a point in time, when it's counted back from there: first the
+/// months, then the seconds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span {
+    pub months: u64,
+    pub seconds: u64
+}
+
+impl Span {
+    /// The span as a fixed number of seconds, if it has no months
+    pub fn fixed(&self) -> Option<Duration> {
+        match self.months {
+            0 => Some(Duration { seconds: self.seconds }),
+            _ => None
+        }
+    }
+}
+
+impl From<Duration> for Span {
+    fn from(val: Duration) -> Self {
+        Span { months: 0, seconds: val.seconds }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum TimeUnit {
     Second,
     Minute,
@@ -106,226 +373,779 @@ pub enum TimeUnit {
     Year
 }
 
-type Error<I> = VerboseError<I>;
+impl fmt::Display for ConditionCompare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op, self.rhs)
+    }
+}
 
-pub fn parse_condition(expr: &str) -> Result<Condition, String> {
-    let result: IResult<&str, Condition, VerboseError<&str>> = delimited(
-        multispace0,
-        condition_expr,
-        multispace0
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!="
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// Renders the expression in the condition language. Brackets are only added
+/// where they are needed, so the output may differ from the original input.
+impl fmt::Display for DurationExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DurationExpr::Literal(d) => write!(f, "{}", d),
+            DurationExpr::Usage(usage) => write!(f, "{}", usage),
+            DurationExpr::Add(d_add) => write_binary(f, &d_add.d1, "+", &d_add.d2),
+            DurationExpr::Sub(d_sub) => write_binary(f, &d_sub.d1, "-", &d_sub.d2)
+        }
+    }
+}
+
+/// Both operators are left-associative, so only a binary right operand needs
+/// brackets.
+fn write_binary(f: &mut fmt::Formatter, d1: &DurationExpr, op: &str, d2: &DurationExpr)
+    -> fmt::Result {
+    match d2 {
+        DurationExpr::Add(_) | DurationExpr::Sub(_) => write!(f, "{} {} ({})", d1, op, d2),
+        _ => write!(f, "{} {} {}", d1, op, d2)
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.subject {
+            Subject::Current => write!(f, "usage({})", self.window),
+            Subject::App(app_id) => write!(f, "usage({}, {})", app_id, self.window),
+            Subject::Group(group_id) => write!(f, "usage(group:{}, {})", group_id, self.window)
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Window::This(unit) => write!(f, "this {}", unit),
+            Window::Last { count, unit } => write!(f, "last {} {}", count, unit),
+            Window::Previous(unit) => write!(f, "previous {}", unit),
+            Window::Sliding(d) => write!(f, "sliding {}", d)
+        }
+    }
+}
+
+/// Uses the largest unit that the duration is a whole multiple of.
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = [(7 * 24 * 3600, "W"), (24 * 3600, "D"), (3600, "h"), (60, "m")];
+        let (factor, unit) = units.iter()
+            .find(|(factor, _)| self.seconds > 0 && self.seconds.is_multiple_of(*factor))
+            .unwrap_or(&(1, "s"));
+        write!(f, "{} {}", self.seconds / factor, unit)
+    }
+}
+
+/// Uses years for a whole number of years, and leaves out the seconds if
+/// there are months, but no seconds.
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let duration = Duration { seconds: self.seconds };
+        match self.months {
+            0 => return write!(f, "{}", duration),
+            months if months % 12 == 0 => write!(f, "{} Y", months / 12)?,
+            months => write!(f, "{} M", months)?
+        }
+        match self.seconds {
+            0 => Ok(()),
+            _ => write!(f, " {}", duration)
+        }
+    }
+}
+
+impl fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self {
+            TimeUnit::Second => "second",
+            TimeUnit::Minute => "minute",
+            TimeUnit::Hour => "hour",
+            TimeUnit::Day => "day",
+            TimeUnit::Week => "week",
+            TimeUnit::Month => "month",
+            TimeUnit::Year => "year"
+        };
+        write!(f, "{}", unit)
+    }
+}
+
+/// Error produced while parsing a condition or duration, pointing at the
+/// position in the input where parsing failed.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ParseError {
+    /// What was being parsed, e.g. "condition"
+    pub what: &'static str,
+    /// Byte offset of the error in the input
+    pub offset: usize,
+    /// Line of the error, starting at 1
+    pub line: usize,
+    /// Column of the error, in characters, starting at 1
+    pub column: usize,
+    /// Number of characters that the error spans, at least 1
+    pub len: usize,
+    /// Tokens that would have been valid at the error position
+    pub expected: Vec<String>,
+    /// Set if the input is well-formed, but the types don't match. Then the
+    /// position is the start of the expression.
+    pub type_error: Option<Box<TypeError>>,
+    /// The line of input that contains the error, with a caret below the
+    /// error position
+    pub snippet: String
+}
+
+impl ParseError {
+    fn new(what: &'static str, input: &str, offset: usize, len: usize) -> ParseError {
+        let (line, column, line_str) = line_and_column(input, offset);
+        ParseError {
+            what,
+            offset,
+            line,
+            column,
+            len: len.max(1),
+            expected: vec![],
+            type_error: None,
+            snippet: caret_snippet(line_str, column, len)
+        }
+    }
+
+    fn syntax(what: &'static str, input: &str, e: Error<&str>) -> ParseError {
+        let offset = input.len() - e.input.len();
+        let mut error = ParseError::new(what, input, offset, 1);
+        error.expected = e.expected;
+        error
+    }
+
+    fn typing(what: &'static str, input: &str, e: TypeError) -> ParseError {
+        let trimmed = input.trim_start();
+        let offset = input.len() - trimmed.len();
+        let len = trimmed.lines().next().unwrap_or("").trim_end().chars().count();
+        let mut error = ParseError::new(what, input, offset, len);
+        error.type_error = Some(Box::new(e));
+        error
+    }
+
+    /// Describes what went wrong, without position information
+    pub fn reason(&self) -> String {
+        if let Some(e) = &self.type_error {
+            return format!("type error: {}", e);
+        }
+        match self.expected.split_last() {
+            None => "unexpected input".to_string(),
+            Some((last, [])) => format!("expected {}", last),
+            Some((last, init)) => format!("expected {} or {}", init.join(", "), last)
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Couldn't parse {} at line {}, column {}: {}\n{}",
+            self.what, self.line, self.column, self.reason(), self.snippet
+        )
+    }
+}
+
+/// Finds the 1-based line and column of a byte offset, and the line itself.
+pub(crate) fn line_and_column(input: &str, offset: usize) -> (usize, usize, &str) {
+    let before = &input[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_end = input[offset..].find('\n').map(|i| offset + i).unwrap_or(input.len());
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line, column, &input[line_start..line_end])
+}
+
+/// Renders a line with a caret underline of `len` characters, starting at the
+/// 1-based `column`.
+pub(crate) fn caret_snippet(line: &str, column: usize, len: usize) -> String {
+    let indent: String = line.chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!("{}\n{}{}", line, indent, "^".repeat(len.max(1)))
+}
+
+/// Error type of the individual parsers. Keeps track of the furthest position
+/// that any alternative got to, and what would have been valid there.
+#[derive(Debug, PartialEq)]
+struct Error<I> {
+    input: I,
+    expected: Vec<String>
+}
+
+impl<I> Error<I> {
+    fn expected(input: I, what: String) -> Error<I> {
+        Error { input, expected: vec![what] }
+    }
+}
+
+impl<I: InputLength> nom::error::ParseError<I> for Error<I> {
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        let expected = match kind {
+            ErrorKind::Eof => vec!["end of input".to_string()],
+            ErrorKind::MultiSpace => vec!["whitespace".to_string()],
+            _ => vec![]
+        };
+        Error { input, expected }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: I, c: char) -> Self {
+        Error::expected(input, format!("`{}`", c))
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        match self.input.input_len().cmp(&other.input.input_len()) {
+            Ordering::Less => self,
+            Ordering::Greater => other,
+            Ordering::Equal => {
+                for e in other.expected {
+                    if !self.expected.contains(&e) {
+                        self.expected.push(e);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<I: InputLength> ContextError<I> for Error<I> {
+    /// If nothing within the context could be parsed, the context describes
+    /// best what was expected.
+    fn add_context(input: I, ctx: &'static str, mut other: Self) -> Self {
+        if other.input.input_len() == input.input_len() {
+            other.expected = vec![ctx.to_string()];
+        }
+        other
+    }
+}
+
+impl<I: InputLength, E> FromExternalError<I, E> for Error<I> {
+    fn from_external_error(input: I, kind: ErrorKind, _e: E) -> Self {
+        nom::error::ParseError::from_error_kind(input, kind)
+    }
+}
+
+pub fn parse_condition(expr: &str) -> Result<Condition, ParseError> {
+    let result: IResult<&str, Expr, Error<&str>> = terminated(
+        delimited(multispace0, condition_expr, multispace0),
+        or_operator(eof)
     )(expr);
     match result.finish() {
-        Ok((_, cond)) => Ok(cond),
-        Err(e) => Err(format!(
-            "Couldn't parse condition: {:#?}",
-            convert_error(expr, e)
-        ))
+        Ok((_, e)) => typecheck::condition(e)
+            .map_err(|e| ParseError::typing("condition", expr, e)),
+        Err(e) => Err(ParseError::syntax("condition", expr, e))
     }
 }
 
-pub fn parse_duration(expr: &str) -> Result<Duration, String> {
-    let result: IResult<&str, Duration, VerboseError<&str>> = delimited(
+pub fn parse_duration(expr: &str) -> Result<Duration, ParseError> {
+    let result: IResult<&str, Duration, Error<&str>> = all_consuming(delimited(
         multispace0,
         duration,
         multispace0
-    )(expr);
+    ))(expr);
     match result.finish() {
         Ok((_, dur)) => Ok(dur),
-        Err(e) => Err(format!(
-            "Couldn't parse condition: {:#?}",
-            convert_error(expr, e)
-        ))
+        Err(e) => Err(ParseError::syntax("duration", expr, e))
+    }
+}
+
+/// Binary operators, from loosest to tightest binding:
+/// `or`, `and`, comparisons, `+` and `-`. Prefix `not` binds tighter than
+/// `and`, but looser than comparisons, so `not usage(this day) > 1 h` negates
+/// the comparison. All binary operators are left-associative.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Compare(CompareOp),
+    Add,
+    Sub
+}
+
+const NOT_PRECEDENCE: u8 = 3;
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Compare(_) => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5
+        }
+    }
+
+    fn apply(self, lhs: Expr, rhs: Expr) -> Expr {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match self {
+            BinaryOp::Or => Expr::Or(lhs, rhs),
+            BinaryOp::And => Expr::And(lhs, rhs),
+            BinaryOp::Compare(op) => Expr::Compare(lhs, op, rhs),
+            BinaryOp::Add => Expr::Add(lhs, rhs),
+            BinaryOp::Sub => Expr::Sub(lhs, rhs)
+        }
     }
 }
 
+fn condition_expr(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    expr_with_min_precedence(s, 0)
+}
 
-fn condition_expr(s: &str) -> IResult<&str, Condition, Error<&str>> {
-    context(
-        "condition_expr",
+/// Precedence climbing: parses a prefix expression, followed by as many
+/// binary operators as bind at least as tightly as `min_precedence`. The right
+/// hand side of an operator only takes operators that bind strictly tighter,
+/// which makes chains left-associative.
+fn expr_with_min_precedence(s: &str, min_precedence: u8)
+    -> IResult<&str, Expr, Error<&str>> {
+    let (mut input, mut lhs) = prefix_expr(s)?;
+    loop {
+        match binary_op(input) {
+            Ok((rest, op)) if op.precedence() >= min_precedence => {
+                let (rest, rhs) = expr_with_min_precedence(rest, op.precedence() + 1)?;
+                lhs = op.apply(lhs, rhs);
+                input = rest;
+            },
+            _ => return Ok((input, lhs))
+        }
+    }
+}
+
+fn prefix_expr(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    alt((
+        map(
+            preceded(
+                tuple((keyword("not"), multispace0)),
+                |s| expr_with_min_precedence(s, NOT_PRECEDENCE)
+            ),
+            |e| Expr::Not(Box::new(e))
+        ),
+        atom
+    ))(s)
+}
+
+fn binary_op(s: &str) -> IResult<&str, BinaryOp, Error<&str>> {
+    delimited(
+        multispace0,
         alt((
-            condition_and,
-            condition_or,
-            condition_not,
-            condition_term
-    )))(s)
+            map(keyword("or"), |_| BinaryOp::Or),
+            map(keyword("and"), |_| BinaryOp::And),
+            map(compare_op, BinaryOp::Compare),
+            map(char('+'), |_| BinaryOp::Add),
+            map(char('-'), |_| BinaryOp::Sub)
+        )),
+        multispace0
+    )(s)
+}
+
+fn atom(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    alt((
+        delimited(
+            terminated(char('('), multispace0),
+            condition_expr,
+            preceded(multispace0, or_operator(char(')')))
+        ),
+        condition_weekday,
+        condition_between,
+        condition_on,
+        condition_at_most,
+        usage,
+        map(span, Expr::Duration)
+    ))(s)
+}
+
+/// Wraps a parser for whatever may follow a complete expression. If it fails
+/// right away, the expression could also have been continued by an operator,
+/// so those are added to the expected tokens.
+fn or_operator<'a, O>(
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, O, Error<&'a str>>
+) -> impl FnMut(&'a str) -> IResult<&'a str, O, Error<&'a str>> {
+    move |s: &'a str| parser(s).map_err(|e| e.map(|mut e| {
+        if e.input.len() == s.len() {
+            let mut expected: Vec<String> = vec![
+                "`and`", "`or`", "comparison operator", "`+`", "`-`"
+            ].into_iter().map(String::from).collect();
+            expected.append(&mut e.expected);
+            e.expected = expected;
+        }
+        e
+    }))
+}
+
+/// Matches a keyword, but not if it's only the prefix of a longer word.
+/// Keywords are case-insensitive, so `And` and `AND` are `and`.
+fn keyword<'a>(k: &'static str)
+    -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, Error<&'a str>> {
+    move |s: &'a str| expecting(
+        s,
+        || format!("`{}`", k),
+        terminated(tag_no_case(k), not(satisfy(is_word_char)))(s)
+    )
+}
+
+/// Matches a token that isn't a word, like an operator.
+fn token<'a>(t: &'static str)
+    -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, Error<&'a str>> {
+    move |s: &'a str| expecting(s, || format!("`{}`", t), tag(t)(s))
+}
+
+/// If the parser failed, reports the failure at the start of its input `s`,
+/// expecting `what`.
+fn expecting<'a, O>(
+    s: &'a str,
+    what: impl FnOnce() -> String,
+    result: IResult<&'a str, O, Error<&'a str>>
+) -> IResult<&'a str, O, Error<&'a str>> {
+    result.map_err(|e| e.map(|_| Error::expected(s, what())))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
 
-fn condition_term(s: &str) -> IResult<&str, Condition, Error<&str>> {
+/// A set of weekdays, optionally after `today is`, e.g. `today is Monday`
+fn condition_weekday(s: &str) -> IResult<&str, Expr, Error<&str>> {
     context(
-        "condition_term",
-        alt((
+        "weekday",
+        map(
+            preceded(
+                opt(tuple((keyword("today"), multispace1, keyword("is"), multispace1))),
+                weekdays
+            ),
+            Expr::Weekday
+        )
+    )(s)
+}
+
+fn weekdays(s: &str) -> IResult<&str, ConditionWeekday, Error<&str>> {
+    alt((
+        map(keyword("weekday"), |_| ConditionWeekday::range(Weekday::Mon, Weekday::Fri)),
+        map(keyword("weekend"), |_| ConditionWeekday::of(&[Weekday::Sat, Weekday::Sun])),
+        map(
             delimited(
-                terminated(char('('), multispace0),
-                condition_expr,
-                preceded(multispace0, char(')'))
+                tuple((char('{'), multispace0)),
+                separated_list1(tuple((multispace0, char(','), multispace0)), day_range),
+                tuple((multispace0, char('}')))
             ),
-            condition_weekday,
-            condition_at_most_in_this,
-            condition_at_most_in_sliding
-    )))(s)
+            |sets| sets.into_iter().fold(ConditionWeekday::of(&[]), ConditionWeekday::union)
+        ),
+        day_range
+    ))(s)
 }
 
-fn condition_and(s: &str) -> IResult<&str, Condition, Error<&str>> {
-    let (input, (c1, _, _, _, c2)) = context(
-        "condition_and",
-        tuple((
-            condition_term,
-            multispace1,
-            tag("and"),
-            multispace1,
-            condition_expr
-        ))
-    )(s)?;
-    let cnd = ConditionAnd {
-        c1: Box::new(c1), c2: Box::new(c2)
-    };
-    Ok((input, Condition::And(cnd)))
+/// A day, or a range of days like `Mon..Fri`
+fn day_range(s: &str) -> IResult<&str, ConditionWeekday, Error<&str>> {
+    let (input, (first, last)) = tuple((
+        day,
+        opt(preceded(tuple((multispace0, token(".."), multispace0)), day))
+    ))(s)?;
+    Ok((input, ConditionWeekday::range(first, last.unwrap_or(first))))
 }
 
-fn condition_or(s: &str) -> IResult<&str, Condition, Error<&str>> {
-    let (input, (c1, _, _, _, c2)) = context(
-        "condition_or",
-        tuple((
-            condition_term,
-            multispace1,
-            tag("or"),
-            multispace1,
-            condition_expr
-        ))
-    )(s)?;
-    let cnd = ConditionOr {
-        c1: Box::new(c1), c2: Box::new(c2)
-    };
-    Ok((input, Condition::Or(cnd)))
+/// A day of the week, by its full name or the first three letters
+fn day(s: &str) -> IResult<&str, Weekday, Error<&str>> {
+    alt((
+        map(alt((keyword("Monday"), keyword("Mon"))), |_| Weekday::Mon),
+        map(alt((keyword("Tuesday"), keyword("Tue"))), |_| Weekday::Tue),
+        map(alt((keyword("Wednesday"), keyword("Wed"))), |_| Weekday::Wed),
+        map(alt((keyword("Thursday"), keyword("Thu"))), |_| Weekday::Thu),
+        map(alt((keyword("Friday"), keyword("Fri"))), |_| Weekday::Fri),
+        map(alt((keyword("Saturday"), keyword("Sat"))), |_| Weekday::Sat),
+        map(alt((keyword("Sunday"), keyword("Sun"))), |_| Weekday::Sun)
+    ))(s)
 }
 
-fn condition_not(s: &str) -> IResult<&str, Condition, Error<&str>> {
-    let (input, (_, _, c)) = context(
-        "condition_not",
-        tuple((
-        tag("not"),
+/// `between` two times of day, or two dates
+fn condition_between(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    let (input, _) = tuple((keyword("between"), multispace1))(s)?;
+    alt((between_dates, between_times))(input)
+}
+
+fn between_times(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    let (input, (from, _, _, _, to)) = tuple((
+        time,
         multispace1,
-        condition_expr
-        ))
-    )(s)?;
-    let cnd = ConditionNot { c: Box::new(c) };
-    Ok((input, Condition::Not(cnd)))
+        keyword("and"),
+        multispace1,
+        time
+    ))(s)?;
+    Ok((input, Expr::Between(ConditionBetween { from, to })))
 }
 
-fn condition_weekday(s: &str) -> IResult<&str, Condition, Error<&str>> {
-    let (input, weekday_str) = context(
-        "condition_weekday",
-        alt((
-            tag("Mon"),
-            tag("Tue"),
-            tag("Wed"),
-            tag("Thu"),
-            tag("Fri"),
-            tag("Sat"),
-            tag("Sun"),
-        )))(s)?;
-    let wd = match weekday_str {
-        "Mon" => ConditionWeekday::Mon,
-        "Tue" => ConditionWeekday::Tue,
-        "Wed" => ConditionWeekday::Wed,
-        "Thu" => ConditionWeekday::Thu,
-        "Fri" => ConditionWeekday::Fri,
-        "Sat" => ConditionWeekday::Sat,
-        "Sun" => ConditionWeekday::Sun,
-        _ => panic!("Reached unreachable code. Draw your own conclusions...")
-    };
-    Ok((input, Condition::Weekday(wd)))
+/// Unlike times of day, both dates are included
+fn between_dates(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    let (last_input, (first, _, _, _)) = tuple((
+        date,
+        multispace1,
+        keyword("and"),
+        multispace1
+    ))(s)?;
+    let (input, last) = date(last_input)?;
+    if last < first {
+        return Err(nom::Err::Error(
+            Error::expected(last_input, format!("date not before {}", first))
+        ));
+    }
+    Ok((input, Expr::Date(DateRange { first, last })))
 }
 
-fn condition_at_most_in_this(s: &str) -> IResult<&str, Condition, Error<&str>> {
-    let (input, (_, _, limit, _, _, _, _, _, time_unit)) = context(
-        "condition_at_most_in_this",
-        tuple((
-            tag("atmost"),
-            multispace1,
-            duration,
-            multispace1,
-            tag("in"),
-            multispace1,
-            tag("this"),
-            multispace1,
-            time_unit
+/// `on {Date}` or `on calendar {Name}`
+fn condition_on(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    preceded(
+        tuple((keyword("on"), multispace1)),
+        alt((
+            map(
+                preceded(tuple((keyword("calendar"), multispace1)), calendar_name),
+                |name: &str| Expr::Calendar(name.to_string())
+            ),
+            map(date, |date| Expr::Date(DateRange { first: date, last: date }))
         ))
-    )(s)?;
-    Ok((input, Condition::AtMostInThis(
-                ConditionAtMostInThis { limit, time_unit }
+    )(s)
+}
+
+fn calendar_name(s: &str) -> IResult<&str, &str, Error<&str>> {
+    context(
+        "calendar name",
+        take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+    )(s)
+}
+
+/// `atmost {Duration} [of {Subject}] in {Window}` is sugar for
+/// `usage([{Subject},] {Window}) < {Duration}`
+fn condition_at_most(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    let (input, (_, _, limit, _, subject, _, _, window)) = tuple((
+        keyword("atmost"),
+        multispace1,
+        span,
+        multispace1,
+        opt(delimited(
+            tuple((keyword("of"), multispace1)),
+            subject,
+            multispace1
+        )),
+        keyword("in"),
+        multispace1,
+        window
+    ))(s)?;
+    let subject = subject.unwrap_or(Subject::Current);
+    Ok((input, Expr::Compare(
+        Box::new(Expr::Usage(Usage { subject, window })),
+        CompareOp::Lt,
+        Box::new(Expr::Duration(limit))
     )))
 }
 
-fn condition_at_most_in_sliding(s: &str) -> IResult<&str, Condition, Error<&str>> {
-    let (input, (_, _, limit, _, _, _, _, _, window_size)) = context(
-        "condition_at_most_in_sliding",
+fn usage(s: &str) -> IResult<&str, Expr, Error<&str>> {
+    let (input, (subject, window)) = delimited(
+        tuple((keyword("usage"), multispace0, char('('), multispace0)),
         tuple((
-            tag("atmost"),
-            multispace1,
-            duration,
-            multispace1,
-            tag("in"),
-            multispace1,
-            tag("sliding"),
-            multispace1,
-            duration
+            opt(terminated(
+                subject,
+                tuple((multispace0, char(','), multispace0))
+            )),
+            window
+        )),
+        tuple((multispace0, char(')')))
+    )(s)?;
+    let subject = subject.unwrap_or(Subject::Current);
+    Ok((input, Expr::Usage(Usage { subject, window })))
+}
+
+fn subject(s: &str) -> IResult<&str, Subject, Error<&str>> {
+    context(
+        "app or group id",
+        alt((
+            map(
+                preceded(token("group:"), app_id),
+                |id: &str| Subject::Group(id.to_string())
+            ),
+            map(app_id, |id: &str| Subject::App(id.to_string()))
+    )))(s)
+}
+
+fn app_id(s: &str) -> IResult<&str, &str, Error<&str>> {
+    context(
+        "app id",
+        take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+    )(s)
+}
+
+fn window(s: &str) -> IResult<&str, Window, Error<&str>> {
+    alt((
+        map(
+            preceded(tuple((keyword("this"), multispace1)), time_unit),
+            Window::This
+        ),
+        map(
+            tuple((keyword("last"), multispace1, integer, multispace1, time_unit)),
+            |(_, _, count, _, unit)| Window::Last { count, unit }
+        ),
+        map(
+            preceded(tuple((keyword("previous"), multispace1)), time_unit),
+            Window::Previous
+        ),
+        // Sugar for `previous day`
+        map(keyword("yesterday"), |_| Window::Previous(TimeUnit::Day)),
+        map(
+            preceded(tuple((keyword("sliding"), multispace1)), span),
+            Window::Sliding
+        )
+    ))(s)
+}
+
+fn compare_op(s: &str) -> IResult<&str, CompareOp, Error<&str>> {
+    let (input, op_str) = context(
+        "comparison operator",
+        alt((
+            token("<="),
+            token(">="),
+            token("=="),
+            token("!="),
+            token("<"),
+            token(">")
         ))
     )(s)?;
-    Ok((input, Condition::AtMostInSliding(
-                ConditionAtMostInSliding { limit, window_size }
-    )))
+    let op = match op_str {
+        "<=" => CompareOp::Le,
+        ">=" => CompareOp::Ge,
+        "==" => CompareOp::Eq,
+        "!=" => CompareOp::Ne,
+        "<" => CompareOp::Lt,
+        ">" => CompareOp::Gt,
+        _ => panic!("Reached unreachable state. Draw your own conclusions.")
+    };
+    Ok((input, op))
 }
 
+/// A fixed number of seconds, like `90 s` or `1 h 30 m`
 fn duration(s: &str) -> IResult<&str, Duration, Error<&str>> {
-    let (input, (number, _, unit)) = context(
+    let (input, span) = duration_parts(s, "smhDW", "duration unit (s, m, h, D or W)")?;
+    // Without the calendar units, there are no months
+    Ok((input, Duration { seconds: span.seconds }))
+}
+
+/// Like a duration, but may include months and years, like `1 M` or `1 Y 6 M`
+fn span(s: &str) -> IResult<&str, Span, Error<&str>> {
+    duration_parts(s, "smhDWMY", "duration unit (s, m, h, D, W, M or Y)")
+}
+
+/// One or more numbers with a unit each, which are added up. Fails if the
+/// sum is too large.
+fn duration_parts<'a>(s: &'a str, units: &'static str, expected: &'static str)
+    -> IResult<&'a str, Span, Error<&'a str>> {
+    let part = |s: &'a str| tuple((
+        integer,
+        multispace1,
+        |s| duration_unit(s, units, expected)
+    ))(s);
+    let (input, (first, rest)) = context(
         "duration",
+        tuple((part, many0(preceded(multispace1, part))))
+    )(s)?;
+    let mut span = Span { months: 0, seconds: 0 };
+    for (number, _, unit) in std::iter::once(first).chain(rest) {
+        let (months, seconds) = match unit {
+            's' => (0, 1),
+            'm' => (0, 60),
+            'h' => (0, 3600),
+            'D' => (0, 86400),
+            'W' => (0, 604800),
+            'M' => (1, 0),
+            'Y' => (12, 0),
+            _ => panic!("Reached unreachable state. Draw your own conclusions.")
+        };
+        let months = number.checked_mul(months).and_then(|m| span.months.checked_add(m));
+        let seconds = number.checked_mul(seconds).and_then(|s| span.seconds.checked_add(s));
+        match (months, seconds) {
+            (Some(months), Some(seconds)) => span = Span { months, seconds },
+            _ => return Err(nom::Err::Error(
+                Error::expected(s, "duration that isn't too large".to_string())
+            ))
+        }
+    }
+    Ok((input, span))
+}
+
+fn duration_unit<'a>(s: &'a str, units: &'static str, expected: &'static str)
+    -> IResult<&'a str, char, Error<&'a str>> {
+    expecting(
+        s,
+        || expected.to_string(),
+        terminated(one_of(units), not(satisfy(is_word_char)))(s)
+    )
+}
+
+fn time(s: &str) -> IResult<&str, Time, Error<&str>> {
+    let (input, (hour, _, minute)) = context(
+        "time of day (HH:MM)",
         tuple((
-            integer,
-            multispace1,
-            one_of("smhDW")
+            context("hour (0-23)", verify(integer, |h| *h < 24)),
+            char(':'),
+            context("minute (00-59)", verify(two_digits, |m| *m < 60))
         ))
     )(s)?;
-    let unit_seconds = match unit {
-        's' => 1,
-        'm' => 60,
-        'h' => 3600,
-        'D' => 86400,
-        'W' => 604800,
-        _ => panic!("Reached unreachable state. Draw your own conclusions.")
-    };
-    Ok((input, Duration { seconds: number * unit_seconds } ))
+    // Both values were range checked above, so the casts are lossless
+    Ok((input, Time { hour: hour as u32, minute: minute as u32 }))
+}
+
+fn date(s: &str) -> IResult<&str, NaiveDate, Error<&str>> {
+    context(
+        "date (YYYY-MM-DD)",
+        map_opt(
+            tuple((
+                map_res(recognize(count(one_of("0123456789"), 4)), |s: &str| i32::from_str(s)),
+                char('-'),
+                two_digits,
+                char('-'),
+                two_digits
+            )),
+            // Both are two digits, so the casts are lossless
+            |(year, _, month, _, day)| NaiveDate::from_ymd_opt(year, month as u32, day as u32)
+        )
+    )(s)
+}
+
+fn two_digits(s: &str) -> IResult<&str, u64, Error<&str>> {
+    map_res(
+        recognize(tuple((one_of("0123456789"), one_of("0123456789")))),
+        |s: &str| u64::from_str(s)
+    )(s)
 }
 
 fn time_unit(s: &str) -> IResult<&str, TimeUnit, Error<&str>> {
-    let (input, unit_str) = context(
-        "time_unit",
+    context(
+        "time unit (second, minute, hour, day, week, month or year)",
         alt((
-            tag("second"),
-            tag("minute"),
-            tag("hour"),
-            tag("day"),
-            tag("week"),
-            tag("month"),
-            tag("year")
+            map(keyword("second"), |_| TimeUnit::Second),
+            map(keyword("minute"), |_| TimeUnit::Minute),
+            map(keyword("hour"), |_| TimeUnit::Hour),
+            map(keyword("day"), |_| TimeUnit::Day),
+            map(keyword("week"), |_| TimeUnit::Week),
+            map(keyword("month"), |_| TimeUnit::Month),
+            map(keyword("year"), |_| TimeUnit::Year)
         ))
-    )(s)?;
-    let unit = match unit_str {
-        "second" => TimeUnit::Second,
-        "minute" => TimeUnit::Minute,
-        "hour" => TimeUnit::Hour,
-        "day" => TimeUnit::Day,
-        "week" => TimeUnit::Week,
-        "month" => TimeUnit::Month,
-        "year" => TimeUnit::Year,
-        _ => panic!("Reached unreachable state. Draw your own conclusions.")
-    };
-    Ok((input, unit))
+    )(s)
 }
 
 fn integer(s: &str) -> IResult<&str, u64, Error<&str>> {
     context(
-        "integer",
+        "number",
         map_res(
             recognize(
                 many1(
@@ -344,19 +1164,64 @@ mod test {
     #[test]
     fn weekday() {
         let test_cases = vec![
-            ("Mon", ConditionWeekday::Mon),
-            ("Tue", ConditionWeekday::Tue),
-            ("Wed", ConditionWeekday::Wed),
-            ("Thu", ConditionWeekday::Thu),
-            ("Fri", ConditionWeekday::Fri),
-            ("Sat", ConditionWeekday::Sat),
-            ("Sun", ConditionWeekday::Sun),
+            ("Mon", ConditionWeekday::of(&[Weekday::Mon])),
+            ("Tue", ConditionWeekday::of(&[Weekday::Tue])),
+            ("Wed", ConditionWeekday::of(&[Weekday::Wed])),
+            ("Thu", ConditionWeekday::of(&[Weekday::Thu])),
+            ("Fri", ConditionWeekday::of(&[Weekday::Fri])),
+            ("Sat", ConditionWeekday::of(&[Weekday::Sat])),
+            ("Sun", ConditionWeekday::of(&[Weekday::Sun])),
         ];
         for (expr, cnd) in test_cases {
             assert_eq!(Ok(Condition::Weekday(cnd)), parse_condition(expr));
         };
     }
 
+    #[test]
+    fn weekday_sets() {
+        use Weekday::*;
+        let days = |days: &[Weekday]| Ok(Condition::Weekday(ConditionWeekday::of(days)));
+        let test_cases = vec![
+            ("Monday", days(&[Mon])),
+            ("Mon..Fri", days(&[Mon, Tue, Wed, Thu, Fri])),
+            ("Thursday .. Sat", days(&[Thu, Fri, Sat])),
+            ("Fri..Mon", days(&[Fri, Sat, Sun, Mon])),
+            ("Wed..Wed", days(&[Wed])),
+            ("{Sat, Sun}", days(&[Sat, Sun])),
+            ("{ Mon..Wed ,Fri,Wed }", days(&[Mon, Tue, Wed, Fri])),
+            ("weekday", days(&[Mon, Tue, Wed, Thu, Fri])),
+            ("weekend", days(&[Sat, Sun])),
+            ("today is Monday", days(&[Mon])),
+            ("today is {Mon, Sun}", days(&[Mon, Sun])),
+            ("today is weekend", days(&[Sat, Sun])),
+            ("MON", days(&[Mon])),
+            ("Today Is SUNDAY", days(&[Sun])),
+            ("tue..thu", days(&[Tue, Wed, Thu])),
+        ];
+        for (e, expected) in test_cases {
+            assert_eq!(expected, parse_condition(e), "Parsing '{}'", e);
+        }
+        let rejected = vec![
+            "{}", "{Mon", "{Mon,}", "Mon..", "Mon..weekend", "today Mon", "today is"
+        ];
+        for e in rejected {
+            assert!(parse_condition(e).is_err(), "Should reject '{}'", e);
+        }
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        assert_eq!(
+            parse_condition("not Mon and atmost 1 h of a in this day or usage(last 2 hour) > 1 m"),
+            parse_condition("NOT mon And ATMOST 1 h Of a In This Day Or Usage(LAST 2 Hour) > 1 m")
+        );
+        // Units of durations are not keywords, `M` is a month
+        assert_ne!(
+            parse_condition("usage(this day) > 1 m"),
+            parse_condition("usage(this day) > 1 M")
+        );
+    }
+
     #[test]
     fn whitespace_around_expression_doesnt_matter() {
         let examples = vec![
@@ -367,7 +1232,7 @@ mod test {
             "\tMon\n\n"
         ];
         for e in examples {
-            assert_eq!(Ok(Condition::Weekday(ConditionWeekday::Mon)), parse_condition(e));
+            assert_eq!(Ok(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))), parse_condition(e));
         }
     }
 
@@ -376,7 +1241,7 @@ mod test {
         assert_eq!(
             Ok(
                 Condition::Not(ConditionNot { 
-                    c: Box::new(Condition::Weekday(ConditionWeekday::Mon)),
+                    c: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))),
                 })
             ),
             parse_condition("not Mon")
@@ -401,8 +1266,8 @@ mod test {
             assert_eq!(
                 Ok(
                     Condition::And(ConditionAnd {
-                        c1: Box::new(Condition::Weekday(ConditionWeekday::Mon)),
-                        c2: Box::new(Condition::Weekday(ConditionWeekday::Mon)),
+                        c1: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))),
+                        c2: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))),
                     })
                 ),
                 parse_condition(e),
@@ -418,17 +1283,145 @@ mod test {
                 Condition::And(ConditionAnd {
                     c1: Box::new(
                             Condition::Or(ConditionOr {
-                                c1: Box::new(Condition::Weekday(ConditionWeekday::Mon)),
-                                c2: Box::new(Condition::Weekday(ConditionWeekday::Mon)),
+                                c1: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))),
+                                c2: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))),
                             })
                         ),
-                    c2: Box::new(Condition::Weekday(ConditionWeekday::Mon)),
+                    c2: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))),
                 })
             ),
-            parse_condition("(Mon or Mon) and Mon)")
+            parse_condition("(Mon or Mon) and Mon")
         );
     }
 
+    #[test]
+    fn precedence_and_associativity() {
+        use Weekday::*;
+        let wd = |d| Condition::Weekday(ConditionWeekday::of(&[d]));
+        let and = |c1, c2| Condition::And(ConditionAnd {
+            c1: Box::new(c1), c2: Box::new(c2)
+        });
+        let or = |c1, c2| Condition::Or(ConditionOr {
+            c1: Box::new(c1), c2: Box::new(c2)
+        });
+        let not = |c| Condition::Not(ConditionNot { c: Box::new(c) });
+        let between = || Condition::Between(ConditionBetween {
+            from: Time { hour: 9, minute: 0 },
+            to: Time { hour: 17, minute: 0 }
+        });
+        let over_1h = || Condition::Compare(ConditionCompare {
+            lhs: DurationExpr::Usage(Usage {
+                subject: Subject::Current,
+                window: Window::This(TimeUnit::Day)
+            }),
+            op: CompareOp::Gt,
+            rhs: DurationExpr::Literal(Span { months: 0, seconds: 3600 })
+        });
+        let test_cases = vec![
+            ("Mon or Tue and Wed", or(wd(Mon), and(wd(Tue), wd(Wed)))),
+            ("Mon and Tue or Wed", or(and(wd(Mon), wd(Tue)), wd(Wed))),
+            ("Mon and Tue and Wed", and(and(wd(Mon), wd(Tue)), wd(Wed))),
+            ("Mon or Tue or Wed", or(or(wd(Mon), wd(Tue)), wd(Wed))),
+            (
+                "Mon or Tue or Wed or Thu or Fri",
+                or(or(or(or(wd(Mon), wd(Tue)), wd(Wed)), wd(Thu)), wd(Fri))
+            ),
+            ("(Mon or Tue) and Wed", and(or(wd(Mon), wd(Tue)), wd(Wed))),
+            ("Mon and (Tue or Wed)", and(wd(Mon), or(wd(Tue), wd(Wed)))),
+            ("Mon and (Tue and Wed)", and(wd(Mon), and(wd(Tue), wd(Wed)))),
+            ("not Mon and Tue", and(not(wd(Mon)), wd(Tue))),
+            ("not Mon or Tue", or(not(wd(Mon)), wd(Tue))),
+            ("Mon and not Tue", and(wd(Mon), not(wd(Tue)))),
+            (
+                "not Mon or not Tue and Wed",
+                or(not(wd(Mon)), and(not(wd(Tue)), wd(Wed)))
+            ),
+            ("not (Mon or Tue)", not(or(wd(Mon), wd(Tue)))),
+            ("not(Mon)", not(wd(Mon))),
+            ("not not Mon", not(not(wd(Mon)))),
+            (
+                "between 09:00 and 17:00 and Mon or Tue",
+                or(and(between(), wd(Mon)), wd(Tue))
+            ),
+            ("Mon and between 09:00 and 17:00", and(wd(Mon), between())),
+            ("usage(this day) > 1 h and Mon", and(over_1h(), wd(Mon))),
+            ("Mon or usage(this day) > 1 h", or(wd(Mon), over_1h())),
+            ("not usage(this day) > 1 h", not(over_1h())),
+            ("(Mon)and(Tue)", and(wd(Mon), wd(Tue))),
+        ];
+        for (e, expected) in test_cases {
+            assert_eq!(Ok(expected), parse_condition(e), "Trying to parse '{}'", e);
+        }
+    }
+
+    #[test]
+    fn trailing_garbage_is_rejected() {
+        let exprs = vec![
+            "Mon)",
+            "(Mon or Mon) and Mon)",
+            "(Mon",
+            "Mon Tue",
+            "Mon and",
+            "Mon or or Tue",
+            "and Mon",
+            "Mondays",
+            "Mon andTue",
+            "notMon",
+            "atmost 1 h in this dayz",
+            "usage(this day) > 1 h garbage",
+            "usage(this day) > 1 hour",
+        ];
+        for e in exprs {
+            assert!(parse_condition(e).is_err(), "Should reject '{}'", e);
+        }
+    }
+
+    #[test]
+    fn duration_rejects_trailing_garbage() {
+        assert_eq!(Ok(Duration { seconds: 600 }), parse_duration(" 10 m "));
+        assert!(parse_duration("10 m 5").is_err());
+        assert!(parse_duration("10 min").is_err());
+    }
+
+    #[test]
+    fn durations_add_up_their_parts() {
+        assert_eq!(Ok(Duration { seconds: 5400 }), parse_duration("1 h 30 m"));
+        assert_eq!(Ok(Duration { seconds: 90061 }), parse_duration("1 D\n1 h 1 m  1 s"));
+        assert!(parse_duration("1 h and 30 m").is_err());
+        assert!(parse_duration("18446744073709551615 s 1 s").is_err());
+        assert!(parse_duration("18446744073709551615 m").is_err());
+        // Durations in the config are a fixed number of seconds
+        let err = parse_duration("1 M").unwrap_err();
+        assert_eq!(vec!["duration unit (s, m, h, D or W)"], err.expected);
+    }
+
+    #[test]
+    fn spans_may_have_months() {
+        let span = |months, seconds| Span { months, seconds };
+        let test_cases = vec![
+            ("atmost 40 h in sliding 1 M", span(0, 40 * 3600), span(1, 0)),
+            ("atmost 1 h 30 m in sliding 1 Y 6 M", span(0, 5400), span(18, 0)),
+            ("atmost 2 D in sliding 1 M 2 W", span(0, 172800), span(1, 1209600)),
+            ("atmost 1 M in sliding 1 h 2 Y", span(1, 0), span(24, 3600)),
+        ];
+        for (e, limit, window) in test_cases {
+            assert_eq!(
+                Ok(
+                    Condition::Compare(ConditionCompare {
+                        lhs: DurationExpr::Usage(Usage {
+                            subject: Subject::Current,
+                            window: Window::Sliding(window)
+                        }),
+                        op: CompareOp::Lt,
+                        rhs: DurationExpr::Literal(limit)
+                    })
+                ),
+                parse_condition(e),
+                "Parsing '{}'", e
+            );
+        }
+    }
+
     #[test]
     fn at_most_in_sliding() {
         let exprs = vec![
@@ -440,9 +1433,13 @@ mod test {
         for e in exprs {
             assert_eq!(
                 Ok(
-                    Condition::AtMostInSliding(ConditionAtMostInSliding {
-                        limit: Duration { seconds: 15 },
-                        window_size: Duration {seconds: 600 }
+                    Condition::Compare(ConditionCompare {
+                        lhs: DurationExpr::Usage(Usage {
+                            subject: Subject::Current,
+                            window: Window::Sliding(Span { months: 0, seconds: 600 })
+                        }),
+                        op: CompareOp::Lt,
+                        rhs: DurationExpr::Literal(Span { months: 0, seconds: 15 })
                     })
                 ),
                 parse_condition(e)
@@ -461,9 +1458,13 @@ mod test {
         for e in exprs {
             assert_eq!(
                 Ok(
-                    Condition::AtMostInThis(ConditionAtMostInThis {
-                        limit: Duration { seconds: 15 },
-                        time_unit: TimeUnit::Week
+                    Condition::Compare(ConditionCompare {
+                        lhs: DurationExpr::Usage(Usage {
+                            subject: Subject::Current,
+                            window: Window::This(TimeUnit::Week)
+                        }),
+                        op: CompareOp::Lt,
+                        rhs: DurationExpr::Literal(Span { months: 0, seconds: 15 })
                     })
                 ),
                 parse_condition(e)
@@ -472,6 +1473,372 @@ mod test {
     }
 
     #[test]
+    fn relative_windows() {
+        let test_cases = vec![
+            ("usage(last 3 day)", Window::Last { count: 3, unit: TimeUnit::Day }),
+            ("usage( last\t0  hour )", Window::Last { count: 0, unit: TimeUnit::Hour }),
+            ("usage(previous week)", Window::Previous(TimeUnit::Week)),
+            ("usage(yesterday)", Window::Previous(TimeUnit::Day)),
+        ];
+        for (usage, window) in test_cases {
+            assert_eq!(
+                Ok(
+                    Condition::Compare(ConditionCompare {
+                        lhs: DurationExpr::Usage(Usage { subject: Subject::Current, window }),
+                        op: CompareOp::Gt,
+                        rhs: DurationExpr::Literal(Span { months: 0, seconds: 3600 })
+                    })
+                ),
+                parse_condition(&format!("{} > 1 h", usage)),
+                "Parsing '{}'", usage
+            );
+        }
+        let rejected = vec![
+            "atmost 1 h in last day",
+            "atmost 1 h in last 3 D",
+            "atmost 1 h in previous 2 day",
+            "atmost 1 h in yesterdays",
+        ];
+        for e in rejected {
+            assert!(parse_condition(e).is_err(), "Parsing '{}'", e);
+        }
+    }
+
+    #[test]
+    fn between() {
+        let test_cases = vec![
+            ("between 09:00 and 17:30", (9, 0), (17, 30)),
+            ("between 9:00 and 17:30", (9, 0), (17, 30)),
+            ("between\t22:00\nand  06:05", (22, 0), (6, 5)),
+            ("between 00:00 and 23:59", (0, 0), (23, 59)),
+        ];
+        for (expr, (from_h, from_m), (to_h, to_m)) in test_cases {
+            assert_eq!(
+                Ok(
+                    Condition::Between(ConditionBetween {
+                        from: Time { hour: from_h, minute: from_m },
+                        to: Time { hour: to_h, minute: to_m }
+                    })
+                ),
+                parse_condition(expr),
+                "Trying to parse '{}'", expr
+            );
+        }
+    }
+
+    #[test]
+    fn between_rejects_invalid_times() {
+        let exprs = vec![
+            "between 24:00 and 06:00",
+            "between 22:00 and 06:60",
+            "between 26:99 and 06:00",
+            "between 22:0 and 06:00",
+            "between 22 and 06:00",
+        ];
+        for e in exprs {
+            assert!(parse_condition(e).is_err(), "Should reject '{}'", e);
+        }
+    }
+
+    #[test]
+    fn between_combines_with_and() {
+        assert_eq!(
+            Ok(
+                Condition::And(ConditionAnd {
+                    c1: Box::new(Condition::Between(ConditionBetween {
+                        from: Time { hour: 9, minute: 0 },
+                        to: Time { hour: 17, minute: 0 }
+                    })),
+                    c2: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon]))),
+                })
+            ),
+            parse_condition("between 09:00 and 17:00 and Mon")
+        );
+    }
+
+    #[test]
+    fn dates() {
+        let date = |y, m, d| NaiveDate::from_ymd(y, m, d);
+        let test_cases = vec![
+            ("on 2026-12-24", date(2026, 12, 24), date(2026, 12, 24)),
+            ("on  2028-02-29", date(2028, 2, 29), date(2028, 2, 29)),
+            ("between 2026-12-20 and 2027-01-06", date(2026, 12, 20), date(2027, 1, 6)),
+            ("between 2026-12-24 and 2026-12-24", date(2026, 12, 24), date(2026, 12, 24)),
+        ];
+        for (e, first, last) in test_cases {
+            assert_eq!(
+                Ok(Condition::Date(DateRange { first, last })),
+                parse_condition(e),
+                "Trying to parse '{}'", e
+            );
+        }
+        let rejected = vec![
+            "on 2027-02-29",
+            "on 2026-12-24T10:00",
+            "on 26-12-24",
+            "between 2026-12-20 and 09:00",
+            "between 2027-01-06 and 2026-12-20",
+        ];
+        for e in rejected {
+            assert!(parse_condition(e).is_err(), "Should reject '{}'", e);
+        }
+    }
+
+    #[test]
+    fn calendars() {
+        assert_eq!(
+            Ok(Condition::Calendar("school-holidays".to_string())),
+            parse_condition("On Calendar school-holidays")
+        );
+        let c = parse_condition("on calendar vacation or not on calendar exams").unwrap();
+        assert_eq!(vec!["vacation", "exams"], c.referenced_calendars());
+        assert!(parse_condition("on calendar").is_err());
+    }
+
+    #[test]
+    fn compare_ops() {
+        let test_cases = vec![
+            ("<", CompareOp::Lt),
+            ("<=", CompareOp::Le),
+            (">", CompareOp::Gt),
+            (">=", CompareOp::Ge),
+            ("==", CompareOp::Eq),
+            ("!=", CompareOp::Ne),
+        ];
+        for (op_str, op) in test_cases {
+            let exprs = vec![
+                format!("usage(this day) {} 30 m", op_str),
+                format!("usage(this day){}30 m", op_str),
+                format!("usage( this day )\n{}\t30 m", op_str),
+            ];
+            for e in exprs {
+                assert_eq!(
+                    Ok(
+                        Condition::Compare(ConditionCompare {
+                            lhs: DurationExpr::Usage(Usage {
+                                subject: Subject::Current,
+                                window: Window::This(TimeUnit::Day)
+                            }),
+                            op,
+                            rhs: DurationExpr::Literal(Span { months: 0, seconds: 1800 })
+                        })
+                    ),
+                    parse_condition(&e),
+                    "Trying to parse '{}'", e
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn duration_arithmetic_is_left_associative() {
+        let lit = |seconds| Box::new(DurationExpr::Literal(Span { months: 0, seconds }));
+        assert_eq!(
+            Ok(
+                Condition::Compare(ConditionCompare {
+                    lhs: DurationExpr::Add(DurationAdd {
+                        d1: Box::new(DurationExpr::Sub(DurationSub {
+                            d1: Box::new(DurationExpr::Usage(Usage {
+                                subject: Subject::Current,
+                                window: Window::Sliding(Span { months: 0, seconds: 7200 })
+                            })),
+                            d2: lit(60)
+                        })),
+                        d2: lit(1)
+                    }),
+                    op: CompareOp::Gt,
+                    rhs: DurationExpr::Sub(DurationSub {
+                        d1: lit(36000),
+                        d2: lit(3600)
+                    })
+                })
+            ),
+            parse_condition("usage(sliding 2 h) - 1 m + 1 s > 10 h - 1 h")
+        );
+    }
+
+    #[test]
+    fn usage_of_other_apps() {
+        let usage_of = |app_id: &str| DurationExpr::Usage(Usage {
+            subject: Subject::App(app_id.to_string()),
+            window: Window::This(TimeUnit::Day)
+        });
+        let test_cases = vec![
+            ("atmost 1 h of discord in this day", "discord"),
+            ("atmost\t1 h\nof  my-app_2.0 in this day", "my-app_2.0"),
+            ("usage(discord, this day) < 1 h", "discord"),
+            ("usage( this ,this day ) < 1 h", "this"),
+        ];
+        for (e, app_id) in test_cases {
+            assert_eq!(
+                Ok(
+                    Condition::Compare(ConditionCompare {
+                        lhs: usage_of(app_id),
+                        op: CompareOp::Lt,
+                        rhs: DurationExpr::Literal(Span { months: 0, seconds: 3600 })
+                    })
+                ),
+                parse_condition(e),
+                "Trying to parse '{}'", e
+            );
+        }
+    }
+
+    #[test]
+    fn usage_of_groups() {
+        let test_cases = vec![
+            "atmost 2 h of group:chat in this day",
+            "usage(group:chat, this day) < 2 h",
+        ];
+        for e in test_cases {
+            assert_eq!(
+                Ok(
+                    Condition::Compare(ConditionCompare {
+                        lhs: DurationExpr::Usage(Usage {
+                            subject: Subject::Group("chat".to_string()),
+                            window: Window::This(TimeUnit::Day)
+                        }),
+                        op: CompareOp::Lt,
+                        rhs: DurationExpr::Literal(Span { months: 0, seconds: 7200 })
+                    })
+                ),
+                parse_condition(e),
+                "Trying to parse '{}'", e
+            );
+        }
+    }
+
+    #[test]
+    fn referenced_groups() {
+        let c = parse_condition(
+            "usage(group:a, this day) + usage(b, this day) > usage(group:c, this day)"
+        ).unwrap();
+        assert_eq!(vec!["a", "c"], c.referenced_groups());
+        assert_eq!(vec!["b"], c.referenced_apps());
+    }
+
+    #[test]
+    fn referenced_apps() {
+        let test_cases = vec![
+            ("Mon", vec![]),
+            ("atmost 1 h in this day", vec![]),
+            ("atmost 1 h of a in this day", vec!["a"]),
+            (
+                "not (usage(a, this day) + usage(b, sliding 1 h) > usage(this day)) or atmost 1 h of c in this week",
+                vec!["a", "b", "c"]
+            ),
+        ];
+        for (e, apps) in test_cases {
+            assert_eq!(apps, parse_condition(e).unwrap().referenced_apps(), "{}", e);
+        }
+    }
+
+    #[test]
+    fn daily_limits() {
+        let app = Subject::App("a".to_string());
+        let group = Subject::Group("g".to_string());
+        let hours = |h: u64| Duration { seconds: h * 3600 };
+        let test_cases = vec![
+            ("Mon", vec![]),
+            ("atmost 1 h in this day", vec![(&Subject::Current, hours(1))]),
+            ("not atmost 2 h of a in this day and Mon", vec![(&app, hours(2))]),
+            ("usage(group:g, this day) < 3 h or atmost 1 h in this week", vec![(&group, hours(3))]),
+            ("atmost 1 h in sliding 1 D", vec![]),
+            ("usage(this day) > 1 h", vec![]),
+        ];
+        for (e, limits) in test_cases {
+            assert_eq!(limits, parse_condition(e).unwrap().daily_limits(), "{}", e);
+        }
+    }
+
+    #[test]
+    fn type_errors_are_rejected() {
+        let exprs = vec![
+            "3 h",
+            "usage(this day)",
+            "usage(this day) + 1 h",
+            "Mon + 1 h",
+            "usage(this day) > Mon",
+            "not 1 h",
+            "Mon and 1 h",
+            "(1 h) or Tue",
+        ];
+        for e in exprs {
+            let err = parse_condition(e).expect_err(e);
+            assert!(err.type_error.is_some(), "Parsing '{}' gave: {}", e, err);
+        }
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_problem() {
+        let test_cases = vec![
+            ("Mon adn Tue", 4, 1, 5, vec!["`and`", "`or`", "comparison operator", "`+`", "`-`", "end of input"]),
+            ("(Mon or Tue", 11, 1, 12, vec!["`and`", "`or`", "comparison operator", "`+`", "`-`", "`)`"]),
+            ("Mon and\n  Tues", 10, 2, 3, vec!["`not`", "`(`", "weekday", "`between`", "`on`", "`atmost`", "`usage`", "duration"]),
+            ("atmost 1 h in this dya", 19, 1, 20, vec!["time unit (second, minute, hour, day, week, month or year)"]),
+            ("atmost 1 h in sliding 1 hour", 24, 1, 25, vec!["duration unit (s, m, h, D, W, M or Y)"]),
+            ("between 09:00 and 24:00", 18, 1, 19, vec!["time of day (HH:MM)"]),
+            ("between 09:00 and 23:60", 21, 1, 22, vec!["minute (00-59)"]),
+            ("between 2027-01-06 and 2026-12-20", 23, 1, 24, vec!["date not before 2027-01-06"]),
+            ("on 2026-02-30", 3, 1, 4, vec!["`calendar`", "date (YYYY-MM-DD)"]),
+            ("usage(this day) >", 17, 1, 18, vec!["`not`", "`(`", "weekday", "`between`", "`on`", "`atmost`", "`usage`", "duration"]),
+        ];
+        for (e, offset, line, column, expected) in test_cases {
+            let err = parse_condition(e).expect_err(e);
+            assert_eq!(
+                (offset, line, column, expected),
+                (err.offset, err.line, err.column, err.expected.iter().map(|s| s.as_str()).collect()),
+                "Parsing '{}'", e
+            );
+            assert_eq!("condition", err.what);
+            assert_eq!(None, err.type_error);
+        }
+    }
+
+    #[test]
+    fn parse_error_display() {
+        let err = parse_condition("Mon and\n  Tue adn Wed").unwrap_err();
+        assert_eq!(
+            "Couldn't parse condition at line 2, column 7: expected `and`, `or`, comparison operator, `+`, `-` or end of input\n  Tue adn Wed\n      ^",
+            err.to_string()
+        );
+        let err = parse_condition("  Mon + 1 h < 2 h").unwrap_err();
+        assert_eq!(
+            "Couldn't parse condition at line 1, column 3: type error: left operand of `+` must be of type Duration, but is of type Bool\n  Mon + 1 h < 2 h\n  ^^^^^^^^^^^^^^^",
+            err.to_string()
+        );
+        let err = parse_duration("10 min").unwrap_err();
+        assert_eq!(
+            "Couldn't parse duration at line 1, column 4: expected duration unit (s, m, h, D or W)\n10 min\n   ^",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn comparisons_display_as_condition_language() {
+        let test_cases = vec![
+            ("usage(this day) >= 30 m", "usage(this day) >= 30 m"),
+            (
+                "usage(this day) - (usage(a, sliding 90 m) + 1 h) != 0 s",
+                "usage(this day) - (usage(a, sliding 90 m) + 1 h) != 0 s"
+            ),
+            ("(1 h + 1 h) + 1 h == 3600 s", "1 h + 1 h + 1 h == 1 h"),
+            ("atmost 2 D of group:chat in sliding 1 W", "usage(group:chat, sliding 1 W) < 2 D"),
+            ("atmost 2 h in last 6 day", "usage(last 6 day) < 2 h"),
+            ("usage(a, yesterday) > 1 h", "usage(a, previous day) > 1 h"),
+            ("atmost 1 h 30 m in sliding 12 M", "usage(sliding 1 Y) < 90 m"),
+            ("usage(sliding 1 Y 3 M 1 D) > 1 M 2 h", "usage(sliding 15 M 1 D) > 1 M 2 h"),
+        ];
+        for (input, expected) in test_cases {
+            match parse_condition(input).unwrap() {
+                Condition::Compare(c) => assert_eq!(expected, c.to_string()),
+                other => panic!("Expected comparison, got {:?}", other)
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::zero_prefixed_literal)]
     fn integers() {
         let test_cases = vec![0, 1, 10, 12343, 0007, 18446744073709551615u64];
         for t in test_cases {
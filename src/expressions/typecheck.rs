@@ -0,0 +1,184 @@
+use crate::expressions::parser::{
+    Condition, ConditionAnd, ConditionCompare, ConditionNot, ConditionOr,
+    DurationAdd, DurationExpr, DurationSub, Expr
+};
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Type {
+    Bool,
+    Duration
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Bool => write!(f, "Bool"),
+            Type::Duration => write!(f, "Duration")
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct TypeError {
+    /// Which part of the expression had the wrong type, e.g. "left operand of `+`"
+    pub position: String,
+    pub expected: Type,
+    pub found: Type
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} must be of type {}, but is of type {}",
+            self.position, self.expected, self.found
+        )
+    }
+}
+
+/// The type of an untyped expression. The type of every node is determined by
+/// the node alone, so this doesn't check the children.
+pub fn type_of(e: &Expr) -> Type {
+    match e {
+        Expr::And(_, _)
+        | Expr::Or(_, _)
+        | Expr::Not(_)
+        | Expr::Compare(_, _, _)
+        | Expr::Weekday(_)
+        | Expr::Between(_)
+        | Expr::Date(_)
+        | Expr::Calendar(_) => Type::Bool,
+        Expr::Add(_, _)
+        | Expr::Sub(_, _)
+        | Expr::Duration(_)
+        | Expr::Usage(_) => Type::Duration
+    }
+}
+
+/// Type checks a top level expression, which must be of type Bool.
+pub fn condition(e: Expr) -> Result<Condition, TypeError> {
+    bool_expr(e, "condition")
+}
+
+fn bool_expr(e: Expr, position: &str) -> Result<Condition, TypeError> {
+    match e {
+        Expr::And(e1, e2) => Ok(Condition::And(ConditionAnd {
+            c1: Box::new(bool_expr(*e1, "left operand of `and`")?),
+            c2: Box::new(bool_expr(*e2, "right operand of `and`")?)
+        })),
+        Expr::Or(e1, e2) => Ok(Condition::Or(ConditionOr {
+            c1: Box::new(bool_expr(*e1, "left operand of `or`")?),
+            c2: Box::new(bool_expr(*e2, "right operand of `or`")?)
+        })),
+        Expr::Not(e) => Ok(Condition::Not(ConditionNot {
+            c: Box::new(bool_expr(*e, "operand of `not`")?)
+        })),
+        Expr::Compare(lhs, op, rhs) => Ok(Condition::Compare(ConditionCompare {
+            lhs: duration_expr(*lhs, "left operand of comparison")?,
+            op,
+            rhs: duration_expr(*rhs, "right operand of comparison")?
+        })),
+        Expr::Weekday(wd) => Ok(Condition::Weekday(wd)),
+        Expr::Between(b) => Ok(Condition::Between(b)),
+        Expr::Date(range) => Ok(Condition::Date(range)),
+        Expr::Calendar(name) => Ok(Condition::Calendar(name)),
+        e => Err(TypeError {
+            position: position.to_string(),
+            expected: Type::Bool,
+            found: type_of(&e)
+        })
+    }
+}
+
+fn duration_expr(e: Expr, position: &str) -> Result<DurationExpr, TypeError> {
+    match e {
+        Expr::Add(e1, e2) => Ok(DurationExpr::Add(DurationAdd {
+            d1: Box::new(duration_expr(*e1, "left operand of `+`")?),
+            d2: Box::new(duration_expr(*e2, "right operand of `+`")?)
+        })),
+        Expr::Sub(e1, e2) => Ok(DurationExpr::Sub(DurationSub {
+            d1: Box::new(duration_expr(*e1, "left operand of `-`")?),
+            d2: Box::new(duration_expr(*e2, "right operand of `-`")?)
+        })),
+        Expr::Duration(d) => Ok(DurationExpr::Literal(d)),
+        Expr::Usage(u) => Ok(DurationExpr::Usage(u)),
+        e => Err(TypeError {
+            position: position.to_string(),
+            expected: Type::Duration,
+            found: type_of(&e)
+        })
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::expressions::parser::*;
+    use crate::expressions::typecheck::*;
+
+    use chrono::Weekday;
+
+    #[test]
+    fn bool_expressions_are_conditions() {
+        let e = Expr::Not(Box::new(Expr::Weekday(ConditionWeekday::of(&[Weekday::Mon]))));
+        assert_eq!(
+            Ok(Condition::Not(ConditionNot {
+                c: Box::new(Condition::Weekday(ConditionWeekday::of(&[Weekday::Mon])))
+            })),
+            condition(e)
+        );
+    }
+
+    #[test]
+    fn duration_is_not_a_condition() {
+        let e = Expr::Duration(Span { months: 0, seconds: 60 });
+        assert_eq!(
+            Err(TypeError {
+                position: "condition".to_string(),
+                expected: Type::Bool,
+                found: Type::Duration
+            }),
+            condition(e)
+        );
+    }
+
+    #[test]
+    fn operands_are_checked() {
+        let mon = || Box::new(Expr::Weekday(ConditionWeekday::of(&[Weekday::Mon])));
+        let minute = || Box::new(Expr::Duration(Span { months: 0, seconds: 60 }));
+        let test_cases = vec![
+            (Expr::And(mon(), minute()), "right operand of `and`", Type::Bool),
+            (Expr::Or(minute(), mon()), "left operand of `or`", Type::Bool),
+            (Expr::Not(minute()), "operand of `not`", Type::Bool),
+            (
+                Expr::Compare(mon(), CompareOp::Lt, minute()),
+                "left operand of comparison",
+                Type::Duration
+            ),
+            (
+                Expr::Compare(
+                    minute(),
+                    CompareOp::Lt,
+                    Box::new(Expr::Add(minute(), mon()))
+                ),
+                "right operand of `+`",
+                Type::Duration
+            ),
+            (
+                Expr::Compare(
+                    Box::new(Expr::Sub(mon(), minute())),
+                    CompareOp::Lt,
+                    minute()
+                ),
+                "left operand of `-`",
+                Type::Duration
+            ),
+        ];
+        for (e, position, expected) in test_cases {
+            let err = condition(e).expect_err("Expected a type error");
+            assert_eq!(position, err.position);
+            assert_eq!(expected, err.expected);
+        }
+    }
+}
@@ -1,5 +1,8 @@
+pub mod calendar;
+pub mod clock;
 pub mod cmd;
 pub mod config;
 pub mod db;
 pub mod expressions;
+pub mod process;
 
@@ -0,0 +1,422 @@
+pub mod scanner;
+pub mod tree;
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The signals that hook actions can send
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Signal {
+    Term,
+    Kill,
+    Stop,
+    Cont
+}
+
+impl Signal {
+    fn number(self) -> libc::c_int {
+        match self {
+            Signal::Term => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Stop => libc::SIGSTOP,
+            Signal::Cont => libc::SIGCONT
+        }
+    }
+}
+
+/// Sends a signal to a process.
+pub fn signal_process(pid: u32, signal: Signal) -> io::Result<()> {
+    kill(pid as libc::pid_t, signal)
+}
+
+/// Sends a signal to all processes in a process group.
+pub fn signal_group(pgid: u32, signal: Signal) -> io::Result<()> {
+    kill(-(pgid as libc::pid_t), signal)
+}
+
+fn kill(pid: libc::pid_t, signal: Signal) -> io::Result<()> {
+    // Safe, since kill doesn't touch any memory of this process
+    if unsafe { libc::kill(pid, signal.number()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Process group that SIGINT and SIGTERM are forwarded to, 0 if none
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pgid = FORWARD_TO.load(Ordering::SeqCst);
+    if pgid > 0 {
+        // Safe, and allowed in a signal handler
+        unsafe { libc::kill(-pgid, signal) };
+    }
+}
+
+/// Forwards SIGINT and SIGTERM to all processes in a process group, instead
+/// of exiting on them.
+pub fn forward_signals_to_group(pgid: u32) -> io::Result<()> {
+    FORWARD_TO.store(pgid as i32, Ordering::SeqCst);
+    for &signal in &[libc::SIGINT, libc::SIGTERM] {
+        // Safe, since the handler only reads an atomic and calls kill
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The process group of this process, if it is the foreground process group
+/// of the terminal on stdin.
+pub fn own_foreground_group() -> Option<u32> {
+    // Safe, since neither touches any memory of this process
+    let (foreground, own) = unsafe {
+        (libc::tcgetpgrp(libc::STDIN_FILENO), libc::getpgrp())
+    };
+    if foreground == own { Some(own as u32) } else { None }
+}
+
+/// Makes a process group the foreground process group of the terminal on
+/// stdin, so that it can read from it and gets Ctrl-C. Only uses functions
+/// that are allowed between fork and exec.
+pub fn set_foreground_group(pgid: u32) -> io::Result<()> {
+    // A process that isn't in the foreground gets SIGTTOU for this, which
+    // would stop it. Safe, since neither touches any memory of this process.
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        let result = libc::tcsetpgrp(libc::STDIN_FILENO, pgid as libc::pid_t);
+        let error = io::Error::last_os_error();
+        libc::signal(libc::SIGTTOU, previous);
+        if result == 0 { Ok(()) } else { Err(error) }
+    }
+}
+
+/// How long to sleep between checks on a child process that has a timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Process ids of the children started by this module that haven't been
+/// reaped yet, from all threads
+static SPAWNED: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+fn spawned() -> MutexGuard<'static, BTreeSet<u32>> {
+    // The set stays consistent even if a thread panicked while holding it
+    SPAWNED.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Calls `f` with the ids of all children that were started through this
+/// module, by any thread, and haven't been reaped yet. These are reaped by
+/// `std::process`, so nothing else may wait for them. No children are
+/// started or reaped until `f` returns.
+pub fn with_spawned<T, F: FnOnce(&BTreeSet<u32>) -> T>(f: F) -> T {
+    f(&spawned())
+}
+
+/// Child processes that run in the background. They are reaped once they
+/// exit, so they don't linger as zombies, and killed if they take too long.
+#[derive(Default)]
+pub struct Children {
+    running: Vec<Tracked>
+}
+
+impl Children {
+    pub fn new() -> Children {
+        Children::default()
+    }
+
+    /// Starts the command in the background. `label` describes the process
+    /// to the user, and ends up in the `Finished` record. Returns the id of
+    /// the process.
+    pub fn spawn(&mut self, label: String, command: &mut Command, timeout: Option<Duration>)
+        -> io::Result<u32> {
+        let tracked = Tracked::spawn(label, command, timeout)?;
+        let pid = tracked.child.id();
+        self.running.push(tracked);
+        Ok(pid)
+    }
+
+    /// Takes over the children of `other`, which is left empty
+    pub fn append(&mut self, other: &mut Children) {
+        self.running.append(&mut other.running);
+    }
+
+    /// Reaps all children that have exited, and kills those that ran out of
+    /// time. Doesn't block.
+    pub fn reap(&mut self) -> Vec<Finished> {
+        let mut finished = vec![];
+        let now = Instant::now();
+        self.running.retain_mut(|tracked| match tracked.poll(now) {
+            Some(f) => {
+                finished.push(f);
+                false
+            },
+            None => true
+        });
+        finished
+    }
+
+    /// Waits until all children have exited or were killed after timing out.
+    pub fn wait_all(&mut self) -> Vec<Finished> {
+        let mut finished = self.reap();
+        while !self.running.is_empty() {
+            thread::sleep(POLL_INTERVAL);
+            finished.extend(self.reap());
+        }
+        finished
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+}
+
+/// Runs the command to completion, but kills it if it takes longer than
+/// `timeout`.
+pub fn run_with_timeout(label: String, command: &mut Command, timeout: Option<Duration>)
+    -> io::Result<Finished> {
+    let mut tracked = Tracked::spawn(label, command, timeout)?;
+    if timeout.is_none() {
+        let outcome = match tracked.child.wait() {
+            Ok(status) => Outcome::Exited(status),
+            Err(e) => Outcome::Lost(e)
+        };
+        return Ok(tracked.finished(outcome));
+    }
+    loop {
+        if let Some(finished) = tracked.poll(Instant::now()) {
+            return Ok(finished);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Record of a child process that is gone
+#[derive(Debug)]
+pub struct Finished {
+    /// Describes the process, e.g. "Time hook `notify-send`"
+    pub label: String,
+    pub pid: u32,
+    pub outcome: Outcome,
+    /// Time from starting the process until it was reaped
+    pub runtime: Duration
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Exited(ExitStatus),
+    /// Killed, because it ran for longer than its timeout
+    TimedOut,
+    /// Waiting for the process failed, so it's gone, but its exit status
+    /// is unknown
+    Lost(io::Error)
+}
+
+impl Finished {
+    /// Whether the process exited by itself, with exit code 0
+    pub fn success(&self) -> bool {
+        matches!(self.outcome, Outcome::Exited(status) if status.success())
+    }
+}
+
+impl fmt::Display for Finished {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let runtime = self.runtime.as_secs_f64();
+        match &self.outcome {
+            Outcome::Exited(status) =>
+                write!(f, "{} finished after {:.1} s with {}", self.label, runtime, status),
+            Outcome::TimedOut =>
+                write!(f, "{} timed out after {:.1} s and was killed", self.label, runtime),
+            Outcome::Lost(e) => write!(
+                f, "{} finished after {:.1} s with unknown status: {}", self.label, runtime, e
+            )
+        }
+    }
+}
+
+struct Tracked {
+    label: String,
+    child: Child,
+    started: Instant,
+    timeout: Option<Duration>
+}
+
+impl Tracked {
+    fn spawn(label: String, command: &mut Command, timeout: Option<Duration>)
+        -> io::Result<Tracked> {
+        // Registered before anyone can see that the child has exited
+        let mut spawned = spawned();
+        let child = command.spawn()?;
+        spawned.insert(child.id());
+        Ok(Tracked { label, child, started: Instant::now(), timeout })
+    }
+
+    /// Checks whether the child has exited, and kills it if it is over time.
+    /// Returns None while the child is still running.
+    fn poll(&mut self, now: Instant) -> Option<Finished> {
+        match self.child.try_wait() {
+            Ok(Some(status)) => return Some(self.finished(Outcome::Exited(status))),
+            // The child can't be waited for, so it won't ever be reaped here
+            Err(e) => return Some(self.finished(Outcome::Lost(e))),
+            Ok(None) => {}
+        }
+        match self.timeout {
+            Some(timeout) if now.duration_since(self.started) >= timeout => {
+                // Kill fails if the child exited in the meantime, in which
+                // case wait reaps it all the same
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                Some(self.finished(Outcome::TimedOut))
+            },
+            _ => None
+        }
+    }
+
+    /// Record of the child, which must have been reaped
+    fn finished(&self, outcome: Outcome) -> Finished {
+        spawned().remove(&self.child.id());
+        Finished {
+            label: self.label.clone(),
+            pid: self.child.id(),
+            outcome,
+            runtime: self.started.elapsed()
+        }
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::process::*;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::Command;
+
+    #[test]
+    fn signals_process() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        signal_process(child.id(), Signal::Term).unwrap();
+        assert_eq!(Some(libc::SIGTERM), child.wait().unwrap().signal());
+    }
+
+    #[test]
+    fn signals_process_group() {
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 10; true"])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        signal_group(child.id(), Signal::Kill).unwrap();
+        assert_eq!(Some(libc::SIGKILL), child.wait().unwrap().signal());
+    }
+
+    #[test]
+    fn forwards_signals_to_group() {
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 10; true"])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        forward_signals_to_group(child.id()).unwrap();
+        unsafe { libc::raise(libc::SIGINT) };
+        let status = child.wait().unwrap();
+        // Let the test harness exit on SIGINT and SIGTERM again
+        FORWARD_TO.store(0, Ordering::SeqCst);
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGTERM, libc::SIG_DFL);
+        }
+        assert_eq!(Some(libc::SIGINT), status.signal());
+    }
+
+    #[test]
+    fn signalling_a_missing_process_is_an_error() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(signal_process(pid, Signal::Term).is_err());
+    }
+
+    #[test]
+    fn children_are_reaped() {
+        let mut children = Children::new();
+        children.spawn("ok".to_string(), &mut Command::new("true"), None).unwrap();
+        children.spawn("fails".to_string(), &mut Command::new("false"), None).unwrap();
+        children.spawn(
+            "slow".to_string(),
+            Command::new("sleep").arg("10"),
+            Some(Duration::from_millis(100))
+        ).unwrap();
+        assert_eq!(3, children.len());
+
+        // Children finish in any order
+        let mut finished = children.wait_all();
+        finished.sort_by(|a, b| a.label.cmp(&b.label));
+        assert!(children.is_empty());
+        let outcomes: Vec<(&str, bool)> = finished.iter()
+            .map(|f| (f.label.as_str(), f.success()))
+            .collect();
+        assert_eq!(vec![("fails", false), ("ok", true), ("slow", false)], outcomes);
+        assert!(matches!(finished[2].outcome, Outcome::TimedOut));
+        assert!(finished[2].runtime >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn reap_doesnt_block() {
+        let mut children = Children::new();
+        children.spawn("slow".to_string(), Command::new("sleep").arg("10"), None).unwrap();
+        assert!(children.reap().is_empty());
+        assert_eq!(1, children.len());
+        signal_process(children.running[0].child.id(), Signal::Kill).unwrap();
+        let finished = children.wait_all();
+        assert_eq!(1, finished.len());
+        assert!(!finished[0].success());
+    }
+
+    #[test]
+    fn children_that_cant_be_waited_for_are_finished() {
+        let mut children = Children::new();
+        children.spawn("reaped".to_string(), &mut Command::new("true"), None).unwrap();
+        let pid = children.running[0].child.id();
+        assert!(with_spawned(|pids| pids.contains(&pid)));
+        // As if another thread had reaped it
+        unsafe { libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), 0) };
+        let finished = children.wait_all();
+        assert!(matches!(finished[0].outcome, Outcome::Lost(_)), "{:?}", finished);
+        assert!(!with_spawned(|pids| pids.contains(&pid)));
+    }
+
+    #[test]
+    fn run_with_timeout_kills_hung_processes() {
+        let finished = run_with_timeout(
+            "hung".to_string(),
+            Command::new("sleep").arg("10"),
+            Some(Duration::from_millis(100))
+        ).unwrap();
+        assert!(matches!(finished.outcome, Outcome::TimedOut));
+        assert!(finished.to_string().ends_with(" s and was killed"), "{}", finished);
+
+        let finished = run_with_timeout(
+            "quick".to_string(),
+            Command::new("sh").args(["-c", "exit 3"]),
+            Some(Duration::from_secs(10))
+        ).unwrap();
+        assert!(!finished.success());
+        assert!(finished.to_string().ends_with(" s with exit status: 3"), "{}", finished);
+        assert!(run_with_timeout("x".to_string(), &mut Command::new("/nonexistent"), None).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// A process, as described by `/proc/<pid>`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Parent process id
+    pub ppid: u32,
+    /// Session id, which daemons change to detach from their parent's
+    /// session
+    pub session: u32,
+    /// Exited, but not yet reaped by its parent
+    pub zombie: bool,
+    /// Name of the process, usually the first 15 bytes of the executable's
+    /// file name
+    pub comm: String,
+    /// Path of the executable. None if it can't be read, e.g. because the
+    /// process belongs to another user.
+    pub exe: Option<PathBuf>,
+    /// Command line, including the program name. Empty for kernel threads
+    /// and zombies.
+    pub cmdline: Vec<String>
+}
+
+/// Source of the process table. Reads `/proc` in practice, but tests can
+/// provide a fixed list of processes instead.
+pub trait Scanner {
+    /// All processes that currently exist
+    fn scan(&mut self) -> io::Result<Vec<ProcessInfo>>;
+}
+
+/// Scans `/proc`
+pub struct ProcScanner;
+
+impl Scanner for ProcScanner {
+    fn scan(&mut self) -> io::Result<Vec<ProcessInfo>> {
+        scan()
+    }
+}
+
+/// Reads all processes from `/proc`. Processes that exit while the scan is
+/// running are skipped.
+pub fn scan() -> io::Result<Vec<ProcessInfo>> {
+    let mut processes = vec![];
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let is_pid = entry.file_name().to_str()
+            .map(|name| name.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false);
+        if !is_pid {
+            continue;
+        }
+        let path = entry.path();
+        if let Some(mut info) = fs::read_to_string(path.join("stat")).ok()
+            .and_then(|stat| parse_stat(&stat)) {
+            info.exe = fs::read_link(path.join("exe")).ok();
+            info.cmdline = fs::read(path.join("cmdline"))
+                .map(|cmdline| parse_cmdline(&cmdline))
+                .unwrap_or_default();
+            processes.push(info);
+        }
+    }
+    Ok(processes)
+}
+
+/// Parses the contents of `/proc/<pid>/stat`. The command name is in
+/// brackets and may itself contain spaces and brackets, so the fields are
+/// counted from the last closing bracket.
+fn parse_stat(stat: &str) -> Option<ProcessInfo> {
+    let (pid, rest) = stat.split_once(" (")?;
+    let comm_end = rest.rfind(')')?;
+    let mut fields = rest[comm_end + 1..].split_whitespace();
+    let state = fields.next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    let _pgrp = fields.next()?;
+    let session = fields.next()?.parse().ok()?;
+    Some(ProcessInfo {
+        pid: pid.parse().ok()?,
+        ppid,
+        session,
+        zombie: state == "Z",
+        comm: rest[..comm_end].to_string(),
+        ..ProcessInfo::default()
+    })
+}
+
+/// Parses the contents of `/proc/<pid>/cmdline`, in which each argument is
+/// terminated by a null byte.
+fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    cmdline.split(|b| *b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect::<Vec<String>>()
+        .split_last()
+        .map(|(last, args)| {
+            let mut args = args.to_vec();
+            // Processes may overwrite their command line without the
+            // trailing null byte
+            if !last.is_empty() {
+                args.push(last.clone());
+            }
+            args
+        })
+        .unwrap_or_default()
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::process::scanner::*;
+
+    #[test]
+    fn parses_stat() {
+        assert_eq!(
+            Some(ProcessInfo {
+                pid: 42,
+                ppid: 7,
+                session: 3,
+                comm: "Web Content".to_string(),
+                ..ProcessInfo::default()
+            }),
+            parse_stat("42 (Web Content) S 7 42 3 0 -1 4194560 1 0 0 0")
+        );
+        assert_eq!(
+            Some(ProcessInfo {
+                pid: 43,
+                ppid: 1,
+                session: 1,
+                zombie: true,
+                comm: "a) b) (c".to_string(),
+                ..ProcessInfo::default()
+            }),
+            parse_stat("43 (a) b) (c) Z 1 43 1 0")
+        );
+        assert_eq!(None, parse_stat("garbage"));
+    }
+
+    #[test]
+    fn parses_cmdline() {
+        assert_eq!(vec!["firefox", "-P", ""], parse_cmdline(b"firefox\0-P\0\0"));
+        assert_eq!(vec!["renamed process"], parse_cmdline(b"renamed process"));
+        assert_eq!(Vec::<String>::new(), parse_cmdline(b""));
+    }
+
+    #[test]
+    fn scan_finds_this_process() {
+        let me = std::process::id();
+        let processes = scan().unwrap();
+        let this = processes.iter().find(|p| p.pid == me).unwrap();
+        assert_eq!(Some(std::env::current_exe().unwrap()), this.exe);
+        assert!(!this.cmdline.is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use crate::process::scanner::ProcessInfo;
+
+/// Makes this process the reaper of all orphaned descendants, instead of
+/// init. This keeps descendants of an app that outlive their parent within
+/// reach: their parent becomes this process, instead of some unrelated one.
+pub fn become_subreaper() -> io::Result<()> {
+    // Safe, since this only sets a flag on this process
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Reaps orphans that were re-parented to this process and have exited.
+/// Processes in `own_children` are left alone, since they were started via
+/// `std::process`, which reaps them itself.
+pub fn reap_orphans(processes: &[ProcessInfo], own_children: &BTreeSet<u32>) {
+    let me = std::process::id();
+    for p in processes {
+        if p.zombie && p.ppid == me && !own_children.contains(&p.pid) {
+            // Safe, since a null status pointer is allowed
+            unsafe { libc::waitpid(p.pid as libc::pid_t, std::ptr::null_mut(), libc::WNOHANG) };
+        }
+    }
+}
+
+/// Session id of a process
+pub fn session_of(pid: u32) -> io::Result<u32> {
+    // Safe, since getsid doesn't touch any memory of this process
+    match unsafe { libc::getsid(pid as libc::pid_t) } {
+        -1 => Err(io::Error::last_os_error()),
+        sid => Ok(sid as u32)
+    }
+}
+
+/// Follows a process and all of its descendants, including those that
+/// outlive their parent, e.g. when a launcher starts the actual app and
+/// exits. For the latter to work, the process that started the root has to
+/// be a subreaper (see `become_subreaper`).
+#[derive(Debug)]
+pub struct ProcessTree {
+    root: u32,
+    /// Session of the root. Descendants in other sessions are daemons.
+    session: u32,
+    count_daemons: bool,
+    /// Processes that belong to the tree and are alive
+    members: BTreeSet<u32>
+}
+
+impl ProcessTree {
+    pub fn new(root: u32, session: u32, count_daemons: bool) -> ProcessTree {
+        ProcessTree { root, session, count_daemons, members: [root].into() }
+    }
+
+    /// Updates the members from a snapshot of all processes. `reaper` is the
+    /// process that started the root, if it is a subreaper. Orphans
+    /// re-parented to it are added to the tree, except for those in `others`,
+    /// which `reaper` started for other reasons.
+    pub fn update(&mut self, processes: &[ProcessInfo], reaper: Option<u32>, others: &BTreeSet<u32>) {
+        let alive: BTreeMap<u32, &ProcessInfo> = processes.iter()
+            .filter(|p| !p.zombie)
+            .map(|p| (p.pid, p))
+            .collect();
+        let mut members: BTreeSet<u32> = self.members.iter()
+            .copied()
+            .filter(|pid| alive.contains_key(pid))
+            .collect();
+        let mut found_new = true;
+        while found_new {
+            found_new = false;
+            for p in alive.values() {
+                let adopted = Some(p.ppid) == reaper && p.pid != self.root && !others.contains(&p.pid);
+                let joins = (members.contains(&p.ppid) || adopted)
+                    && (self.count_daemons || p.session == self.session);
+                if joins && members.insert(p.pid) {
+                    found_new = true;
+                }
+            }
+        }
+        self.members = members;
+    }
+
+    /// Adds a process to the tree, along with its descendants from the next
+    /// update on.
+    pub fn add(&mut self, pid: u32) {
+        self.members.insert(pid);
+    }
+
+    /// The process that the tree started from
+    pub fn root(&self) -> u32 {
+        self.root
+    }
+
+    /// Whether any process of the tree is still running
+    pub fn is_alive(&self) -> bool {
+        !self.members.is_empty()
+    }
+
+    /// Processes that belong to the tree and were alive at the last update
+    pub fn members(&self) -> &BTreeSet<u32> {
+        &self.members
+    }
+}
+
+#[allow(dead_code, unused_imports)]
+mod test {
+    use crate::process::scanner::{scan, ProcessInfo};
+    use crate::process::tree::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const REAPER: u32 = 1000;
+    const SESSION: u32 = 500;
+
+    fn p(pid: u32, ppid: u32) -> ProcessInfo {
+        ProcessInfo { pid, ppid, session: SESSION, ..ProcessInfo::default() }
+    }
+
+    #[test]
+    fn follows_descendants() {
+        let mut tree = ProcessTree::new(1, SESSION, false);
+        let none = BTreeSet::new();
+        tree.update(&[p(1, REAPER), p(2, 1), p(3, 2), p(4, 99)], Some(REAPER), &none);
+        assert_eq!(&BTreeSet::from([1, 2, 3]), tree.members());
+
+        // The launcher exits, and its children are re-parented to the reaper
+        tree.update(&[p(2, REAPER), p(3, 2), p(5, 3)], Some(REAPER), &none);
+        assert_eq!(&BTreeSet::from([2, 3, 5]), tree.members());
+
+        let mut zombie = p(2, REAPER);
+        zombie.zombie = true;
+        tree.update(&[zombie], Some(REAPER), &none);
+        assert!(!tree.is_alive());
+    }
+
+    #[test]
+    fn ignores_other_children_of_reaper() {
+        let mut tree = ProcessTree::new(1, SESSION, false);
+        let hooks = BTreeSet::from([7]);
+        tree.update(&[p(1, REAPER), p(7, REAPER), p(8, 7), p(9, REAPER)], Some(REAPER), &hooks);
+        // 9 is an orphan whose parent was never seen, so it's attributed to
+        // the app
+        assert_eq!(&BTreeSet::from([1, 9]), tree.members());
+    }
+
+    #[test]
+    fn daemons_only_count_if_enabled() {
+        let daemon = ProcessInfo { pid: 3, ppid: REAPER, session: 3, ..ProcessInfo::default() };
+        let processes = [p(2, REAPER), daemon.clone(), p(4, 3)];
+        let none = BTreeSet::new();
+
+        let mut tree = ProcessTree::new(1, SESSION, false);
+        tree.update(&processes, Some(REAPER), &none);
+        assert_eq!(&BTreeSet::from([2]), tree.members());
+
+        let mut tree = ProcessTree::new(1, SESSION, true);
+        tree.update(&processes, Some(REAPER), &none);
+        assert_eq!(&BTreeSet::from([2, 3, 4]), tree.members());
+    }
+
+    #[test]
+    fn added_processes_bring_their_descendants() {
+        let mut tree = ProcessTree::new(1, SESSION, false);
+        tree.add(5);
+        // Without a reaper, orphans don't join the tree
+        tree.update(&[p(1, 0), p(5, 4), p(6, 5), p(7, 1), p(8, 0)], None, &BTreeSet::new());
+        assert_eq!(&BTreeSet::from([1, 5, 6, 7]), tree.members());
+    }
+
+    #[test]
+    fn follows_processes_that_outlive_the_launcher() {
+        // Other tests start children of this process as well, which would be
+        // taken for orphans. So only look at the process of interest.
+        become_subreaper().unwrap();
+        let mut launcher = Command::new("sh")
+            .args(["-c", "sleep 1 & echo $!"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(launcher.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let sleep_pid: u32 = line.trim().parse().unwrap();
+        launcher.wait().unwrap();
+
+        let me = std::process::id();
+        let mut tree = ProcessTree::new(launcher.id(), session_of(me).unwrap(), false);
+        let scan_sleep = || -> Vec<ProcessInfo> {
+            scan().unwrap().into_iter().filter(|p| p.pid == sleep_pid).collect()
+        };
+        tree.update(&scan_sleep(), Some(me), &BTreeSet::new());
+        assert_eq!(&BTreeSet::from([sleep_pid]), tree.members());
+
+        let started = Instant::now();
+        while tree.is_alive() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(50));
+            let processes = scan_sleep();
+            reap_orphans(&processes, &BTreeSet::new());
+            tree.update(&processes, Some(me), &BTreeSet::new());
+        }
+        assert!(!tree.is_alive());
+        assert!(scan_sleep().is_empty(), "Orphan should have been reaped");
+    }
+}